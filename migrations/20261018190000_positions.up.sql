@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS positions (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    recorded_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    altitude DOUBLE PRECISION,
+    speed DOUBLE PRECISION,
+    course DOUBLE PRECISION,
+    satellites INTEGER,
+    ignition BOOLEAN,
+    io JSONB,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE INDEX IF NOT EXISTS positions_device_recorded_at_idx ON positions (device_id, recorded_at DESC);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS alerts (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    kind VARCHAR(50) NOT NULL,
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    triggered_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    resolved_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE INDEX IF NOT EXISTS alerts_device_triggered_at_idx ON alerts (device_id, triggered_at DESC);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS telemetry (
+    id BIGSERIAL PRIMARY KEY NOT NULL,
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    metric VARCHAR(100) NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    recorded_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE INDEX IF NOT EXISTS telemetry_device_metric_recorded_at_idx ON telemetry (device_id, metric, recorded_at DESC);
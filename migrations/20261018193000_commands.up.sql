@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS commands (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    command VARCHAR(50) NOT NULL,
+    payload JSONB,
+    status VARCHAR(20) NOT NULL DEFAULT 'queued'
+        CHECK (status IN ('queued', 'sent', 'acknowledged', 'failed', 'expired')),
+    channel VARCHAR(20),
+    response TEXT,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    sent_at TIMESTAMP WITH TIME ZONE,
+    completed_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE INDEX IF NOT EXISTS commands_device_status_idx ON commands (device_id, status, created_at);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS device_configs (
+    device_id UUID PRIMARY KEY NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL DEFAULT 1,
+    config JSONB NOT NULL,
+    reported_version INTEGER,
+    reported_at TIMESTAMP WITH TIME ZONE,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
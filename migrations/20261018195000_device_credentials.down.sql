@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS device_request_signatures;
+DROP TABLE IF EXISTS device_credentials;
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS device_credentials (
+    device_id UUID PRIMARY KEY NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    -- SHA-256 of the secret, also the HMAC key for signed requests
+    secret_hash VARCHAR(64) NOT NULL,
+    rotated_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+-- Signatures seen inside the timestamp window, a repeat is a replay
+CREATE TABLE IF NOT EXISTS device_request_signatures (
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    signature VARCHAR(64) NOT NULL,
+    received_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (device_id, signature)
+);
+
+CREATE INDEX IF NOT EXISTS device_request_signatures_received_idx ON device_request_signatures (received_at);
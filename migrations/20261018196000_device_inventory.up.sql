@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS device_inventory (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    imei VARCHAR(20) NOT NULL UNIQUE,
+    model VARCHAR(100) NOT NULL,
+    serial_number VARCHAR(100) NOT NULL,
+    claim_code VARCHAR(20) NOT NULL UNIQUE,
+    device_id UUID REFERENCES devices(id) ON DELETE SET NULL,
+    claimed_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
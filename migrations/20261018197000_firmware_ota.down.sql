@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS ota_updates;
+DROP TABLE IF EXISTS ota_campaigns;
+DROP TABLE IF EXISTS firmwares;
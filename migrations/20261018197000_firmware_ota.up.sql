@@ -0,0 +1,39 @@
+CREATE TABLE IF NOT EXISTS firmwares (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    version VARCHAR(50) NOT NULL UNIQUE,
+    -- Values of devices.model the image can be installed on
+    models TEXT[] NOT NULL,
+    checksum VARCHAR(64) NOT NULL,
+    size BIGINT NOT NULL,
+    notes TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE TABLE IF NOT EXISTS ota_campaigns (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    firmware_id UUID NOT NULL REFERENCES firmwares(id) ON DELETE RESTRICT,
+    client_id UUID NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    -- Cumulative percentages of the target devices, e.g. {5,25,100}
+    stages INTEGER[] NOT NULL,
+    current_stage INTEGER NOT NULL DEFAULT 0,
+    failure_threshold DOUBLE PRECISION NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'active'
+        CHECK (status IN ('active', 'paused', 'completed', 'cancelled')),
+    paused_reason TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now()),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE TABLE IF NOT EXISTS ota_updates (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    campaign_id UUID NOT NULL REFERENCES ota_campaigns(id) ON DELETE CASCADE,
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'downloading', 'installed', 'failed')),
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now()),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now()),
+    UNIQUE (campaign_id, device_id)
+);
+
+CREATE INDEX IF NOT EXISTS ota_updates_device_status_idx ON ota_updates (device_id, status);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE device_inventory DROP CONSTRAINT IF EXISTS device_inventory_model_fkey;
+ALTER TABLE devices DROP CONSTRAINT IF EXISTS devices_model_fkey;
+DROP TABLE IF EXISTS device_models;
@@ -0,0 +1,43 @@
+CREATE TABLE IF NOT EXISTS device_models (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    name VARCHAR(100) NOT NULL UNIQUE,
+    manufacturer VARCHAR(100) NOT NULL,
+    protocol VARCHAR(50) NOT NULL,
+    capabilities TEXT[] NOT NULL DEFAULT '{}',
+    gps_interval_seconds INTEGER,
+    data_interval_seconds INTEGER,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+-- "Model X", "model-x" and "MODELX" share a key and become one catalog entry,
+-- named after the spelling used the most
+INSERT INTO device_models (name, manufacturer, protocol)
+SELECT mode() WITHIN GROUP (ORDER BY model), 'unknown', 'unknown'
+FROM (
+    SELECT model FROM devices
+    UNION ALL SELECT model FROM device_inventory
+    UNION ALL SELECT unnest(models) FROM firmwares
+) existing
+GROUP BY lower(regexp_replace(model, '[^a-zA-Z0-9]', '', 'g'));
+
+UPDATE devices d SET model = m.name
+FROM device_models m
+WHERE lower(regexp_replace(d.model, '[^a-zA-Z0-9]', '', 'g')) = lower(regexp_replace(m.name, '[^a-zA-Z0-9]', '', 'g'))
+    AND d.model <> m.name;
+
+UPDATE device_inventory i SET model = m.name
+FROM device_models m
+WHERE lower(regexp_replace(i.model, '[^a-zA-Z0-9]', '', 'g')) = lower(regexp_replace(m.name, '[^a-zA-Z0-9]', '', 'g'))
+    AND i.model <> m.name;
+
+UPDATE firmwares f SET models = ARRAY(
+    SELECT DISTINCT m.name
+    FROM unnest(f.models) AS model
+    JOIN device_models m
+        ON lower(regexp_replace(model, '[^a-zA-Z0-9]', '', 'g')) = lower(regexp_replace(m.name, '[^a-zA-Z0-9]', '', 'g'))
+);
+
+ALTER TABLE devices
+    ADD CONSTRAINT devices_model_fkey FOREIGN KEY (model) REFERENCES device_models(name) ON UPDATE CASCADE;
+ALTER TABLE device_inventory
+    ADD CONSTRAINT device_inventory_model_fkey FOREIGN KEY (model) REFERENCES device_models(name) ON UPDATE CASCADE;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS sim_assignments;
+DROP TABLE IF EXISTS sim_cards;
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS sim_cards (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    iccid VARCHAR(22) NOT NULL UNIQUE,
+    msisdn VARCHAR(20),
+    carrier VARCHAR(100) NOT NULL,
+    data_plan VARCHAR(100),
+    -- Device the SIM is currently installed in
+    device_id UUID UNIQUE REFERENCES devices(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE TABLE IF NOT EXISTS sim_assignments (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    sim_id UUID NOT NULL REFERENCES sim_cards(id) ON DELETE CASCADE,
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    assigned_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    unassigned_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS sim_assignments_sim_idx ON sim_assignments (sim_id, assigned_at DESC);
+CREATE UNIQUE INDEX IF NOT EXISTS sim_assignments_open_idx ON sim_assignments (sim_id) WHERE unassigned_at IS NULL;
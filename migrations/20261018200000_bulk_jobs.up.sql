@@ -0,0 +1,14 @@
+-- Bulk device operations too large to answer in the request
+CREATE TABLE IF NOT EXISTS bulk_jobs (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    -- Client of the token that started the job, NULL for operators
+    client_id UUID REFERENCES clients(id) ON DELETE CASCADE,
+    operation JSONB NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'running'
+        CHECK (status IN ('running', 'completed', 'failed')),
+    total INTEGER NOT NULL,
+    results JSONB,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now()),
+    finished_at TIMESTAMP WITH TIME ZONE
+);
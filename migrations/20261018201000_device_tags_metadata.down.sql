@@ -0,0 +1,7 @@
+-- Add down migration script here
+ALTER TABLE ota_campaigns DROP COLUMN IF EXISTS tag;
+DROP TABLE IF EXISTS device_tags;
+DROP TABLE IF EXISTS tags;
+ALTER TABLE clients DROP COLUMN IF EXISTS metadata_schema;
+DROP INDEX IF EXISTS devices_metadata_idx;
+ALTER TABLE devices DROP COLUMN IF EXISTS metadata;
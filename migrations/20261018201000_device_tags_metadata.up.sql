@@ -0,0 +1,24 @@
+ALTER TABLE devices ADD COLUMN IF NOT EXISTS metadata JSONB NOT NULL DEFAULT '{}'::JSONB;
+-- Serves the containment (@>) filter of the device list
+CREATE INDEX IF NOT EXISTS devices_metadata_idx ON devices USING GIN (metadata jsonb_path_ops);
+
+-- JSON Schema the metadata of the client's devices must follow
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS metadata_schema JSONB;
+
+CREATE TABLE IF NOT EXISTS tags (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    name VARCHAR(50) NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE TABLE IF NOT EXISTS device_tags (
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    tag_id UUID NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now()),
+    PRIMARY KEY (device_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS device_tags_tag_idx ON device_tags (tag_id, device_id);
+
+-- Limits a campaign to the devices carrying the tag
+ALTER TABLE ota_campaigns ADD COLUMN IF NOT EXISTS tag VARCHAR(50);
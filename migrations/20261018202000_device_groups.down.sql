@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS device_group_members;
+DROP TABLE IF EXISTS device_groups;
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS device_groups (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    client_id UUID NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    -- Groups with subgroups cannot be deleted
+    parent_id UUID REFERENCES device_groups(id),
+    name VARCHAR(100) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now()),
+    UNIQUE (client_id, name)
+);
+
+CREATE INDEX IF NOT EXISTS device_groups_parent_idx ON device_groups (parent_id);
+
+CREATE TABLE IF NOT EXISTS device_group_members (
+    group_id UUID NOT NULL REFERENCES device_groups(id) ON DELETE CASCADE,
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now()),
+    PRIMARY KEY (group_id, device_id)
+);
+
+CREATE INDEX IF NOT EXISTS device_group_members_device_idx ON device_group_members (device_id);
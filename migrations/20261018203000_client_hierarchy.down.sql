@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS clients_parent_idx;
+
+ALTER TABLE clients DROP COLUMN IF EXISTS parent_id;
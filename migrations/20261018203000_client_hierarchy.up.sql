@@ -0,0 +1,4 @@
+-- Resellers own their sub-clients, clients with sub-clients cannot be deleted
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS parent_id UUID REFERENCES clients(id);
+
+CREATE INDEX IF NOT EXISTS clients_parent_idx ON clients (parent_id);
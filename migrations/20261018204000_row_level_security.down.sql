@@ -0,0 +1,28 @@
+-- Add down migration script here
+DO $$
+DECLARE
+    name TEXT;
+BEGIN
+    FOREACH name IN ARRAY ARRAY[
+        'clients', 'devices', 'device_groups', 'ota_campaigns', 'bulk_jobs',
+        'alerts', 'commands', 'device_configs', 'device_credentials',
+        'device_group_members', 'device_request_signatures', 'device_tags',
+        'ota_updates', 'positions', 'sim_assignments', 'telemetry',
+        'sim_cards', 'device_inventory'
+    ]
+    LOOP
+        EXECUTE format('DROP POLICY IF EXISTS tenant_isolation ON %I', name);
+        EXECUTE format('ALTER TABLE %I NO FORCE ROW LEVEL SECURITY', name);
+        EXECUTE format('ALTER TABLE %I DISABLE ROW LEVEL SECURITY', name);
+    END LOOP;
+END
+$$;
+
+DROP FUNCTION IF EXISTS app_device_visible(UUID);
+DROP FUNCTION IF EXISTS app_client_visible(UUID);
+
+ALTER DEFAULT PRIVILEGES IN SCHEMA public REVOKE USAGE, SELECT ON SEQUENCES FROM app_tenant;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public REVOKE SELECT, INSERT, UPDATE, DELETE ON TABLES FROM app_tenant;
+REVOKE ALL ON ALL SEQUENCES IN SCHEMA public FROM app_tenant;
+REVOKE ALL ON ALL TABLES IN SCHEMA public FROM app_tenant;
+REVOKE USAGE ON SCHEMA public FROM app_tenant;
@@ -0,0 +1,71 @@
+-- Tenant isolation below the handlers. Requests made with a client token set
+-- app.client_ids to the array of clients they may reach, every other
+-- connection leaves it empty and sees all rows.
+-- Superusers and BYPASSRLS roles skip the policies, the API switches its
+-- connections to app_tenant so they hold whatever user it logs in as.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'app_tenant') THEN
+        CREATE ROLE app_tenant NOLOGIN NOBYPASSRLS;
+    END IF;
+END
+$$;
+
+GRANT app_tenant TO CURRENT_USER;
+GRANT USAGE ON SCHEMA public TO app_tenant;
+GRANT SELECT, INSERT, UPDATE, DELETE ON ALL TABLES IN SCHEMA public TO app_tenant;
+GRANT USAGE, SELECT ON ALL SEQUENCES IN SCHEMA public TO app_tenant;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO app_tenant;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public GRANT USAGE, SELECT ON SEQUENCES TO app_tenant;
+
+CREATE OR REPLACE FUNCTION app_client_visible(client UUID) RETURNS BOOLEAN
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(current_setting('app.client_ids', true), '') = ''
+        OR client = ANY(NULLIF(current_setting('app.client_ids', true), '')::UUID[])
+$$;
+
+CREATE OR REPLACE FUNCTION app_device_visible(device UUID) RETURNS BOOLEAN
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(current_setting('app.client_ids', true), '') = ''
+        OR EXISTS (
+            SELECT 1 FROM devices
+            WHERE id = device AND client_id = ANY(NULLIF(current_setting('app.client_ids', true), '')::UUID[])
+        )
+$$;
+
+DO $$
+DECLARE
+    target RECORD;
+BEGIN
+    FOR target IN
+        SELECT * FROM (VALUES
+            ('clients', 'app_client_visible(id)'),
+            ('devices', 'app_client_visible(client_id)'),
+            ('device_groups', 'app_client_visible(client_id)'),
+            ('ota_campaigns', 'app_client_visible(client_id)'),
+            ('bulk_jobs', 'app_client_visible(client_id)'),
+            ('alerts', 'app_device_visible(device_id)'),
+            ('commands', 'app_device_visible(device_id)'),
+            ('device_configs', 'app_device_visible(device_id)'),
+            ('device_credentials', 'app_device_visible(device_id)'),
+            ('device_group_members', 'app_device_visible(device_id)'),
+            ('device_request_signatures', 'app_device_visible(device_id)'),
+            ('device_tags', 'app_device_visible(device_id)'),
+            ('ota_updates', 'app_device_visible(device_id)'),
+            ('positions', 'app_device_visible(device_id)'),
+            ('sim_assignments', 'app_device_visible(device_id)'),
+            ('telemetry', 'app_device_visible(device_id)'),
+            -- Unassigned SIM cards and unclaimed stock stay reachable, claiming needs them
+            ('sim_cards', 'device_id IS NULL OR app_device_visible(device_id)'),
+            ('device_inventory', 'device_id IS NULL OR app_device_visible(device_id)')
+        ) AS tables (name, visible)
+    LOOP
+        EXECUTE format('ALTER TABLE %I ENABLE ROW LEVEL SECURITY', target.name);
+        EXECUTE format('ALTER TABLE %I FORCE ROW LEVEL SECURITY', target.name);
+        EXECUTE format(
+            'CREATE POLICY tenant_isolation ON %I USING (%s) WITH CHECK (%s)',
+            target.name, target.visible, target.visible
+        );
+    END LOOP;
+END
+$$;
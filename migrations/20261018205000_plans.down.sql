@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS clients_plan_idx;
+
+ALTER TABLE clients DROP COLUMN IF EXISTS plan_id;
+
+DROP TABLE IF EXISTS plans;
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS plans (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    name VARCHAR(100) NOT NULL UNIQUE,
+    -- No device limit when NULL
+    max_devices INTEGER CHECK (max_devices >= 0),
+    retention_days INTEGER NOT NULL CHECK (retention_days > 0),
+    features TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+-- Clients without a plan have no limits, plans in use cannot be deleted
+ALTER TABLE clients ADD COLUMN IF NOT EXISTS plan_id UUID REFERENCES plans(id);
+
+CREATE INDEX IF NOT EXISTS clients_plan_idx ON clients (plan_id);
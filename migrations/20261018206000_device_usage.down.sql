@@ -0,0 +1,14 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS devices_usage ON devices;
+
+DROP FUNCTION IF EXISTS record_device_usage();
+
+DROP FUNCTION IF EXISTS device_usage(DATE);
+
+DROP TABLE IF EXISTS usage_months;
+
+DROP TABLE IF EXISTS usage_snapshots;
+
+DROP TABLE IF EXISTS device_activity_days;
+
+DROP TABLE IF EXISTS device_status_history;
@@ -0,0 +1,109 @@
+-- Billing inputs. Rows keep the device id without a foreign key so months
+-- stay billable after the device is deleted or moved to another client.
+CREATE TABLE IF NOT EXISTS device_status_history (
+    id BIGSERIAL PRIMARY KEY,
+    device_id UUID NOT NULL,
+    client_id UUID NOT NULL,
+    status VARCHAR(50) NOT NULL,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS device_status_history_device_idx ON device_status_history (device_id, changed_at);
+
+-- Days, in BRT, on which upload_data moved forward for the device under the client
+CREATE TABLE IF NOT EXISTS device_activity_days (
+    device_id UUID NOT NULL,
+    client_id UUID NOT NULL,
+    day DATE NOT NULL,
+    PRIMARY KEY (device_id, client_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS device_activity_days_client_idx ON device_activity_days (client_id, day);
+
+-- Active device-days per device of the closed months
+CREATE TABLE IF NOT EXISTS usage_snapshots (
+    client_id UUID NOT NULL,
+    month DATE NOT NULL,
+    device_id UUID NOT NULL,
+    imei VARCHAR(20),
+    active_days INTEGER NOT NULL,
+    PRIMARY KEY (client_id, month, device_id)
+);
+
+-- Months whose snapshots were computed, also the ones without any usage
+CREATE TABLE IF NOT EXISTS usage_months (
+    month DATE PRIMARY KEY NOT NULL,
+    computed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE OR REPLACE FUNCTION record_device_usage() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        INSERT INTO device_status_history (device_id, client_id, status)
+        VALUES (OLD.id, OLD.client_id, 'deleted');
+        RETURN OLD;
+    END IF;
+
+    IF TG_OP = 'INSERT' OR NEW.status IS DISTINCT FROM OLD.status OR NEW.client_id IS DISTINCT FROM OLD.client_id THEN
+        INSERT INTO device_status_history (device_id, client_id, status)
+        VALUES (NEW.id, NEW.client_id, NEW.status);
+    END IF;
+
+    IF TG_OP = 'UPDATE' AND NEW.upload_data > OLD.upload_data THEN
+        INSERT INTO device_activity_days (device_id, client_id, day)
+        VALUES (NEW.id, NEW.client_id, (NEW.upload_data AT TIME ZONE 'brt')::DATE)
+        ON CONFLICT DO NOTHING;
+    END IF;
+
+    RETURN NEW;
+END
+$$;
+
+CREATE TRIGGER devices_usage
+AFTER INSERT OR DELETE OR UPDATE OF status, client_id, upload_data ON devices
+FOR EACH ROW EXECUTE FUNCTION record_device_usage();
+
+-- Devices that exist already start their history now, with the day of their last upload
+INSERT INTO device_status_history (device_id, client_id, status, changed_at)
+SELECT id, client_id, status, COALESCE(created_at, now()) FROM devices;
+
+INSERT INTO device_activity_days (device_id, client_id, day)
+SELECT id, client_id, (upload_data AT TIME ZONE 'brt')::DATE FROM devices
+ON CONFLICT DO NOTHING;
+
+-- Days of the month starting at $1 on which each device was active and sent
+-- data. A day counts for the client the device belonged to while it did both.
+CREATE OR REPLACE FUNCTION device_usage(month DATE)
+RETURNS TABLE (client_id UUID, device_id UUID, active_days INTEGER)
+LANGUAGE sql STABLE AS $$
+    WITH periods AS (
+        SELECT h.device_id, h.client_id, h.status, h.changed_at AS starts_at,
+            LEAD(h.changed_at) OVER (PARTITION BY h.device_id ORDER BY h.changed_at, h.id) AS ends_at
+        FROM device_status_history h
+        WHERE h.changed_at < (($1 + INTERVAL '1 month')::TIMESTAMP AT TIME ZONE 'brt')
+    )
+    SELECT a.client_id, a.device_id, COUNT(DISTINCT a.day)::INTEGER
+    FROM device_activity_days a
+    JOIN periods p ON p.device_id = a.device_id AND p.client_id = a.client_id AND p.status = 'active'
+        AND p.starts_at < ((a.day + 1)::TIMESTAMP AT TIME ZONE 'brt')
+        AND (p.ends_at IS NULL OR p.ends_at > (a.day::TIMESTAMP AT TIME ZONE 'brt'))
+    WHERE a.day >= $1 AND a.day < ($1 + INTERVAL '1 month')::DATE
+    GROUP BY a.client_id, a.device_id
+$$;
+
+DO $$
+DECLARE
+    target TEXT;
+BEGIN
+    FOREACH target IN ARRAY ARRAY['device_status_history', 'device_activity_days', 'usage_snapshots']
+    LOOP
+        EXECUTE format('ALTER TABLE %I ENABLE ROW LEVEL SECURITY', target);
+        EXECUTE format('ALTER TABLE %I FORCE ROW LEVEL SECURITY', target);
+        EXECUTE format(
+            'CREATE POLICY tenant_isolation ON %I USING (app_client_visible(client_id)) WITH CHECK (app_client_visible(client_id))',
+            target
+        );
+    END LOOP;
+END
+$$;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS alerts_open_idx;
+
+DROP INDEX IF EXISTS devices_client_idx;
@@ -0,0 +1,4 @@
+-- Aggregates per client read the devices of one client and the alerts still open
+CREATE INDEX IF NOT EXISTS devices_client_idx ON devices (client_id);
+
+CREATE INDEX IF NOT EXISTS alerts_open_idx ON alerts (device_id) WHERE resolved_at IS NULL;
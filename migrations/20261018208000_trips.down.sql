@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS trip_states;
+
+DROP INDEX IF EXISTS trips_device_started_at_idx;
+
+DROP TABLE IF EXISTS trips;
@@ -0,0 +1,45 @@
+CREATE TABLE IF NOT EXISTS trips (
+    id UUID PRIMARY KEY NOT NULL DEFAULT (uuid_generate_v4()),
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    start_latitude DOUBLE PRECISION NOT NULL,
+    start_longitude DOUBLE PRECISION NOT NULL,
+    -- NULL while the trip is in progress
+    ended_at TIMESTAMP WITH TIME ZONE,
+    end_latitude DOUBLE PRECISION,
+    end_longitude DOUBLE PRECISION,
+    distance_m DOUBLE PRECISION NOT NULL DEFAULT 0,
+    max_speed DOUBLE PRECISION NOT NULL DEFAULT 0,
+    avg_speed DOUBLE PRECISION NOT NULL DEFAULT 0,
+    moving_seconds INTEGER NOT NULL DEFAULT 0,
+    idle_seconds INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT timezone('brt'::text, now())
+);
+
+CREATE INDEX IF NOT EXISTS trips_device_started_at_idx ON trips (device_id, started_at DESC);
+
+-- Where the segmentation of each device stands, advanced with every stored batch
+CREATE TABLE IF NOT EXISTS trip_states (
+    device_id UUID PRIMARY KEY NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    last_recorded_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_latitude DOUBLE PRECISION NOT NULL,
+    last_longitude DOUBLE PRECISION NOT NULL,
+    last_moving BOOLEAN NOT NULL,
+    trip_id UUID REFERENCES trips(id) ON DELETE SET NULL,
+    -- First stationary fix of the stop the open trip may be ending in
+    stopped_since TIMESTAMP WITH TIME ZONE,
+    stop_latitude DOUBLE PRECISION,
+    stop_longitude DOUBLE PRECISION,
+    -- Idle time of that stop, added to the trip only if it moves on
+    pending_idle_seconds INTEGER NOT NULL DEFAULT 0
+);
+
+ALTER TABLE trips ENABLE ROW LEVEL SECURITY;
+ALTER TABLE trips FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON trips
+    USING (app_device_visible(device_id)) WITH CHECK (app_device_visible(device_id));
+
+ALTER TABLE trip_states ENABLE ROW LEVEL SECURITY;
+ALTER TABLE trip_states FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON trip_states
+    USING (app_device_visible(device_id)) WITH CHECK (app_device_visible(device_id));
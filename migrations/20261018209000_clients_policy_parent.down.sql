@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP POLICY IF EXISTS tenant_isolation ON clients;
+
+CREATE POLICY tenant_isolation ON clients
+    USING (app_client_visible(id)) WITH CHECK (app_client_visible(id));
@@ -0,0 +1,7 @@
+-- Resellers create sub-clients whose ids are not in their scope yet, a
+-- client is reachable through its parent as well
+DROP POLICY IF EXISTS tenant_isolation ON clients;
+
+CREATE POLICY tenant_isolation ON clients
+    USING (app_client_visible(id) OR app_client_visible(parent_id))
+    WITH CHECK (app_client_visible(id) OR app_client_visible(parent_id));
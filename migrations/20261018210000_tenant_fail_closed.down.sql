@@ -0,0 +1,14 @@
+CREATE OR REPLACE FUNCTION app_client_visible(client UUID) RETURNS BOOLEAN
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(current_setting('app.client_ids', true), '') = ''
+        OR client = ANY(NULLIF(current_setting('app.client_ids', true), '')::UUID[])
+$$;
+
+CREATE OR REPLACE FUNCTION app_device_visible(device UUID) RETURNS BOOLEAN
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(current_setting('app.client_ids', true), '') = ''
+        OR EXISTS (
+            SELECT 1 FROM devices
+            WHERE id = device AND client_id = ANY(NULLIF(current_setting('app.client_ids', true), '')::UUID[])
+        )
+$$;
@@ -0,0 +1,17 @@
+-- Fail closed: a connection without a client scope sees no tenant rows.
+-- Operators and the server's own jobs set app.bypass = 'on' instead of
+-- leaving the scope empty.
+CREATE OR REPLACE FUNCTION app_client_visible(client UUID) RETURNS BOOLEAN
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(current_setting('app.bypass', true), '') = 'on'
+        OR COALESCE(client = ANY(NULLIF(current_setting('app.client_ids', true), '')::UUID[]), false)
+$$;
+
+CREATE OR REPLACE FUNCTION app_device_visible(device UUID) RETURNS BOOLEAN
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(current_setting('app.bypass', true), '') = 'on'
+        OR EXISTS (
+            SELECT 1 FROM devices
+            WHERE id = device AND client_id = ANY(NULLIF(current_setting('app.client_ids', true), '')::UUID[])
+        )
+$$;
@@ -0,0 +1,4 @@
+DELETE FROM device_credentials;
+
+ALTER TABLE device_credentials DROP COLUMN secret_salt;
+ALTER TABLE device_credentials ADD COLUMN secret_hash VARCHAR(64) NOT NULL;
@@ -0,0 +1,8 @@
+-- Device secrets are derived as HMAC(DEVICE_SECRET_KEY, device id || salt),
+-- only the salt is stored so a copy of the table cannot sign requests.
+-- The stored hashes were enough to sign, those secrets are revoked and the
+-- devices need a new one from POST /devices/{id}/secret.
+DELETE FROM device_credentials;
+
+ALTER TABLE device_credentials DROP COLUMN secret_hash;
+ALTER TABLE device_credentials ADD COLUMN secret_salt VARCHAR(64) NOT NULL;
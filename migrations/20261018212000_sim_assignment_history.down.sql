@@ -0,0 +1,11 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS devices_close_sim_assignments ON devices;
+DROP FUNCTION IF EXISTS close_sim_assignments();
+
+DELETE FROM sim_assignments WHERE device_id IS NULL;
+ALTER TABLE sim_assignments DROP CONSTRAINT sim_assignments_device_id_fkey;
+ALTER TABLE sim_assignments ADD CONSTRAINT sim_assignments_device_id_fkey
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE CASCADE;
+ALTER TABLE sim_assignments ALTER COLUMN device_id SET NOT NULL;
+
+ALTER TABLE sim_assignments DROP COLUMN imei;
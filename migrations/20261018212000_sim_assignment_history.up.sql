@@ -0,0 +1,24 @@
+-- Assignments outlive the devices they were made to, the IMEI identifies the
+-- device once it is deleted.
+ALTER TABLE sim_assignments ADD COLUMN imei VARCHAR(20);
+UPDATE sim_assignments a SET imei = d.imei FROM devices d WHERE d.id = a.device_id;
+ALTER TABLE sim_assignments ALTER COLUMN imei SET NOT NULL;
+
+ALTER TABLE sim_assignments ALTER COLUMN device_id DROP NOT NULL;
+ALTER TABLE sim_assignments DROP CONSTRAINT sim_assignments_device_id_fkey;
+ALTER TABLE sim_assignments ADD CONSTRAINT sim_assignments_device_id_fkey
+    FOREIGN KEY (device_id) REFERENCES devices(id) ON DELETE SET NULL;
+
+-- Deleting a device takes its SIM out, the open assignment ends with it
+CREATE OR REPLACE FUNCTION close_sim_assignments() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+    UPDATE sim_assignments SET unassigned_at = now()
+    WHERE device_id = OLD.id AND unassigned_at IS NULL;
+    RETURN OLD;
+END;
+$$;
+
+CREATE TRIGGER devices_close_sim_assignments
+    BEFORE DELETE ON devices
+    FOR EACH ROW EXECUTE FUNCTION close_sim_assignments();
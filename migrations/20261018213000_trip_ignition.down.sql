@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE trip_states DROP COLUMN last_ignition;
+ALTER TABLE trip_states DROP COLUMN ignition_off_since;
@@ -0,0 +1,4 @@
+-- When the ignition went off in the stop the open trip may be ending in, and
+-- the ignition of the last fix, so idling with the engine on keeps the trip
+ALTER TABLE trip_states ADD COLUMN ignition_off_since TIMESTAMP WITH TIME ZONE;
+ALTER TABLE trip_states ADD COLUMN last_ignition BOOLEAN;
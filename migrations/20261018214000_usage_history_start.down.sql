@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP POLICY IF EXISTS tenant_read ON usage_months;
+DROP POLICY IF EXISTS system_write ON usage_months;
+ALTER TABLE usage_months NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE usage_months DISABLE ROW LEVEL SECURITY;
+
+DROP TABLE IF EXISTS usage_history;
@@ -0,0 +1,27 @@
+-- First month with device usage recorded, the ones before it cannot be billed
+CREATE TABLE IF NOT EXISTS usage_history (
+    first_month DATE PRIMARY KEY NOT NULL
+);
+
+-- Activity days are recorded from now on, the history backfilled for existing
+-- devices only holds their last upload day
+INSERT INTO usage_history (first_month) VALUES (date_trunc('month', now() AT TIME ZONE 'brt')::DATE);
+
+-- Everyone reads them, only the server's own jobs write: a snapshot taken on
+-- a connection scoped to some clients would close the month for all others
+DO $$
+DECLARE
+    target TEXT;
+BEGIN
+    FOREACH target IN ARRAY ARRAY['usage_months', 'usage_history']
+    LOOP
+        EXECUTE format('ALTER TABLE %I ENABLE ROW LEVEL SECURITY', target);
+        EXECUTE format('ALTER TABLE %I FORCE ROW LEVEL SECURITY', target);
+        EXECUTE format('CREATE POLICY tenant_read ON %I FOR SELECT USING (true)', target);
+        EXECUTE format(
+            'CREATE POLICY system_write ON %I USING (COALESCE(current_setting(''app.bypass'', true), '''') = ''on'') WITH CHECK (COALESCE(current_setting(''app.bypass'', true), '''') = ''on'')',
+            target
+        );
+    END LOOP;
+END
+$$;
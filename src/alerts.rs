@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    events::{EventHub, FleetEventKind},
+    model::{AlertModel, DeviceModel},
+};
+
+/// Stores an alert raised by a device and publishes it to the live feeds.
+pub async fn store_alert(
+    db: &Pool<Postgres>,
+    events: &EventHub,
+    device: &DeviceModel,
+    kind: &str,
+    location: Option<(f64, f64)>,
+    triggered_at: DateTime<Utc>,
+) -> Result<AlertModel, sqlx::Error> {
+    let alert = sqlx::query_as!(
+        AlertModel,
+        r#"
+        INSERT INTO alerts (device_id, kind, latitude, longitude, triggered_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#,
+        device.id,
+        kind,
+        location.map(|(latitude, _)| latitude),
+        location.map(|(_, longitude)| longitude),
+        triggered_at
+    )
+    .fetch_one(db)
+    .await?;
+
+    events.publish(FleetEventKind::Alert, device.client_id, device.id, &alert);
+
+    Ok(alert)
+}
@@ -0,0 +1,261 @@
+use actix_web::{
+    dev::ServiceRequest,
+    error::{ErrorInternalServerError, InternalError},
+    web::Data,
+    Error, HttpMessage, HttpResponse,
+};
+use actix_web_httpauth::extractors::{
+    bearer::{self, BearerAuth},
+    AuthenticationError,
+};
+use hmac::{Hmac, Mac};
+use jwt::VerifyWithKey;
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::{tenant, AppState, TokenClaims};
+
+/// Decodes and verifies a JWT signed with `JWT_SECRET`, read once at startup.
+pub fn decode_token(token: &str, jwt_secret: &str) -> Result<TokenClaims, &'static str> {
+    let key: Hmac<Sha256> = Hmac::new_from_slice(jwt_secret.as_bytes())
+        .map_err(|_| "Invalid JWT secret")?;
+
+    let claims: TokenClaims = token.verify_with_key(&key).map_err(|_| "Invalid token")?;
+
+    // Without its client a group token would pass for an operator
+    if claims.group_id.is_some() && claims.client_id.is_none() {
+        return Err("Group tokens must carry a client_id");
+    }
+
+    Ok(claims)
+}
+
+/// The client and its sub-clients at any depth.
+pub async fn client_subtree(db: &Pool<Postgres>, client_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM clients WHERE id = $1
+            UNION
+            SELECT c.id FROM clients c JOIN subtree s ON c.parent_id = s.id
+        )
+        SELECT id AS "id!" FROM subtree
+        "#,
+        client_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Resellers reach their sub-clients, group tokens stay on their own client.
+pub async fn load_client_scope(db: &Pool<Postgres>, claims: &mut TokenClaims) -> Result<(), sqlx::Error> {
+    if let Some(client_id) = claims.client_id {
+        claims.clients = match claims.group_id {
+            Some(_) => vec![client_id],
+            // Looked up before the request has a scope to see the clients through
+            None => tenant::system(client_subtree(db, client_id)).await?,
+        };
+    }
+    Ok(())
+}
+
+/// Bearer validator used by `HttpAuthentication`, stores the claims in the request extensions.
+/// Group tokens are refused, row-level security would open their whole client to them.
+pub async fn validator(
+    req: ServiceRequest,
+    credentials: BearerAuth,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    authorize(req, credentials, false).await
+}
+
+/// Validator of the device group routes, the only ones group tokens reach.
+/// Those routes hold a group token to its group themselves.
+pub async fn group_validator(
+    req: ServiceRequest,
+    credentials: BearerAuth,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    authorize(req, credentials, true).await
+}
+
+async fn authorize(
+    req: ServiceRequest,
+    credentials: BearerAuth,
+    group_route: bool,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let data = match req.app_data::<Data<AppState>>() {
+        Some(data) => data.clone(),
+        None => return Err((ErrorInternalServerError("Application state missing"), req)),
+    };
+
+    match decode_token(credentials.token(), &data.jwt_secret) {
+        Ok(claims) if claims.group_id.is_some() && !group_route => {
+            let response = HttpResponse::Forbidden().json(json!({
+                "status": "error",
+                "message": "Group tokens only reach the routes of their device group"
+            }));
+            Err((InternalError::from_response("group token", response).into(), req))
+        }
+        Ok(mut claims) => {
+            if let Err(error) = load_client_scope(&data.db, &mut claims).await {
+                return Err((ErrorInternalServerError(format!("{:?}", error)), req));
+            }
+            // Row-level security holds the rest of the request to the token's clients
+            tenant::restrict(claims.client_scope());
+            req.extensions_mut().insert(claims);
+            Ok(req)
+        }
+        Err(_) => {
+            let config = req
+                .app_data::<bearer::Config>()
+                .cloned()
+                .unwrap_or_default()
+                .scope("");
+
+            Err((AuthenticationError::from(config).into(), req))
+        }
+    }
+}
+
+impl TokenClaims {
+    /// Operators without a `client_id` claim can see every client, resellers
+    /// their own and its sub-clients.
+    /// Group tokens only reach their group, never the whole client.
+    pub fn can_access_client(&self, client_id: Uuid) -> bool {
+        if self.group_id.is_some() {
+            return false;
+        }
+
+        match self.client_id {
+            Some(own) => own == client_id || self.clients.contains(&client_id),
+            None => true,
+        }
+    }
+
+    /// Operator tokens carry no `client_id` and manage every client.
+    pub fn is_operator(&self) -> bool {
+        self.client_id.is_none()
+    }
+
+    /// Clients a query must be restricted to, none for operators.
+    pub fn client_scope(&self) -> Option<&[Uuid]> {
+        self.client_id.map(|_| self.clients.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{dev::Service, http::StatusCode, test, App};
+    use jwt::SignWithKey;
+
+    use super::*;
+    use crate::{
+        events::EventHub,
+        services::{get_device_by_id, get_group_by_id},
+    };
+
+    const SECRET: &str = "group-token-test";
+
+    fn sign(claims: &TokenClaims) -> String {
+        let key: Hmac<Sha256> = Hmac::new_from_slice(SECRET.as_bytes()).unwrap();
+        claims.clone().sign_with_key(&key).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn group_token_is_refused_outside_group_routes() {
+        dotenv::dotenv().ok();
+        let Ok(url) = std::env::var("DATABASE_URL") else { return };
+        let db = tenant::pool_options()
+            .max_connections(2)
+            .connect(&url)
+            .await
+            .expect("Failed to connect to the database");
+
+        // A client with a group and a device outside of it
+        let name = Uuid::new_v4().simple().to_string();
+        let (client_id, group_id, device_id) = tenant::system(async {
+            sqlx::query!(
+                "INSERT INTO device_models (name, manufacturer, protocol) VALUES ('RLS Test', 'Test', 'http') ON CONFLICT DO NOTHING"
+            )
+            .execute(&db)
+            .await
+            .unwrap();
+            let client_id = sqlx::query_scalar!(
+                "INSERT INTO clients (name, status) VALUES ($1, 'active') RETURNING id",
+                name
+            )
+            .fetch_one(&db)
+            .await
+            .unwrap();
+            let group_id = sqlx::query_scalar!(
+                "INSERT INTO device_groups (client_id, name) VALUES ($1, 'viewers') RETURNING id",
+                client_id
+            )
+            .fetch_one(&db)
+            .await
+            .unwrap();
+            let device_id = sqlx::query_scalar!(
+                r#"
+                INSERT INTO devices (client_id, nickname, imei, model, serial_number, upload_data, upload_gps, status)
+                VALUES ($1, $2, $3, 'RLS Test', $2, now(), now(), 'active')
+                RETURNING id
+                "#,
+                client_id,
+                name,
+                &name[..15]
+            )
+            .fetch_one(&db)
+            .await
+            .unwrap();
+            (client_id, group_id, device_id)
+        })
+        .await;
+
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|req, srv| tenant::scope_request(srv.call(req)))
+                .app_data(Data::new(AppState {
+                    db: db.clone(),
+                    events: EventHub::new(),
+                    jwt_secret: SECRET.to_string(),
+                }))
+                .service(get_device_by_id)
+                .service(get_group_by_id),
+        )
+        .await;
+        let token = sign(&TokenClaims {
+            id: 1,
+            client_id: Some(client_id),
+            group_id: Some(group_id),
+            clients: Vec::new(),
+        });
+
+        let device = test::TestRequest::get()
+            .uri(&format!("/devices/{}", device_id))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        let device_status = test::call_service(&app, device).await.status();
+
+        let group = test::TestRequest::get()
+            .uri(&format!("/device-groups/{}", group_id))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        let group_status = test::call_service(&app, group).await.status();
+
+        tenant::system(async {
+            sqlx::query!("DELETE FROM clients WHERE id = $1", client_id)
+                .execute(&db)
+                .await
+                .unwrap();
+            sqlx::query!("DELETE FROM device_status_history WHERE client_id = $1", client_id)
+                .execute(&db)
+                .await
+                .unwrap();
+        })
+        .await;
+
+        assert_eq!(device_status, StatusCode::FORBIDDEN, "group token read a device of its client");
+        assert_eq!(group_status, StatusCode::OK, "group token refused on its own group");
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashSet;
+
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::{
+    events::{EventHub, FleetEventKind},
+    model::DeviceModel,
+    plans::{lock_device_quota, DeviceQuota},
+    schema::BulkOperationSchema,
+};
+
+/// Devices changed while the request waits, larger operations run as a job.
+pub const MAX_SYNC_DEVICES: usize = 500;
+
+/// Why a bulk operation was not applied.
+pub enum BulkError {
+    Database(sqlx::Error),
+    /// The transfer would take the target client past the limit of its plan
+    QuotaExceeded { quota: DeviceQuota, adding: usize },
+}
+
+impl From<sqlx::Error> for BulkError {
+    fn from(error: sqlx::Error) -> Self {
+        BulkError::Database(error)
+    }
+}
+
+impl BulkError {
+    fn message(&self) -> String {
+        match self {
+            BulkError::Database(error) => format!("{:?}", error),
+            BulkError::QuotaExceeded { quota, adding } => quota.describe(*adding),
+        }
+    }
+}
+
+/// Applies the operation to every device in one transaction and reports the
+/// outcome per device. Devices removed since they were selected are reported
+/// as not found.
+pub async fn apply(
+    db: &Pool<Postgres>,
+    events: &EventHub,
+    device_ids: &[Uuid],
+    operation: &BulkOperationSchema,
+) -> Result<Vec<Value>, BulkError> {
+    let mut tx = db.begin().await?;
+
+    let (updated, deleted) = match operation {
+        BulkOperationSchema::SetStatus { status } => {
+            let devices = sqlx::query_as!(
+                DeviceModel,
+                "UPDATE devices SET status = $1 WHERE id = ANY($2) RETURNING *",
+                status,
+                device_ids
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            (devices, Vec::new())
+        }
+        BulkOperationSchema::Transfer { client_id } => {
+            // Held until commit so concurrent additions to the target count these devices
+            if let Some(quota) = lock_device_quota(&mut tx, *client_id).await? {
+                let adding = sqlx::query_scalar!(
+                    r#"SELECT COUNT(*) AS "adding!" FROM devices WHERE id = ANY($1) AND client_id <> $2"#,
+                    device_ids,
+                    client_id
+                )
+                .fetch_one(&mut tx)
+                .await? as usize;
+
+                if !quota.allows(adding) {
+                    return Err(BulkError::QuotaExceeded { quota, adding });
+                }
+            }
+
+            // Groups belong to the previous client
+            sqlx::query!(
+                "DELETE FROM device_group_members WHERE device_id = ANY($1)",
+                device_ids
+            )
+            .execute(&mut tx)
+            .await?;
+
+            // Nicknames still derived from the previous client follow the new one
+            let devices = sqlx::query_as!(
+                DeviceModel,
+                r#"
+                UPDATE devices
+                SET client_id = target.id,
+                    nickname = CASE
+                        WHEN devices.nickname = lower(source.name) || devices.serial_number
+                        THEN lower(target.name) || devices.serial_number
+                        ELSE devices.nickname
+                    END
+                FROM clients AS source, clients AS target
+                WHERE source.id = devices.client_id AND target.id = $1 AND devices.id = ANY($2)
+                RETURNING devices.*
+                "#,
+                client_id,
+                device_ids
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            (devices, Vec::new())
+        }
+        BulkOperationSchema::AddTag { tag } => {
+            sqlx::query!(
+                "INSERT INTO tags (name) VALUES ($1) ON CONFLICT (name) DO NOTHING",
+                tag
+            )
+            .execute(&mut tx)
+            .await?;
+
+            sqlx::query!(
+                r#"
+                INSERT INTO device_tags (device_id, tag_id)
+                SELECT d.id, t.id FROM devices d, tags t
+                WHERE t.name = $1 AND d.id = ANY($2)
+                ON CONFLICT DO NOTHING
+                "#,
+                tag,
+                device_ids
+            )
+            .execute(&mut tx)
+            .await?;
+
+            let devices = sqlx::query_as!(
+                DeviceModel,
+                "SELECT * FROM devices WHERE id = ANY($1)",
+                device_ids
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            (devices, Vec::new())
+        }
+        BulkOperationSchema::RemoveTag { tag } => {
+            sqlx::query!(
+                r#"
+                DELETE FROM device_tags
+                WHERE tag_id = (SELECT id FROM tags WHERE name = $1) AND device_id = ANY($2)
+                "#,
+                tag,
+                device_ids
+            )
+            .execute(&mut tx)
+            .await?;
+
+            let devices = sqlx::query_as!(
+                DeviceModel,
+                "SELECT * FROM devices WHERE id = ANY($1)",
+                device_ids
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            (devices, Vec::new())
+        }
+        BulkOperationSchema::Delete => {
+            let ids = sqlx::query_scalar!(
+                "DELETE FROM devices WHERE id = ANY($1) RETURNING id",
+                device_ids
+            )
+            .fetch_all(&mut tx)
+            .await?;
+            (Vec::new(), ids)
+        }
+    };
+
+    tx.commit().await?;
+
+    for device in &updated {
+        events.publish(FleetEventKind::DeviceUpdated, device.client_id, device.id, device);
+    }
+
+    let updated: HashSet<Uuid> = updated.iter().map(|device| device.id).collect();
+    let deleted: HashSet<Uuid> = deleted.into_iter().collect();
+
+    Ok(device_ids
+        .iter()
+        .map(|id| {
+            let status = if updated.contains(id) {
+                "updated"
+            } else if deleted.contains(id) {
+                "deleted"
+            } else {
+                "not found"
+            };
+            json!({ "device_id": id, "status": status })
+        })
+        .collect())
+}
+
+/// Runs a queued bulk operation and records its outcome on the job.
+pub async fn run_job(
+    db: Pool<Postgres>,
+    events: EventHub,
+    job_id: Uuid,
+    device_ids: Vec<Uuid>,
+    operation: BulkOperationSchema,
+) {
+    let finished = match apply(&db, &events, &device_ids, &operation).await {
+        Ok(results) => sqlx::query!(
+            r#"
+            UPDATE bulk_jobs
+            SET status = 'completed', results = $1, finished_at = now()
+            WHERE id = $2
+            "#,
+            Value::from(results),
+            job_id
+        )
+        .execute(&db)
+        .await,
+        Err(error) => {
+            let message = error.message();
+            log::error!("Bulk job {} failed: {}", job_id, message);
+            sqlx::query!(
+                r#"
+                UPDATE bulk_jobs
+                SET status = 'failed', error = $1, finished_at = now()
+                WHERE id = $2
+                "#,
+                message,
+                job_id
+            )
+            .execute(&db)
+            .await
+        }
+    };
+
+    if let Err(error) = finished {
+        log::error!("Bulk job {} outcome not stored: {:?}", job_id, error);
+    }
+}
+
+/// Jobs left running by a previous process never committed anything.
+pub async fn fail_interrupted_jobs(db: &Pool<Postgres>) {
+    if let Err(error) = sqlx::query!(
+        r#"
+        UPDATE bulk_jobs
+        SET status = 'failed', error = 'Interrupted by a server restart, nothing was applied', finished_at = now()
+        WHERE status = 'running'
+        "#
+    )
+    .execute(db)
+    .await
+    {
+        log::error!("Interrupted bulk jobs not marked as failed: {:?}", error);
+    }
+}
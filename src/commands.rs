@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::model::CommandModel;
+
+pub const COMMAND_NAMES: [&str; 5] = ["reboot", "set_interval", "cut_engine", "restore_engine", "custom"];
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Gateway a command left the queue through.
+#[derive(Debug, Clone, Copy)]
+pub enum DeliveryChannel {
+    Teltonika,
+    Mqtt,
+    Http,
+}
+
+impl DeliveryChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryChannel::Teltonika => "teltonika",
+            DeliveryChannel::Mqtt => "mqtt",
+            DeliveryChannel::Http => "http",
+        }
+    }
+}
+
+/// Takes up to `limit` queued commands of a device, oldest first, and marks them sent.
+pub async fn drain_pending(
+    db: &Pool<Postgres>,
+    device_id: Uuid,
+    channel: DeliveryChannel,
+    limit: i64,
+) -> Result<Vec<CommandModel>, sqlx::Error> {
+    sqlx::query_as!(
+        CommandModel,
+        r#"
+        UPDATE commands SET status = 'sent', sent_at = now(), channel = $2
+        WHERE id IN (
+            SELECT id FROM commands
+            WHERE device_id = $1 AND status = 'queued' AND expires_at > now()
+            ORDER BY created_at
+            LIMIT $3
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#,
+        device_id,
+        channel.as_str(),
+        limit
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Puts commands taken by `drain_pending` back in the queue when the gateway
+/// could not hand them over.
+pub async fn requeue(db: &Pool<Postgres>, command_ids: &[Uuid]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE commands SET status = 'queued', sent_at = NULL, channel = NULL
+        WHERE id = ANY($1) AND status = 'sent'
+        "#,
+        command_ids
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Records the device answer to a sent command, `None` when it was not waiting for one.
+pub async fn complete(
+    db: &Pool<Postgres>,
+    device_id: Uuid,
+    command_id: Uuid,
+    success: bool,
+    response: Option<&str>,
+) -> Result<Option<CommandModel>, sqlx::Error> {
+    sqlx::query_as!(
+        CommandModel,
+        r#"
+        UPDATE commands
+        SET status = CASE WHEN $3 THEN 'acknowledged' ELSE 'failed' END,
+            response = $4,
+            completed_at = now()
+        WHERE id = $1 AND device_id = $2 AND status = 'sent'
+        RETURNING *
+        "#,
+        command_id,
+        device_id,
+        success,
+        response
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Whether a sent command still waits for the device answer, false once it
+/// was answered, expired or failed meanwhile.
+pub async fn awaiting_answer(db: &Pool<Postgres>, command_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM commands WHERE id = $1 AND status = 'sent') AS "awaiting!""#,
+        command_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Fails a command the gateway could not translate for its device.
+pub async fn reject(
+    db: &Pool<Postgres>,
+    command_id: Uuid,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE commands SET status = 'failed', response = $2, completed_at = now() WHERE id = $1",
+        command_id,
+        reason
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Periodically expires commands that were not delivered or answered in time.
+pub async fn run_expiry(db: Pool<Postgres>) {
+    let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = sqlx::query!(
+            r#"
+            UPDATE commands SET status = 'expired', completed_at = now()
+            WHERE status IN ('queued', 'sent') AND expires_at <= now()
+            "#
+        )
+        .execute(&db)
+        .await
+        {
+            log::error!("Command expiry sweep failed: {:?}", error);
+        }
+    }
+}
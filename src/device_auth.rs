@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use actix_web::{HttpRequest, HttpResponse};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{model::DeviceModel, positions::find_device_by_imei, tenant};
+
+/// Unix time in seconds at which the device signed the request.
+pub const TIMESTAMP_HEADER: &str = "X-Device-Timestamp";
+/// Hex HMAC-SHA256 of `{method}\n{path}\n{imei}\n{timestamp}\n{body}`, keyed with SHA-256 of the device secret,
+/// see [`verify_signature`] for the other channels.
+pub const SIGNATURE_HEADER: &str = "X-Device-Signature";
+
+/// How far the signed timestamp may drift from the server clock, in seconds.
+const TIMESTAMP_WINDOW: i64 = 300;
+const SIGNATURE_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+fn unauthorized(message: &str) -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({
+        "status": "error",
+        "message": message
+    }))
+}
+
+fn internal_error(error: sqlx::Error) -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({
+        "status": "error",
+        "message": format!("{:?}", error)
+    }))
+}
+
+/// Server key mixed into every device secret, see [`device_secret`].
+fn server_key() -> Vec<u8> {
+    std::env::var("DEVICE_SECRET_KEY")
+        .expect("DEVICE_SECRET_KEY must be set")
+        .into_bytes()
+}
+
+/// Secret of a device: HMAC-SHA256 under `DEVICE_SECRET_KEY` of its id and
+/// stored salt, so the salt alone is not enough to sign.
+fn device_secret(device_id: Uuid, salt: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&server_key()).expect("HMAC accepts keys of any size");
+    mac.update(device_id.as_bytes());
+    mac.update(salt.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Generates a new secret for the device and stores the salt it is derived from,
+/// returning the secret.
+///
+/// The secret is only ever shown in this return value.
+pub async fn issue_secret(
+    tx: &mut Transaction<'_, Postgres>,
+    device_id: Uuid,
+) -> Result<String, sqlx::Error> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let secret_salt = hex::encode(bytes);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO device_credentials (device_id, secret_salt)
+        VALUES ($1, $2)
+        ON CONFLICT (device_id) DO UPDATE
+        SET secret_salt = EXCLUDED.secret_salt, rotated_at = now()
+        "#,
+        device_id,
+        secret_salt
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(device_secret(device_id, &secret_salt))
+}
+
+/// Why a device signature was not accepted.
+pub enum SignatureError {
+    /// Reason given back to the device
+    Rejected(&'static str),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for SignatureError {
+    fn from(error: sqlx::Error) -> Self {
+        SignatureError::Database(error)
+    }
+}
+
+/// Resolves the device calling a device-facing route from its signed headers,
+/// or the response to reject it with. The rest of the request reaches the
+/// rows of the device's client only.
+pub async fn authenticate_device(
+    req: &HttpRequest,
+    db: &Pool<Postgres>,
+    imei: &str,
+    body: &[u8],
+) -> Result<DeviceModel, HttpResponse> {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let (timestamp, signature) = match (header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER)) {
+        (Some(timestamp), Some(signature)) => (timestamp, signature),
+        _ => return Err(unauthorized("Missing device signature headers")),
+    };
+
+    // The device is looked up before the request has a client to scope it to
+    let verified = tenant::system(verify_signature(
+        db,
+        req.method().as_str(),
+        req.path(),
+        imei,
+        &timestamp,
+        &signature,
+        body,
+    ))
+    .await;
+
+    match verified {
+        Ok(device) => {
+            tenant::restrict(Some(&[device.client_id]));
+            Ok(device)
+        }
+        Err(SignatureError::Rejected(message)) => Err(unauthorized(message)),
+        Err(SignatureError::Database(error)) => Err(internal_error(error)),
+    }
+}
+
+/// Checks the signature of a device message and records it against replays,
+/// returning the device that signed it.
+///
+/// The signed text is `{channel}\n{target}\n{imei}\n{timestamp}\n{body}`: the HTTP
+/// method and path for requests, `UDP` and `nmea` for datagrams, `MQTT` and
+/// the topic for broker messages.
+pub async fn verify_signature(
+    db: &Pool<Postgres>,
+    channel: &str,
+    target: &str,
+    imei: &str,
+    timestamp: &str,
+    signature: &str,
+    body: &[u8],
+) -> Result<DeviceModel, SignatureError> {
+    let signature = signature.to_lowercase();
+
+    match timestamp.parse::<i64>() {
+        Ok(seconds) if (Utc::now().timestamp() - seconds).abs() <= TIMESTAMP_WINDOW => (),
+        _ => return Err(SignatureError::Rejected("Request timestamp outside the accepted window")),
+    }
+
+    let device = match find_device_by_imei(db, imei).await? {
+        Some(device) => device,
+        None => return Err(SignatureError::Rejected("Invalid device credentials")),
+    };
+
+    let secret_salt = sqlx::query_scalar!(
+        "SELECT secret_salt FROM device_credentials WHERE device_id = $1",
+        device.id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    let key = match secret_salt {
+        Some(salt) => Sha256::digest(device_secret(device.id, &salt).as_bytes()),
+        None => return Err(SignatureError::Rejected("Invalid device credentials")),
+    };
+    let signature_bytes = match hex::decode(&signature) {
+        Ok(bytes) => bytes,
+        Err(_) => return Err(SignatureError::Rejected("Invalid device credentials")),
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC accepts keys of any size");
+    // Channel and target keep a signature from being replayed against another route
+    mac.update(format!("{}\n{}\n{}\n{}\n", channel, target, imei, timestamp).as_bytes());
+    mac.update(body);
+    if mac.verify_slice(&signature_bytes).is_err() {
+        return Err(SignatureError::Rejected("Invalid device credentials"));
+    }
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO device_request_signatures (device_id, signature)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        device.id,
+        signature
+    )
+    .execute(db)
+    .await?;
+
+    if inserted.rows_affected() == 0 {
+        return Err(SignatureError::Rejected("Replayed request"));
+    }
+
+    Ok(device)
+}
+
+/// Periodically forgets signatures old enough to be rejected by their timestamp anyway.
+pub async fn run_signature_sweep(db: Pool<Postgres>) {
+    let mut interval = tokio::time::interval(SIGNATURE_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = sqlx::query!(
+            "DELETE FROM device_request_signatures WHERE received_at < now() - make_interval(secs => $1)",
+            (2 * TIMESTAMP_WINDOW) as f64
+        )
+        .execute(&db)
+        .await
+        {
+            log::error!("Device signature sweep failed: {:?}", error);
+        }
+    }
+}
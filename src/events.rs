@@ -0,0 +1,130 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Number of events kept in memory so reconnecting clients can resume.
+const BACKLOG_SIZE: usize = 1024;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FleetEventKind {
+    #[serde(rename = "device.created")]
+    DeviceCreated,
+    #[serde(rename = "device.updated")]
+    DeviceUpdated,
+    #[serde(rename = "position")]
+    Position,
+    #[serde(rename = "alert")]
+    Alert,
+}
+
+impl FleetEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FleetEventKind::DeviceCreated => "device.created",
+            FleetEventKind::DeviceUpdated => "device.updated",
+            FleetEventKind::Position => "position",
+            FleetEventKind::Alert => "alert",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FleetEvent {
+    pub id: u64,
+    pub kind: FleetEventKind,
+    pub client_id: Uuid,
+    pub device_id: Uuid,
+    pub data: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+struct Backlog {
+    next_id: u64,
+    events: VecDeque<FleetEvent>,
+}
+
+/// In-process fan-out of fleet updates to live subscribers (SSE, WebSocket).
+#[derive(Clone)]
+pub struct EventHub {
+    sender: broadcast::Sender<FleetEvent>,
+    backlog: Arc<Mutex<Backlog>>,
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(BACKLOG_SIZE);
+
+        EventHub {
+            sender,
+            backlog: Arc::new(Mutex::new(Backlog {
+                next_id: 1,
+                events: VecDeque::with_capacity(BACKLOG_SIZE),
+            })),
+        }
+    }
+
+    pub fn publish<T: Serialize>(
+        &self,
+        kind: FleetEventKind,
+        client_id: Uuid,
+        device_id: Uuid,
+        data: &T,
+    ) {
+        let data = match serde_json::to_value(data) {
+            Ok(value) => value,
+            Err(error) => {
+                log::error!("failed to serialize {} event: {:?}", kind.as_str(), error);
+                return;
+            }
+        };
+
+        // The lock is held while sending so ids reach subscribers in order
+        let mut backlog = self.backlog.lock().unwrap();
+        let event = FleetEvent {
+            id: backlog.next_id,
+            kind,
+            client_id,
+            device_id,
+            data,
+            created_at: Utc::now(),
+        };
+        backlog.next_id += 1;
+
+        if backlog.events.len() == BACKLOG_SIZE {
+            backlog.events.pop_front();
+        }
+        backlog.events.push_back(event.clone());
+
+        // No receivers is not an error, nobody is listening right now
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribes to new events and returns the buffered ones after `last_event_id`.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Vec<FleetEvent>, broadcast::Receiver<FleetEvent>) {
+        let backlog = self.backlog.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        let replay = match last_event_id {
+            // An id we never issued means the server restarted, replay everything kept
+            Some(last) if last >= backlog.next_id => backlog.events.iter().cloned().collect(),
+            Some(last) => backlog
+                .events
+                .iter()
+                .filter(|event| event.id > last)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+
+        (replay, receiver)
+    }
+}
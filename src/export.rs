@@ -0,0 +1,403 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use actix_web::{
+    error::ErrorInternalServerError,
+    http::header::ACCEPT,
+    web::Bytes,
+    Error, HttpRequest, HttpResponse,
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use zip::{
+    write::{SimpleFileOptions, StreamWriter},
+    CompressionMethod, ZipWriter,
+};
+
+/// Rows fetched ahead of the client before the query waits for it to catch up.
+pub const EXPORT_BUFFER: usize = 256;
+/// Encoded bytes gathered before a chunk is sent.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const XLSX_CONTENT_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+pub type ExportRow = Result<Value, sqlx::Error>;
+
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    Xlsx,
+}
+
+impl ExportFormat {
+    /// First supported media type in the Accept header, CSV when any type goes.
+    pub fn from_request(req: &HttpRequest) -> Option<ExportFormat> {
+        let accept = match req.headers().get(ACCEPT).and_then(|value| value.to_str().ok()) {
+            Some(accept) => accept,
+            None => return Some(ExportFormat::Csv),
+        };
+
+        accept
+            .split(',')
+            .map(|media| media.split(';').next().unwrap_or_default().trim().to_lowercase())
+            .find_map(|media| match media.as_str() {
+                "text/csv" | "text/*" | "*/*" => Some(ExportFormat::Csv),
+                "application/x-ndjson" | "application/jsonl" => Some(ExportFormat::Ndjson),
+                XLSX_CONTENT_TYPE => Some(ExportFormat::Xlsx),
+                _ => None,
+            })
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Xlsx => XLSX_CONTENT_TYPE,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Xlsx => "xlsx",
+        }
+    }
+}
+
+pub fn not_acceptable() -> HttpResponse {
+    HttpResponse::NotAcceptable().json(json!({
+        "status": "error",
+        "message": format!("Accept one of text/csv, application/x-ndjson or {}", XLSX_CONTENT_TYPE)
+    }))
+}
+
+/// Columns asked for in `fields`, every available column when omitted.
+pub fn select_fields(fields: Option<&str>, available: &[&str]) -> Result<Vec<String>, HttpResponse> {
+    let fields: Vec<String> = match fields {
+        Some(fields) => fields
+            .split(',')
+            .map(|field| field.trim().to_string())
+            .filter(|field| !field.is_empty())
+            .collect(),
+        None => Vec::new(),
+    };
+
+    if fields.is_empty() {
+        return Ok(available.iter().map(|field| field.to_string()).collect());
+    }
+
+    let unknown: Vec<&str> = fields
+        .iter()
+        .map(String::as_str)
+        .filter(|field| !available.contains(field))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("Unknown fields {}, use any of {}", unknown.join(", "), available.join(", "))
+        })));
+    }
+
+    Ok(fields)
+}
+
+/// Sends rows of a query to the export channel until it ends or the client goes away.
+pub async fn forward_rows<T: Serialize>(
+    rows: impl Stream<Item = Result<T, sqlx::Error>>,
+    sender: mpsc::Sender<ExportRow>,
+) {
+    let mut rows = Box::pin(rows);
+
+    while let Some(row) = rows.next().await {
+        let failed = row.is_err();
+        let row = row.map(|row| serde_json::to_value(row).unwrap_or_default());
+        if sender.send(row).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
+/// Streams the rows received as an attachment named after the sheet.
+///
+/// Rows are encoded as they arrive, a failed query aborts the response
+/// instead of ending it as if the file were complete.
+pub fn export_response(
+    format: ExportFormat,
+    sheet: &str,
+    fields: Vec<String>,
+    rows: mpsc::Receiver<ExportRow>,
+) -> HttpResponse {
+    let encoder = match Encoder::new(format, sheet, &fields) {
+        Ok(encoder) => encoder,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", sheet, format.extension()),
+        ))
+        .streaming(encode(encoder, fields, rows))
+}
+
+fn encode(
+    encoder: Encoder,
+    fields: Vec<String>,
+    rows: mpsc::Receiver<ExportRow>,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    stream::unfold(Some((encoder, fields, rows)), |state| async move {
+        let (mut encoder, fields, mut rows) = state?;
+
+        loop {
+            let result = match rows.recv().await {
+                Some(Ok(row)) => encoder.row(&fields, &row),
+                Some(Err(error)) => {
+                    log::error!("Export query failed: {:?}", error);
+                    return Some((Err(ErrorInternalServerError("Export query failed")), None));
+                }
+                None => {
+                    return match encoder.finish() {
+                        Ok(chunk) => Some((Ok(chunk), None)),
+                        Err(error) => Some((Err(ErrorInternalServerError(error)), None)),
+                    };
+                }
+            };
+
+            if let Err(error) = result {
+                return Some((Err(ErrorInternalServerError(error)), None));
+            }
+            if encoder.pending() >= CHUNK_SIZE {
+                let chunk = encoder.take();
+                return Some((Ok(chunk), Some((encoder, fields, rows))));
+            }
+        }
+    })
+}
+
+/// Text of a cell, JSON for nested values.
+fn cell_text(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// Zip output shared with the encoder so it can be drained between rows.
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Encoder {
+    Csv(Vec<u8>),
+    Ndjson(Vec<u8>),
+    Xlsx(Box<ZipWriter<StreamWriter<SharedBuffer>>>, SharedBuffer),
+}
+
+impl Encoder {
+    /// Starts the output with everything that comes before the first row.
+    fn new(format: ExportFormat, sheet: &str, fields: &[String]) -> io::Result<Encoder> {
+        match format {
+            ExportFormat::Csv => {
+                let mut encoder = Encoder::Csv(Vec::new());
+                encoder.write_csv(fields.iter().map(String::as_str))?;
+                Ok(encoder)
+            }
+            ExportFormat::Ndjson => Ok(Encoder::Ndjson(Vec::new())),
+            ExportFormat::Xlsx => {
+                let buffer = SharedBuffer::default();
+                let mut zip = ZipWriter::new_stream(buffer.clone());
+                let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+                for (name, content) in xlsx_parts(sheet) {
+                    zip.start_file(name, options)?;
+                    zip.write_all(content.as_bytes())?;
+                }
+
+                zip.start_file("xl/worksheets/sheet1.xml", options)?;
+                zip.write_all(concat!(
+                    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                    r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#
+                ).as_bytes())?;
+
+                let mut encoder = Encoder::Xlsx(Box::new(zip), buffer);
+                let header: Vec<Value> = fields.iter().map(|field| Value::from(field.as_str())).collect();
+                encoder.write_xlsx_row(&header)?;
+                Ok(encoder)
+            }
+        }
+    }
+
+    fn row(&mut self, fields: &[String], row: &Value) -> io::Result<()> {
+        match self {
+            Encoder::Csv(_) => {
+                let cells: Vec<String> = fields.iter().map(|field| cell_text(row.get(field))).collect();
+                self.write_csv(cells.iter().map(String::as_str))
+            }
+            Encoder::Ndjson(output) => {
+                let object: serde_json::Map<String, Value> = fields
+                    .iter()
+                    .map(|field| (field.clone(), row.get(field).cloned().unwrap_or_default()))
+                    .collect();
+                serde_json::to_writer(&mut *output, &object)?;
+                output.push(b'\n');
+                Ok(())
+            }
+            Encoder::Xlsx(..) => {
+                let cells: Vec<Value> = fields
+                    .iter()
+                    .map(|field| row.get(field).cloned().unwrap_or_default())
+                    .collect();
+                self.write_xlsx_row(&cells)
+            }
+        }
+    }
+
+    fn write_csv<'a>(&mut self, cells: impl Iterator<Item = &'a str>) -> io::Result<()> {
+        if let Encoder::Csv(output) = self {
+            let mut writer = csv::Writer::from_writer(output);
+            writer.write_record(cells)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Inline strings keep the sheet free of a shared string table that
+    /// could only be written after the last row.
+    fn write_xlsx_row(&mut self, cells: &[Value]) -> io::Result<()> {
+        if let Encoder::Xlsx(zip, _) = self {
+            let mut xml = String::from("<row>");
+            for cell in cells {
+                match cell {
+                    Value::Null => xml.push_str("<c/>"),
+                    Value::Number(number) => xml.push_str(&format!("<c><v>{}</v></c>", number)),
+                    Value::Bool(flag) => xml.push_str(&format!("<c t=\"b\"><v>{}</v></c>", *flag as u8)),
+                    cell => xml.push_str(&format!(
+                        "<c t=\"inlineStr\"><is><t xml:space=\"preserve\">{}</t></is></c>",
+                        escape_xml(&cell_text(Some(cell)))
+                    )),
+                }
+            }
+            xml.push_str("</row>");
+            zip.write_all(xml.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn pending(&self) -> usize {
+        match self {
+            Encoder::Csv(output) | Encoder::Ndjson(output) => output.len(),
+            Encoder::Xlsx(_, buffer) => buffer.0.borrow().len(),
+        }
+    }
+
+    fn take(&mut self) -> Bytes {
+        match self {
+            Encoder::Csv(output) | Encoder::Ndjson(output) => Bytes::from(std::mem::take(output)),
+            Encoder::Xlsx(_, buffer) => Bytes::from(std::mem::take(&mut *buffer.0.borrow_mut())),
+        }
+    }
+
+    /// Whatever is left of the output, closing the sheet and the zip for XLSX.
+    fn finish(self) -> io::Result<Bytes> {
+        match self {
+            Encoder::Csv(output) | Encoder::Ndjson(output) => Ok(Bytes::from(output)),
+            Encoder::Xlsx(mut zip, buffer) => {
+                zip.write_all(b"</sheetData></worksheet>")?;
+                zip.finish()?;
+                let output = std::mem::take(&mut *buffer.0.borrow_mut());
+                Ok(Bytes::from(output))
+            }
+        }
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            // Not allowed anywhere in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Workbook parts around the single sheet.
+fn xlsx_parts(sheet: &str) -> [(&'static str, String); 4] {
+    [
+        (
+            "[Content_Types].xml",
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+                r#"<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+                r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+                r#"<Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#,
+                r#"<Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#,
+                r#"</Types>"#
+            )
+            .to_string(),
+        ),
+        (
+            "_rels/.rels",
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>"#,
+                r#"</Relationships>"#
+            )
+            .to_string(),
+        ),
+        (
+            "xl/workbook.xml",
+            format!(
+                concat!(
+                    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                    r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" "#,
+                    r#"xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">"#,
+                    r#"<sheets><sheet name="{}" sheetId="1" r:id="rId1"/></sheets></workbook>"#
+                ),
+                escape_xml(sheet)
+            ),
+        ),
+        (
+            "xl/_rels/workbook.xml.rels",
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+                r#"<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/>"#,
+                r#"</Relationships>"#
+            )
+            .to_string(),
+        ),
+    ]
+}
@@ -0,0 +1,345 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use sqlx::{Pool, Postgres};
+use tokio::net::UdpSocket;
+
+use crate::{
+    device_auth::{verify_signature, SignatureError},
+    events::EventHub,
+    model::DeviceModel,
+    positions::{store_positions, NewPosition},
+};
+
+const KNOTS_TO_KMH: f64 = 1.852;
+
+#[derive(Debug, Default)]
+struct FixBuilder {
+    time: Option<NaiveTime>,
+    date: Option<NaiveDate>,
+    valid: bool,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    altitude: Option<f64>,
+    satellites: Option<i32>,
+    speed: Option<f64>,
+    course: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+pub struct NmeaBatch {
+    pub positions: Vec<NewPosition>,
+    pub rejected: usize,
+}
+
+/// Returns the sentence fields when the `*HH` checksum matches.
+fn checked_fields(sentence: &str) -> Option<Vec<&str>> {
+    let body = sentence.trim().strip_prefix('$')?;
+    let (body, checksum) = body.split_once('*')?;
+    let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+    let actual = body.bytes().fold(0u8, |acc, byte| acc ^ byte);
+
+    if actual != expected {
+        return None;
+    }
+
+    Some(body.split(',').collect())
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    let seconds: f64 = value.get(4..)?.parse().ok()?;
+    NaiveTime::from_hms_milli_opt(
+        value.get(..2)?.parse().ok()?,
+        value.get(2..4)?.parse().ok()?,
+        seconds.trunc() as u32,
+        (seconds.fract() * 1000.0).round() as u32,
+    )
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    // Two digit years, receivers from before 2000 still report the 1900s
+    let year = value.get(4..6)?.parse::<i32>().ok()?;
+    NaiveDate::from_ymd_opt(
+        if year < 80 { 2000 + year } else { 1900 + year },
+        value.get(2..4)?.parse().ok()?,
+        value.get(..2)?.parse().ok()?,
+    )
+}
+
+/// `ddmm.mmmm` / `dddmm.mmmm` plus hemisphere into signed degrees.
+fn parse_coordinate(value: &str, hemisphere: &str, degree_digits: usize) -> Option<f64> {
+    let degrees: f64 = value.get(..degree_digits)?.parse().ok()?;
+    let minutes: f64 = value.get(degree_digits..)?.parse().ok()?;
+    let coordinate = degrees + minutes / 60.0;
+
+    match hemisphere {
+        "N" | "E" => Some(coordinate),
+        "S" | "W" => Some(-coordinate),
+        _ => None,
+    }
+}
+
+fn field<'a>(fields: &[&'a str], index: usize) -> &'a str {
+    fields.get(index).copied().unwrap_or("")
+}
+
+impl FixBuilder {
+    fn set_location(&mut self, fields: &[&str], index: usize) {
+        self.latitude = parse_coordinate(field(fields, index), field(fields, index + 1), 2);
+        self.longitude = parse_coordinate(field(fields, index + 2), field(fields, index + 3), 3);
+    }
+
+    /// Fixes without an `RMC` date are dated to the day that puts them nearest
+    /// `reference`, which crosses midnight with the receiver.
+    fn build(self, reference: DateTime<Utc>) -> Option<NewPosition> {
+        if !self.valid {
+            return None;
+        }
+
+        let time = self.time?;
+        let recorded_at = match self.date {
+            Some(date) => Utc.from_utc_datetime(&date.and_time(time)),
+            None => [-1, 0, 1]
+                .into_iter()
+                .map(|days| Utc.from_utc_datetime(&(reference.date_naive() + Duration::days(days)).and_time(time)))
+                .min_by_key(|at| (*at - reference).num_seconds().abs())?,
+        };
+
+        Some(NewPosition {
+            recorded_at,
+            latitude: self.latitude?,
+            longitude: self.longitude?,
+            altitude: self.altitude,
+            speed: self.speed,
+            course: self.course,
+            satellites: self.satellites,
+            ..Default::default()
+        })
+    }
+}
+
+/// What undated fixes of a device are dated against: its last fix, unless that
+/// is more than a day old and says nothing about the current day.
+pub fn date_reference(device: &DeviceModel) -> DateTime<Utc> {
+    let now = Utc::now();
+    if (now - device.upload_gps).num_hours().abs() < 24 {
+        device.upload_gps
+    } else {
+        now
+    }
+}
+
+/// Combines `RMC`, `GGA` and `VTG` sentences sharing a fix time into positions.
+/// `GGA` carries no date, fixes without an `RMC` are dated by the last `RMC`
+/// before them or, failing that, by `reference`.
+pub fn parse_sentences(text: &str, reference: DateTime<Utc>) -> NmeaBatch {
+    let mut batch = NmeaBatch::default();
+    let mut current = FixBuilder::default();
+    let mut reference = reference;
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let fields = match checked_fields(line) {
+            Some(fields) => fields,
+            None => {
+                batch.rejected += 1;
+                continue;
+            }
+        };
+
+        let kind = match fields[0].get(2..) {
+            Some(kind) if matches!(fields[0].get(..2), Some("GP") | Some("GN")) => kind,
+            _ => {
+                batch.rejected += 1;
+                continue;
+            }
+        };
+
+        // RMC and GGA are timestamped, a new time closes the previous fix
+        let time = match kind {
+            "RMC" | "GGA" => match parse_time(field(&fields, 1)) {
+                Some(time) => Some(time),
+                None => {
+                    batch.rejected += 1;
+                    continue;
+                }
+            },
+            _ => None,
+        };
+
+        if let Some(time) = time {
+            if current.time.is_some_and(|current_time| current_time != time) {
+                if let Some(position) = std::mem::take(&mut current).build(reference) {
+                    batch.positions.push(position);
+                }
+            }
+            current.time = Some(time);
+        }
+
+        match kind {
+            "RMC" => {
+                current.valid = field(&fields, 2) == "A";
+                current.set_location(&fields, 3);
+                current.speed = field(&fields, 7).parse::<f64>().ok().map(|knots| knots * KNOTS_TO_KMH);
+                current.course = field(&fields, 8).parse().ok();
+                current.date = parse_date(field(&fields, 9));
+                if let (Some(date), Some(time)) = (current.date, current.time) {
+                    reference = Utc.from_utc_datetime(&date.and_time(time));
+                }
+            }
+            "GGA" => {
+                current.valid = current.valid || field(&fields, 6).parse::<u8>().is_ok_and(|quality| quality > 0);
+                if current.latitude.is_none() {
+                    current.set_location(&fields, 2);
+                }
+                current.satellites = field(&fields, 7).parse().ok();
+                current.altitude = field(&fields, 9).parse().ok();
+            }
+            "VTG" => {
+                if let Ok(course) = field(&fields, 1).parse() {
+                    current.course = Some(course);
+                }
+                if let Ok(speed) = field(&fields, 7).parse() {
+                    current.speed = Some(speed);
+                }
+            }
+            _ => batch.rejected += 1,
+        }
+    }
+
+    if let Some(position) = current.build(reference) {
+        batch.positions.push(position);
+    }
+
+    batch
+}
+
+/// Accepts datagrams whose first line is `{imei} {timestamp} {signature}`, signed as
+/// described in [`verify_signature`], followed by NMEA sentences.
+pub async fn run(port: u16, db: Pool<Postgres>, events: EventHub) {
+    let socket = match UdpSocket::bind(("0.0.0.0", port)).await {
+        Ok(socket) => socket,
+        Err(error) => {
+            log::error!("NMEA listener failed to bind port {}: {:?}", port, error);
+            return;
+        }
+    };
+    log::info!("NMEA listener on UDP port {}", port);
+
+    let mut buf = vec![0u8; 65_535];
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(error) => {
+                log::error!("NMEA listener receive failed: {:?}", error);
+                continue;
+            }
+        };
+
+        let text = String::from_utf8_lossy(&buf[..len]);
+        let (header, sentences) = match text.split_once('\n') {
+            Some((header, sentences)) => (header.trim(), sentences),
+            None => {
+                log::warn!("NMEA datagram from {} without header line", addr);
+                continue;
+            }
+        };
+        let (imei, timestamp, signature) = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [imei, timestamp, signature] => (imei, timestamp, signature),
+            _ => {
+                log::warn!("NMEA datagram from {} without signature", addr);
+                continue;
+            }
+        };
+
+        let device = match verify_signature(&db, "UDP", "nmea", imei, timestamp, signature, sentences.as_bytes()).await {
+            Ok(device) => device,
+            Err(SignatureError::Rejected(reason)) => {
+                log::warn!("NMEA datagram from {} for {} rejected: {}", addr, imei, reason);
+                continue;
+            }
+            Err(SignatureError::Database(error)) => {
+                log::error!("NMEA device lookup failed for {}: {:?}", imei, error);
+                continue;
+            }
+        };
+
+        let batch = parse_sentences(sentences, date_reference(&device));
+        if let Err(error) = store_positions(&db, &events, &device, &batch.positions).await {
+            log::error!("NMEA positions from {} not stored: {:?}", imei, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RMC: &str = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+    const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn rmc_and_gga_of_one_fix_are_combined() {
+        let batch = parse_sentences(&format!("{}\n{}\n", RMC, GGA), Utc::now());
+
+        assert_eq!(batch.rejected, 0);
+        assert_eq!(batch.positions.len(), 1);
+        let position = &batch.positions[0];
+        assert_eq!(position.recorded_at, at(1994, 3, 23, 12, 35, 19));
+        assert!((position.latitude - 48.1173).abs() < 1e-9);
+        assert!((position.longitude - 11.516_666_666).abs() < 1e-6);
+        assert!((position.speed.unwrap() - 22.4 * KNOTS_TO_KMH).abs() < 1e-9);
+        assert_eq!(position.course, Some(84.4));
+        assert_eq!(position.satellites, Some(8));
+        assert_eq!(position.altitude, Some(545.4));
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let batch = parse_sentences(&RMC.replace("*6A", "*6B"), Utc::now());
+
+        assert_eq!(batch.rejected, 1);
+        assert!(batch.positions.is_empty());
+    }
+
+    #[test]
+    fn south_and_west_are_negative() {
+        let batch = parse_sentences(
+            "$GPRMC,235950,A,2333.030,S,04638.000,W,000.0,000.0,171026,,*12",
+            Utc::now(),
+        );
+
+        let position = &batch.positions[0];
+        assert!((position.latitude + 23.5505).abs() < 1e-9);
+        assert!((position.longitude + 46.633_333_333).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gga_only_fixes_follow_the_rmc_date_past_midnight() {
+        let batch = parse_sentences(
+            "$GPRMC,235950,A,2333.030,S,04638.000,W,000.0,000.0,171026,,*12\n\
+             $GPGGA,235959,2333.030,S,04638.000,W,1,07,1.1,760.0,M,,M,,*5F\n\
+             $GPGGA,000009,2333.030,S,04638.000,W,1,07,1.1,760.0,M,,M,,*57\n",
+            at(2026, 3, 1, 12, 0, 0),
+        );
+
+        let recorded_at: Vec<_> = batch.positions.iter().map(|position| position.recorded_at).collect();
+        assert_eq!(
+            recorded_at,
+            [at(2026, 10, 17, 23, 59, 50), at(2026, 10, 17, 23, 59, 59), at(2026, 10, 18, 0, 0, 9)]
+        );
+        assert_eq!(batch.positions[1].altitude, Some(760.0));
+    }
+
+    #[test]
+    fn gga_only_fix_is_dated_nearest_the_reference() {
+        let gga = "$GPGGA,235959,2333.030,S,04638.000,W,1,07,1.1,760.0,M,,M,,*5F";
+
+        let batch = parse_sentences(gga, at(2026, 10, 18, 0, 0, 30));
+        assert_eq!(batch.positions[0].recorded_at, at(2026, 10, 17, 23, 59, 59));
+
+        let batch = parse_sentences(gga, at(2026, 10, 18, 12, 0, 30));
+        assert_eq!(batch.positions[0].recorded_at, at(2026, 10, 18, 23, 59, 59));
+    }
+}
@@ -0,0 +1,628 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::{sleep, timeout},
+};
+use uuid::Uuid;
+
+use super::Reader;
+use crate::{
+    commands::{awaiting_answer, complete, drain_pending, reject, DeliveryChannel},
+    events::EventHub,
+    model::{CommandModel, DeviceModel},
+    positions::{find_device_by_imei, insert_positions, publish_positions, touch_upload_data, NewPosition},
+    schema::TelemetryPayloadSchema,
+    telemetry::insert_telemetry,
+    tenant,
+};
+
+const CODEC_8: u8 = 0x08;
+const CODEC_8E: u8 = 0x8E;
+const CODEC_12: u8 = 0x0C;
+const CODEC_12_COMMAND: u8 = 0x05;
+const CODEC_12_RESPONSE: u8 = 0x06;
+/// Largest AVL data field accepted from a tracker.
+const MAX_PACKET_LEN: usize = 64 * 1024;
+/// Trackers keep the socket open between reports, drop it after this much silence.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const IGNITION_IO_ID: u16 = 239;
+/// How often an idle session looks for newly queued commands.
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// A command left unanswered this long fails and the next one is sent.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IoValue {
+    Number(u64),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvlRecord {
+    pub timestamp: DateTime<Utc>,
+    pub longitude: f64,
+    pub latitude: f64,
+    pub altitude: i16,
+    pub angle: u16,
+    pub satellites: u8,
+    pub speed: u16,
+    pub io: BTreeMap<u16, IoValue>,
+}
+
+impl AvlRecord {
+    /// Trackers report 0/0 while they have no fix.
+    pub fn has_fix(&self) -> bool {
+        self.latitude != 0.0 || self.longitude != 0.0
+    }
+
+    fn io_json(&self) -> Value {
+        let io: serde_json::Map<String, Value> = self
+            .io
+            .iter()
+            .map(|(id, value)| {
+                let value = match value {
+                    IoValue::Number(number) => json!(number),
+                    IoValue::Bytes(bytes) => json!(bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+                };
+                (id.to_string(), value)
+            })
+            .collect();
+
+        Value::Object(io)
+    }
+
+    /// Numeric IO elements as `io_{id}` telemetry readings, byte values have no
+    /// reading to go in and are left out.
+    fn io_readings(&self) -> TelemetryPayloadSchema {
+        let metrics = self
+            .io
+            .iter()
+            .filter_map(|(id, value)| match value {
+                IoValue::Number(number) => Some((format!("io_{}", id), *number as f64)),
+                IoValue::Bytes(_) => None,
+            })
+            .collect();
+
+        TelemetryPayloadSchema {
+            timestamp: Some(self.timestamp),
+            metrics,
+        }
+    }
+
+    fn to_position(&self) -> NewPosition {
+        NewPosition {
+            recorded_at: self.timestamp,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            altitude: Some(self.altitude as f64),
+            speed: Some(self.speed as f64),
+            course: Some(self.angle as f64),
+            satellites: Some(self.satellites as i32),
+            ignition: match self.io.get(&IGNITION_IO_ID) {
+                Some(IoValue::Number(value)) => Some(*value != 0),
+                _ => None,
+            },
+            io: Some(self.io_json()),
+        }
+    }
+}
+
+/// CRC-16/IBM as used by Teltonika over the AVL data field.
+pub fn crc16_ibm(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+fn read_coordinate(reader: &mut Reader) -> Result<f64, &'static str> {
+    Ok(reader.u32()? as i32 as f64 / 10_000_000.0)
+}
+
+/// Reads one IO group: element count, then `(id, value)` pairs of a fixed width.
+fn read_io_group(
+    reader: &mut Reader,
+    extended: bool,
+    width: usize,
+    io: &mut BTreeMap<u16, IoValue>,
+) -> Result<(), &'static str> {
+    let count = if extended { reader.u16()? } else { reader.u8()? as u16 };
+
+    for _ in 0..count {
+        let id = if extended { reader.u16()? } else { reader.u8()? as u16 };
+        let value = match width {
+            1 => reader.u8()? as u64,
+            2 => reader.u16()? as u64,
+            4 => reader.u32()? as u64,
+            _ => reader.u64()?,
+        };
+        io.insert(id, IoValue::Number(value));
+    }
+
+    Ok(())
+}
+
+fn read_record(reader: &mut Reader, extended: bool) -> Result<AvlRecord, &'static str> {
+    let timestamp = Utc
+        .timestamp_millis_opt(reader.u64()? as i64)
+        .single()
+        .ok_or("Invalid timestamp")?;
+    // Priority
+    reader.u8()?;
+    let longitude = read_coordinate(reader)?;
+    let latitude = read_coordinate(reader)?;
+    let altitude = reader.u16()? as i16;
+    let angle = reader.u16()?;
+    let satellites = reader.u8()?;
+    let speed = reader.u16()?;
+
+    // Event IO id and total IO count, the groups below carry their own counts
+    if extended {
+        reader.u16()?;
+        reader.u16()?;
+    } else {
+        reader.u8()?;
+        reader.u8()?;
+    }
+
+    let mut io = BTreeMap::new();
+    for width in [1, 2, 4, 8] {
+        read_io_group(reader, extended, width, &mut io)?;
+    }
+
+    if extended {
+        let count = reader.u16()?;
+        for _ in 0..count {
+            let id = reader.u16()?;
+            let len = reader.u16()? as usize;
+            io.insert(id, IoValue::Bytes(reader.bytes(len)?.to_vec()));
+        }
+    }
+
+    Ok(AvlRecord {
+        timestamp,
+        longitude,
+        latitude,
+        altitude,
+        angle,
+        satellites,
+        speed,
+        io,
+    })
+}
+
+/// Decodes the AVL data field (codec id up to the second record count).
+pub fn decode_avl(data: &[u8]) -> Result<Vec<AvlRecord>, &'static str> {
+    let mut reader = Reader::new(data);
+
+    let extended = match reader.u8()? {
+        CODEC_8 => false,
+        CODEC_8E => true,
+        _ => return Err("Unsupported codec"),
+    };
+
+    let count = reader.u8()?;
+    let mut records = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        records.push(read_record(&mut reader, extended)?);
+    }
+
+    if reader.u8()? != count || reader.remaining() != 0 {
+        return Err("Record count mismatch");
+    }
+
+    Ok(records)
+}
+
+/// Text a Teltonika tracker understands for a queued command.
+fn command_text(command: &CommandModel) -> Option<String> {
+    let payload = command.payload.as_ref();
+
+    match command.command.as_str() {
+        "reboot" => Some("cpureset".to_string()),
+        "set_interval" => payload?
+            .get("interval")?
+            .as_u64()
+            .map(|seconds| format!("setparam 10050:{}", seconds)),
+        "cut_engine" => Some("setdigout 1".to_string()),
+        "restore_engine" => Some("setdigout 0".to_string()),
+        "custom" => payload?.get("text")?.as_str().map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Frames a Codec 12 GPRS command.
+pub fn encode_command(text: &str) -> Vec<u8> {
+    let mut data = vec![CODEC_12, 0x01, CODEC_12_COMMAND];
+    data.extend_from_slice(&(text.len() as u32).to_be_bytes());
+    data.extend_from_slice(text.as_bytes());
+    data.push(0x01);
+
+    let mut packet = vec![0, 0, 0, 0];
+    packet.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    packet.extend_from_slice(&data);
+    packet.extend_from_slice(&(crc16_ibm(&data) as u32).to_be_bytes());
+    packet
+}
+
+/// Decodes the data field of a Codec 12 response into its text.
+pub fn decode_response(data: &[u8]) -> Result<String, &'static str> {
+    let mut reader = Reader::new(data);
+
+    if reader.u8()? != CODEC_12 || reader.u8()? != 0x01 || reader.u8()? != CODEC_12_RESPONSE {
+        return Err("Not a Codec 12 response");
+    }
+
+    let len = reader.u32()? as usize;
+    let text = String::from_utf8_lossy(reader.bytes(len)?).to_string();
+
+    if reader.u8()? != 0x01 || reader.remaining() != 0 {
+        return Err("Record count mismatch");
+    }
+
+    Ok(text)
+}
+
+/// Sends the oldest deliverable command, returning its id to await the response.
+async fn send_next_command(
+    socket: &mut TcpStream,
+    db: &Pool<Postgres>,
+    device: &DeviceModel,
+) -> io::Result<Option<Uuid>> {
+    loop {
+        let command = match drain_pending(db, device.id, DeliveryChannel::Teltonika, 1).await {
+            Ok(mut commands) => match commands.pop() {
+                Some(command) => command,
+                None => return Ok(None),
+            },
+            Err(error) => {
+                log::error!("Commands for {} not drained: {:?}", device.imei, error);
+                return Ok(None);
+            }
+        };
+
+        match command_text(&command) {
+            Some(text) => {
+                socket.write_all(&encode_command(&text)).await?;
+                return Ok(Some(command.id));
+            }
+            None => {
+                if let Err(error) = reject(db, command.id, "Unsupported by Teltonika trackers").await {
+                    log::error!("Command {} not rejected: {:?}", command.id, error);
+                }
+            }
+        }
+    }
+}
+
+/// Whether the response to the command sent at `sent_at` is still awaited.
+/// Commands expired or failed meanwhile are let go, unanswered ones fail
+/// after `RESPONSE_TIMEOUT`.
+async fn still_in_flight(db: &Pool<Postgres>, device: &DeviceModel, command_id: Uuid, sent_at: Instant) -> bool {
+    if sent_at.elapsed() > RESPONSE_TIMEOUT {
+        if let Err(error) = complete(db, device.id, command_id, false, Some("No response from the tracker")).await {
+            log::error!("Command {} not failed: {:?}", command_id, error);
+        }
+        return false;
+    }
+
+    match awaiting_answer(db, command_id).await {
+        Ok(awaiting) => awaiting,
+        Err(error) => {
+            log::error!("Command {} not checked: {:?}", command_id, error);
+            true
+        }
+    }
+}
+
+/// Reads one framed AVL packet and returns its data field after the CRC check.
+async fn read_packet(socket: &mut TcpStream) -> io::Result<Option<Result<Vec<u8>, &'static str>>> {
+    let mut header = [0u8; 8];
+    match socket.read_exact(&mut header).await {
+        Ok(_) => (),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+
+    if header[..4] != [0, 0, 0, 0] {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid preamble"));
+    }
+
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if len == 0 || len > MAX_PACKET_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid packet length"));
+    }
+
+    let mut data = vec![0u8; len + 4];
+    socket.read_exact(&mut data).await?;
+    let crc = data.split_off(len);
+
+    if u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) != crc16_ibm(&data) as u32 {
+        return Ok(Some(Err("CRC mismatch")));
+    }
+
+    Ok(Some(Ok(data)))
+}
+
+async fn handle_connection(
+    mut socket: TcpStream,
+    db: &Pool<Postgres>,
+    events: &EventHub,
+) -> io::Result<()> {
+    let len = timeout(IDLE_TIMEOUT, socket.read_u16()).await?? as usize;
+    if len == 0 || len > 32 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid IMEI length"));
+    }
+    let mut imei = vec![0u8; len];
+    socket.read_exact(&mut imei).await?;
+    let imei = String::from_utf8_lossy(&imei).to_string();
+
+    let device = match find_device_by_imei(db, &imei).await {
+        Ok(Some(device)) => device,
+        Ok(None) => {
+            log::warn!("Teltonika login rejected for unknown IMEI {}", imei);
+            socket.write_all(&[0x00]).await?;
+            return Ok(());
+        }
+        Err(error) => {
+            log::error!("Teltonika login lookup failed for {}: {:?}", imei, error);
+            socket.write_all(&[0x00]).await?;
+            return Ok(());
+        }
+    };
+    socket.write_all(&[0x01]).await?;
+
+    let mut in_flight: Option<(Uuid, Instant)> = None;
+    let mut last_seen = Instant::now();
+    let mut probe = [0u8; 1];
+
+    loop {
+        if let Some((command_id, sent_at)) = in_flight {
+            if !still_in_flight(db, &device, command_id, sent_at).await {
+                in_flight = None;
+            }
+        }
+        if in_flight.is_none() {
+            in_flight = send_next_command(&mut socket, db, &device)
+                .await?
+                .map(|command_id| (command_id, Instant::now()));
+        }
+
+        // Wake up now and then to push commands queued while the tracker is quiet.
+        // Peeking waits for actual bytes and leaves them to read_packet.
+        tokio::select! {
+            peeked = socket.peek(&mut probe) => {
+                if peeked? == 0 {
+                    return Ok(());
+                }
+            }
+            _ = sleep(COMMAND_POLL_INTERVAL) => {
+                if last_seen.elapsed() > IDLE_TIMEOUT {
+                    return Ok(());
+                }
+                continue;
+            }
+        }
+        last_seen = Instant::now();
+
+        let packet = match timeout(IDLE_TIMEOUT, read_packet(&mut socket)).await? {
+            Ok(Some(packet)) => packet,
+            Ok(None) => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        if let Ok(data) = &packet {
+            if data.first() == Some(&CODEC_12) {
+                match (decode_response(data), in_flight.take()) {
+                    (Ok(text), Some((command_id, _))) => {
+                        if let Err(error) = complete(db, device.id, command_id, true, Some(&text)).await {
+                            log::error!("Command {} not acknowledged: {:?}", command_id, error);
+                        }
+                    }
+                    (Ok(_), None) => log::warn!("Teltonika response from {} without a command", imei),
+                    (Err(error), awaited) => {
+                        in_flight = awaited;
+                        log::warn!("Teltonika response from {} dropped: {}", imei, error);
+                    }
+                }
+                continue;
+            }
+        }
+
+        // Acknowledging zero records makes the tracker send the packet again
+        let records = match packet.and_then(|data| decode_avl(&data)) {
+            Ok(records) => records,
+            Err(error) => {
+                log::warn!("Teltonika packet from {} dropped: {}", imei, error);
+                socket.write_all(&0u32.to_be_bytes()).await?;
+                continue;
+            }
+        };
+
+        let (located, unlocated): (Vec<&AvlRecord>, Vec<&AvlRecord>) =
+            records.iter().partition(|record| record.has_fix());
+        let positions: Vec<NewPosition> = located.into_iter().map(AvlRecord::to_position).collect();
+        // Without a fix there is no position, the IO is kept as telemetry
+        let readings: Vec<TelemetryPayloadSchema> = unlocated
+            .into_iter()
+            .map(AvlRecord::io_readings)
+            .filter(|readings| !readings.metrics.is_empty())
+            .collect();
+
+        // One transaction for the whole packet, the tracker resends it unless
+        // every record is committed
+        let stored = async {
+            let mut tx = db.begin().await?;
+            let stored = insert_positions(&mut tx, &device, &positions).await?;
+            insert_telemetry(&mut tx, device.id, &readings).await?;
+            if let Some(latest) = records.iter().map(|record| record.timestamp).max() {
+                touch_upload_data(&mut tx, device.id, latest).await?;
+            }
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(stored)
+        }
+        .await;
+
+        match stored {
+            Ok(stored) => {
+                publish_positions(events, &device, &stored);
+                socket.write_all(&(records.len() as u32).to_be_bytes()).await?;
+            }
+            Err(error) => {
+                log::error!("Teltonika records from {} not stored: {:?}", imei, error);
+                socket.write_all(&0u32.to_be_bytes()).await?;
+            }
+        }
+    }
+}
+
+/// Accepts Teltonika trackers on `port` and stores what they report.
+pub async fn run(port: u16, db: Pool<Postgres>, events: EventHub) {
+    let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            log::error!("Teltonika gateway failed to bind port {}: {:?}", port, error);
+            return;
+        }
+    };
+    log::info!("Teltonika gateway listening on port {}", port);
+
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                log::error!("Teltonika gateway accept failed: {:?}", error);
+                continue;
+            }
+        };
+
+        let db = db.clone();
+        let events = events.clone();
+        actix_web::rt::spawn(tenant::inherit(async move {
+            if let Err(error) = handle_connection(socket, &db, &events).await {
+                log::warn!("Teltonika session {} closed: {}", addr, error);
+            }
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a framed packet from the Teltonika protocol documentation into
+    /// its data field and CRC.
+    fn packet(hex: &str) -> (Vec<u8>, u16) {
+        let bytes = hex::decode(hex).unwrap();
+        let len = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        let crc = u32::from_be_bytes([bytes[8 + len], bytes[9 + len], bytes[10 + len], bytes[11 + len]]);
+        (bytes[8..8 + len].to_vec(), crc as u16)
+    }
+
+    const CODEC_8_EXAMPLE: &str = "000000000000003608010000016B40D8EA30010000000000000000000000000000000105021503010101425E0F01F10000601A014E0000000000000000010000C7CF";
+    const CODEC_8E_EXAMPLE: &str = "000000000000004A8E010000016B412CEE000100000000000000000000000000000000010005000100010100010011001D00010010015E2C880002000B000000003544C87A000E000000001DD7E06A00000100002994";
+    const CODEC_12_GETINFO: &str = "000000000000000F0C010500000007676574696E666F0100004312";
+    const CODEC_12_GETINFO_RESPONSE: &str = "00000000000000900C010600000088494E493A323031392F372F323220373A3232205254433A323031392F372F323220373A3533205253543A32204552523A312053523A302042523A302043463A302046473A3020464C3A302054553A302F302055543A3020534D533A30204E4F4750533A303A3330204750533A31205341543A302052533A332052463A36352053463A31204D443A30010000C78F";
+
+    #[test]
+    fn crc16_matches_the_documented_packets() {
+        assert_eq!(crc16_ibm(b"123456789"), 0xBB3D);
+        for example in [CODEC_8_EXAMPLE, CODEC_8E_EXAMPLE, CODEC_12_GETINFO, CODEC_12_GETINFO_RESPONSE] {
+            let (data, crc) = packet(example);
+            assert_eq!(crc16_ibm(&data), crc);
+        }
+    }
+
+    #[test]
+    fn codec_8_record() {
+        let records = decode_avl(&packet(CODEC_8_EXAMPLE).0).unwrap();
+
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.timestamp, Utc.with_ymd_and_hms(2019, 6, 10, 10, 4, 46).unwrap());
+        assert!(!record.has_fix());
+        assert_eq!(
+            record.io,
+            BTreeMap::from([
+                (1, IoValue::Number(1)),
+                (21, IoValue::Number(3)),
+                (66, IoValue::Number(0x5E0F)),
+                (78, IoValue::Number(0)),
+                (241, IoValue::Number(0x601A)),
+            ])
+        );
+    }
+
+    #[test]
+    fn codec_8e_record() {
+        let records = decode_avl(&packet(CODEC_8E_EXAMPLE).0).unwrap();
+
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.timestamp, Utc.with_ymd_and_hms(2019, 6, 10, 11, 36, 32).unwrap());
+        assert_eq!(
+            record.io,
+            BTreeMap::from([
+                (1, IoValue::Number(1)),
+                (11, IoValue::Number(0x3544C87A)),
+                (14, IoValue::Number(0x1DD7E06A)),
+                (16, IoValue::Number(0x015E2C88)),
+                (17, IoValue::Number(0x1D)),
+            ])
+        );
+    }
+
+    #[test]
+    fn coordinates_are_signed() {
+        let mut data = packet(CODEC_8_EXAMPLE).0;
+        // Longitude and latitude of the first record follow the codec id,
+        // record count, timestamp and priority
+        data[11..15].copy_from_slice(&(-466_000_000i32).to_be_bytes());
+        data[15..19].copy_from_slice(&(-235_500_000i32).to_be_bytes());
+
+        let record = &decode_avl(&data).unwrap()[0];
+        assert!(record.has_fix());
+        assert!((record.longitude + 46.6).abs() < 1e-9);
+        assert!((record.latitude + 23.55).abs() < 1e-9);
+    }
+
+    #[test]
+    fn record_count_mismatch_is_rejected() {
+        let mut data = packet(CODEC_8_EXAMPLE).0;
+        *data.last_mut().unwrap() = 2;
+
+        assert_eq!(decode_avl(&data), Err("Record count mismatch"));
+        assert_eq!(decode_avl(&[0x10, 0x00, 0x00]), Err("Unsupported codec"));
+    }
+
+    #[test]
+    fn command_is_framed_as_codec_12() {
+        assert_eq!(encode_command("getinfo"), hex::decode(CODEC_12_GETINFO).unwrap());
+    }
+
+    #[test]
+    fn codec_12_response_text() {
+        let text = decode_response(&packet(CODEC_12_GETINFO_RESPONSE).0).unwrap();
+
+        assert!(text.starts_with("INI:2019/7/22 7:22 RTC:2019/7/22 7:53"));
+        assert!(text.ends_with("MD:0"));
+        assert_eq!(decode_response(&packet(CODEC_12_GETINFO).0), Err("Not a Codec 12 response"));
+    }
+}
@@ -1,16 +1,32 @@
 mod schema;
 mod model;
 mod services;
+mod alerts;
+mod auth;
+mod bulk;
+mod commands;
+mod device_auth;
+mod events;
+mod export;
+mod gateways;
+mod ota;
+mod plans;
+mod positions;
+mod telemetry;
+mod tenant;
+mod trips;
+mod usage;
+mod validation;
 
 use actix_cors::Cors;
 use actix_web::{
+    dev::Service,
     http::header,
     middleware::Logger,
     web, App, HttpResponse, HttpServer,
 };
 use dotenv::dotenv;
 use sqlx::{
-    postgres::PgPoolOptions,
     Postgres,
     Pool,
 };
@@ -22,14 +38,29 @@ use utoipa::{
 };
 use utoipa_swagger_ui::SwaggerUi;
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use events::EventHub;
 
 pub struct AppState {
     pub db: Pool<Postgres>,
+    pub events: EventHub,
+    /// Key the API tokens are signed with
+    pub jwt_secret: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct TokenClaims {
     id: i32,
+    /// Restricts the token to a single client, operators have none
+    #[serde(default)]
+    client_id: Option<Uuid>,
+    /// Read-only access to one device group of the client and its subgroups
+    #[serde(default)]
+    group_id: Option<Uuid>,
+    /// The token's client and all of its sub-clients, loaded when the token is validated
+    #[serde(skip)]
+    clients: Vec<Uuid>,
 }
 
 #[derive(OpenApi)]
@@ -37,15 +68,95 @@ pub struct TokenClaims {
     paths(
         crate::services::create_client,
         crate::services::get_all_clients,
+        crate::services::export_clients,
         crate::services::get_client_by_id,
         crate::services::update_client_by_id,
         crate::services::delete_client_by_id,
+        crate::services::get_client_tags,
+        crate::services::set_client_metadata_schema,
+        crate::services::delete_client_metadata_schema,
+        crate::services::get_client_quota,
+        crate::services::get_client_usage,
+        crate::services::get_client_summary,
 
         crate::services::create_device,
         crate::services::get_all_devices,
+        crate::services::export_devices,
         crate::services::get_device_by_id,
         crate::services::update_device_by_id,
         crate::services::delete_device_by_id,
+        crate::services::rotate_device_secret,
+        crate::services::import_devices,
+        crate::services::bulk_devices,
+        crate::services::get_bulk_job,
+        crate::services::get_device_tags,
+        crate::services::set_device_tags,
+
+        crate::services::create_group,
+        crate::services::get_client_groups,
+        crate::services::get_group_by_id,
+        crate::services::update_group_by_id,
+        crate::services::delete_group_by_id,
+        crate::services::add_group_devices,
+        crate::services::remove_group_device,
+        crate::services::get_group_devices,
+        crate::services::get_group_positions,
+
+        crate::services::create_sim_card,
+        crate::services::get_all_sim_cards,
+        crate::services::get_sim_cards_on_retired_devices,
+        crate::services::get_devices_without_sim,
+        crate::services::get_sim_card_by_id,
+        crate::services::update_sim_card_by_id,
+        crate::services::delete_sim_card_by_id,
+        crate::services::assign_sim_card,
+        crate::services::unassign_sim_card,
+        crate::services::get_sim_card_history,
+
+        crate::services::create_device_model,
+        crate::services::get_all_device_models,
+        crate::services::get_device_model_by_id,
+        crate::services::update_device_model_by_id,
+        crate::services::delete_device_model_by_id,
+
+        crate::services::create_plan,
+        crate::services::get_all_plans,
+        crate::services::get_plan_by_id,
+        crate::services::update_plan_by_id,
+        crate::services::delete_plan_by_id,
+
+        crate::services::create_inventory_devices,
+        crate::services::get_inventory_devices,
+        crate::services::claim_device,
+
+        crate::services::create_command,
+        crate::services::get_device_commands,
+        crate::services::get_command_by_id,
+        crate::services::update_device_config,
+        crate::services::get_device_config,
+
+        crate::services::get_pending_work,
+        crate::services::acknowledge_work,
+        crate::services::download_firmware,
+        crate::services::report_firmware_progress,
+
+        crate::services::upload_firmware,
+        crate::services::get_all_firmwares,
+        crate::services::create_campaign,
+        crate::services::get_campaign_by_id,
+        crate::services::get_campaign_updates,
+        crate::services::update_campaign_by_id,
+        crate::services::advance_campaign,
+
+        crate::services::ingest_nmea,
+        crate::services::ingest_telemetry,
+
+        crate::services::get_device_telemetry,
+
+        crate::services::get_device_trips,
+
+        crate::services::stream_devices,
+        crate::services::live_tracking,
 
         crate::services::health_checker,
     ),
@@ -53,6 +164,18 @@ pub struct TokenClaims {
     tags(
         (name = "Clientes", description = "Rotas relacionadas aos clientes"),
         (name = "Dispositivos", description = "Rotas relacionadas aos dispositivos"),
+        (name = "Grupos", description = "Rotas dos grupos de dispositivos dos clientes"),
+        (name = "SIM", description = "Rotas do estoque de SIM cards e da sua instalação nos dispositivos"),
+        (name = "Modelos", description = "Rotas do catálogo de modelos de dispositivos"),
+        (name = "Planos", description = "Rotas dos planos de assinatura e dos limites dos clientes"),
+        (name = "Inventário", description = "Rotas do estoque de fábrica e da reivindicação de dispositivos"),
+        (name = "Comandos", description = "Rotas de envio de comandos aos dispositivos"),
+        (name = "Gateway HTTP", description = "Rotas de consulta de trabalho pendente pelos dispositivos"),
+        (name = "Firmware", description = "Rotas do catálogo de firmware e das campanhas de atualização OTA"),
+        (name = "Ingestão", description = "Rotas de ingestão de dados dos rastreadores"),
+        (name = "Telemetria", description = "Rotas de consulta da telemetria dos dispositivos"),
+        (name = "Viagens", description = "Rotas das viagens detectadas a partir das posições"),
+        (name = "Tempo real", description = "Rotas de atualização em tempo real da frota"),
         (name = "Health", description = "Rotas para verificação do status da API"),
     ),
     modifiers(&SecurityAddon)
@@ -90,6 +213,14 @@ async fn openapi_json() -> HttpResponse {
     HttpResponse::Ok().json(openapi)
 }
 
+// Porta de um gateway a partir do ambiente, com valor padrão
+fn env_port(name: &str, default: u16) -> u16 {
+    std::env::var(name)
+        .ok()
+        .and_then(|port| port.parse::<u16>().ok())
+        .unwrap_or(default)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("Server started successfully");
@@ -102,24 +233,52 @@ async fn main() -> std::io::Result<()> {
     env_logger::init();
 
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
 
-    let pool = PgPoolOptions::new()
+    let pool = tenant::pool_options()
         .max_connections(10)
         .connect(&database_url)
         .await
         .expect("Failed to connect to the database");
 
+    let events = EventHub::new();
+
+    // The gateways and jobs serve every tenant
+    tenant::system(bulk::fail_interrupted_jobs(&pool)).await;
+
+    actix_web::rt::spawn(tenant::system(gateways::teltonika::run(env_port("TELTONIKA_PORT", 5027), pool.clone(), events.clone())));
+    actix_web::rt::spawn(tenant::system(gateways::gt06::run(env_port("GT06_PORT", 5023), pool.clone(), events.clone())));
+    actix_web::rt::spawn(tenant::system(gateways::nmea::run(env_port("NMEA_UDP_PORT", 5030), pool.clone(), events.clone())));
+
+    actix_web::rt::spawn(tenant::system(commands::run_expiry(pool.clone())));
+    actix_web::rt::spawn(tenant::system(device_auth::run_signature_sweep(pool.clone())));
+    actix_web::rt::spawn(tenant::system(plans::run_retention_sweep(pool.clone())));
+    actix_web::rt::spawn(tenant::system(usage::run_snapshots(pool.clone())));
+
+    if let Ok(mqtt_url) = std::env::var("MQTT_URL") {
+        actix_web::rt::spawn(tenant::system(gateways::mqtt::run(mqtt_url, pool.clone(), events.clone())));
+    }
+
     HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://localhost:5173")
             .allowed_methods(vec!["GET", "POST", "PATCH", "DELETE"])
-            .allowed_headers(vec![header::CONTENT_TYPE])
+            .allowed_headers(vec![
+                header::CONTENT_TYPE,
+                header::AUTHORIZATION,
+                header::HeaderName::from_static("last-event-id"),
+            ])
             .max_age(3600);
 
         App::new()
             .wrap(Logger::default())
             .wrap(cors)
-            .app_data(web::Data::new(AppState { db: pool.clone() }))
+            .wrap_fn(|req, srv| tenant::scope_request(srv.call(req)))
+            .app_data(web::Data::new(AppState {
+                db: pool.clone(),
+                events: events.clone(),
+                jwt_secret: jwt_secret.clone(),
+            }))
             .service(
                 web::scope("/docs")
                     .route("/swagger-ui/openapi.json", web::get().to(openapi_json))
@@ -9,6 +9,34 @@ pub struct ClientModel {
     pub name: String,
     pub status: String,
     pub created_at: Option<DateTime<Utc>>,
+    /// JSON Schema the metadata of the client's devices must follow
+    pub metadata_schema: Option<serde_json::Value>,
+    /// Reseller the client is sold through, none for direct clients
+    pub parent_id: Option<Uuid>,
+    /// Subscription plan, none for clients without limits
+    pub plan_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct PlanModel {
+    pub id: Uuid,
+    pub name: String,
+    /// Devices a client may have, none for no limit
+    pub max_devices: Option<i32>,
+    /// Days positions and telemetry are kept
+    pub retention_days: i32,
+    /// geofences and webhooks
+    pub features: Vec<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct DeviceUsageModel {
+    pub device_id: Uuid,
+    /// None when the device was deleted before the month was computed
+    pub imei: Option<String>,
+    /// Days the device was active and sent data
+    pub active_days: i32,
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
@@ -23,4 +51,237 @@ pub struct DeviceModel {
     pub upload_gps: DateTime<Utc>,
     pub status: String,
     pub created_at: Option<DateTime<Utc>>,
+    /// Free-form attributes such as plate, driver or region
+    pub metadata: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct PositionModel {
+    pub id: i64,
+    pub device_id: Uuid,
+    pub recorded_at: DateTime<Utc>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+    /// km/h
+    pub speed: Option<f64>,
+    pub course: Option<f64>,
+    pub satellites: Option<i32>,
+    pub ignition: Option<bool>,
+    pub io: Option<serde_json::Value>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AlertModel {
+    pub id: Uuid,
+    pub device_id: Uuid,
+    pub kind: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub triggered_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TripModel {
+    pub id: Uuid,
+    pub device_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub start_latitude: f64,
+    pub start_longitude: f64,
+    /// None while the trip is in progress
+    pub ended_at: Option<DateTime<Utc>>,
+    pub end_latitude: Option<f64>,
+    pub end_longitude: Option<f64>,
+    /// Meters
+    pub distance_m: f64,
+    /// km/h
+    pub max_speed: f64,
+    /// km/h over the time in motion
+    pub avg_speed: f64,
+    pub moving_seconds: i32,
+    /// Stationary with the ignition on, or stopped for less than 5 minutes on a
+    /// device without ignition readings, stops that ended the trip excluded
+    pub idle_seconds: i32,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TelemetryModel {
+    pub id: i64,
+    pub device_id: Uuid,
+    pub metric: String,
+    pub value: f64,
+    pub recorded_at: DateTime<Utc>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TelemetryBucketModel {
+    pub metric: String,
+    pub bucket: DateTime<Utc>,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub last: f64,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CommandModel {
+    pub id: Uuid,
+    pub device_id: Uuid,
+    pub command: String,
+    pub payload: Option<serde_json::Value>,
+    /// queued, sent, acknowledged, failed or expired
+    pub status: String,
+    pub channel: Option<String>,
+    pub response: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct DeviceConfigModel {
+    pub device_id: Uuid,
+    /// Desired version, bumped on every change
+    pub version: i32,
+    pub config: serde_json::Value,
+    /// Last version the device confirmed it applied
+    pub reported_version: Option<i32>,
+    pub reported_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct InventoryDeviceModel {
+    pub id: Uuid,
+    pub imei: String,
+    pub model: String,
+    pub serial_number: String,
+    pub claim_code: String,
+    /// Set once a client claims the unit
+    pub device_id: Option<Uuid>,
+    pub claimed_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct FirmwareModel {
+    pub id: Uuid,
+    pub version: String,
+    /// Device models the image can be installed on
+    pub models: Vec<String>,
+    /// SHA-256 of the image, hex encoded
+    pub checksum: String,
+    pub size: i64,
+    pub notes: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct OtaCampaignModel {
+    pub id: Uuid,
+    pub firmware_id: Uuid,
+    pub client_id: Uuid,
+    /// Cumulative percentages of the target devices
+    pub stages: Vec<i32>,
+    /// Index into `stages` of the stage being rolled out
+    pub current_stage: i32,
+    /// Failed share of finished updates that pauses the campaign
+    pub failure_threshold: f64,
+    /// active, paused, completed or cancelled
+    pub status: String,
+    pub paused_reason: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Only devices carrying this tag are enrolled
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct OtaUpdateModel {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub device_id: Uuid,
+    /// pending, downloading, installed or failed
+    pub status: String,
+    pub error: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Entry of the `device_models` catalog referenced by `DeviceModel.model`.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CatalogModel {
+    pub id: Uuid,
+    pub name: String,
+    pub manufacturer: String,
+    /// teltonika, gt06, nmea, mqtt or http
+    pub protocol: String,
+    pub capabilities: Vec<String>,
+    pub gps_interval_seconds: Option<i32>,
+    pub data_interval_seconds: Option<i32>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct SimCardModel {
+    pub id: Uuid,
+    pub iccid: String,
+    pub msisdn: Option<String>,
+    pub carrier: String,
+    pub data_plan: Option<String>,
+    /// Device the SIM is installed in
+    pub device_id: Option<Uuid>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct SimAssignmentModel {
+    pub id: Uuid,
+    pub sim_id: Uuid,
+    /// None once the device was deleted
+    pub device_id: Option<Uuid>,
+    /// IMEI of the device when it was assigned
+    pub imei: String,
+    pub assigned_at: DateTime<Utc>,
+    pub unassigned_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct BulkJobModel {
+    pub id: Uuid,
+    /// Client of the token that started the job
+    pub client_id: Option<Uuid>,
+    pub operation: serde_json::Value,
+    /// running, completed or failed
+    pub status: String,
+    pub total: i32,
+    /// Per-device results once completed
+    pub results: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct TagCountModel {
+    pub name: String,
+    /// Devices of the client carrying the tag
+    pub devices: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct DeviceGroupModel {
+    pub id: Uuid,
+    pub client_id: Uuid,
+    /// Enclosing group, none for top-level groups
+    pub parent_id: Option<Uuid>,
+    pub name: String,
+    pub created_at: Option<DateTime<Utc>>,
 }
@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::model::{OtaCampaignModel, OtaUpdateModel};
+
+/// Finished updates needed before the failure rate can pause a campaign.
+const MIN_FINISHED_FOR_PAUSE: i64 = 5;
+
+/// Directory holding the firmware images, `FIRMWARE_DIR` or `./firmware`.
+pub fn firmware_dir() -> PathBuf {
+    std::env::var("FIRMWARE_DIR")
+        .unwrap_or_else(|_| "firmware".to_string())
+        .into()
+}
+
+pub fn firmware_path(firmware_id: Uuid) -> PathBuf {
+    firmware_dir().join(format!("{}.bin", firmware_id))
+}
+
+/// Enrolls devices until the campaign covers the percentage of its current stage.
+///
+/// Devices are ordered by a hash of their id, so each stage extends the previous one.
+/// Devices busy with another active campaign, or without the campaign tag, are left out.
+pub async fn enroll_stage(
+    tx: &mut Transaction<'_, Postgres>,
+    campaign: &OtaCampaignModel,
+) -> Result<u64, sqlx::Error> {
+    let percentage = campaign.stages[campaign.current_stage as usize];
+
+    let result = sqlx::query!(
+        r#"
+        WITH targets AS (
+            SELECT d.id FROM devices d
+            JOIN firmwares f ON f.id = $2
+            WHERE d.client_id = $3 AND d.model = ANY(f.models)
+                AND ($5::VARCHAR IS NULL OR EXISTS (
+                    SELECT 1 FROM device_tags dt JOIN tags t ON t.id = dt.tag_id
+                    WHERE dt.device_id = d.id AND t.name = $5
+                ))
+        )
+        INSERT INTO ota_updates (campaign_id, device_id)
+        SELECT $1, t.id FROM targets t
+        WHERE NOT EXISTS (
+            SELECT 1 FROM ota_updates u
+            JOIN ota_campaigns c ON c.id = u.campaign_id
+            WHERE u.device_id = t.id
+                AND (c.id = $1 OR (c.status = 'active' AND u.status IN ('pending', 'downloading')))
+        )
+        ORDER BY md5(t.id::TEXT || $1::TEXT)
+        LIMIT GREATEST(
+            CEIL((SELECT COUNT(*) FROM targets) * $4::INTEGER / 100.0)::BIGINT
+                - (SELECT COUNT(*) FROM ota_updates WHERE campaign_id = $1),
+            0
+        )
+        "#,
+        campaign.id,
+        campaign.firmware_id,
+        campaign.client_id,
+        percentage,
+        campaign.tag
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Records a progress report from the device, `None` when the update already finished.
+///
+/// Failures pause the campaign once the failure rate exceeds its threshold, and the
+/// last stage completes it when every update has finished.
+pub async fn record_progress(
+    db: &Pool<Postgres>,
+    device_id: Uuid,
+    update_id: Uuid,
+    status: &str,
+    error: Option<&str>,
+) -> Result<Option<OtaUpdateModel>, sqlx::Error> {
+    let update = match sqlx::query_as!(
+        OtaUpdateModel,
+        r#"
+        UPDATE ota_updates SET status = $3, error = $4, updated_at = now()
+        WHERE id = $1 AND device_id = $2 AND status IN ('pending', 'downloading')
+        RETURNING *
+        "#,
+        update_id,
+        device_id,
+        status,
+        error
+    )
+    .fetch_optional(db)
+    .await?
+    {
+        Some(update) => update,
+        None => return Ok(None),
+    };
+
+    if status == "downloading" {
+        return Ok(Some(update));
+    }
+
+    let stats = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE status = 'failed') AS "failed!",
+            COUNT(*) FILTER (WHERE status IN ('installed', 'failed')) AS "finished!",
+            COUNT(*) FILTER (WHERE status IN ('pending', 'downloading')) AS "unfinished!"
+        FROM ota_updates WHERE campaign_id = $1
+        "#,
+        update.campaign_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    let campaign = sqlx::query_as!(
+        OtaCampaignModel,
+        "SELECT * FROM ota_campaigns WHERE id = $1",
+        update.campaign_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    let failure_rate = stats.failed as f64 / stats.finished.max(1) as f64;
+
+    if stats.finished >= MIN_FINISHED_FOR_PAUSE && failure_rate > campaign.failure_threshold {
+        sqlx::query!(
+            r#"
+            UPDATE ota_campaigns SET status = 'paused', paused_reason = $2, updated_at = now()
+            WHERE id = $1 AND status = 'active'
+            "#,
+            campaign.id,
+            format!(
+                "Failure rate {:.0}% exceeded the {:.0}% threshold",
+                failure_rate * 100.0,
+                campaign.failure_threshold * 100.0
+            )
+        )
+        .execute(db)
+        .await?;
+    } else if stats.unfinished == 0 && campaign.current_stage as usize + 1 == campaign.stages.len() {
+        sqlx::query!(
+            "UPDATE ota_campaigns SET status = 'completed', updated_at = now() WHERE id = $1 AND status = 'active'",
+            campaign.id
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(Some(update))
+}
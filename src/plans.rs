@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use actix_web::HttpResponse;
+use serde_json::json;
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+pub const FEATURES: [&str; 2] = ["geofences", "webhooks"];
+const RETENTION_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Devices of a client against the limit of its plan.
+pub struct DeviceQuota {
+    pub max_devices: Option<i32>,
+    pub devices: i64,
+}
+
+impl DeviceQuota {
+    pub fn allows(&self, adding: usize) -> bool {
+        self.max_devices
+            .is_none_or(|max| self.devices + adding as i64 <= max as i64)
+    }
+
+    pub fn describe(&self, adding: usize) -> String {
+        format!(
+            "The plan allows {} devices, the client has {} and {} more were asked for",
+            self.max_devices.unwrap_or_default(),
+            self.devices,
+            adding
+        )
+    }
+
+    /// 402, the client has to move to a larger plan first.
+    pub fn exceeded(&self, adding: usize) -> HttpResponse {
+        HttpResponse::PaymentRequired().json(json!({
+            "status": "error",
+            "message": self.describe(adding),
+            "max_devices": self.max_devices,
+            "devices": self.devices,
+        }))
+    }
+}
+
+/// Device quota of the client, none when the client does not exist.
+/// The client stays locked until the transaction ends so concurrent
+/// additions count each other's devices.
+pub async fn lock_device_quota(
+    tx: &mut Transaction<'_, Postgres>,
+    client_id: Uuid,
+) -> Result<Option<DeviceQuota>, sqlx::Error> {
+    let locked = sqlx::query_scalar!("SELECT id FROM clients WHERE id = $1 FOR UPDATE", client_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if locked.is_none() {
+        return Ok(None);
+    }
+
+    // Counted after the lock is held, a statement started before it would
+    // miss the devices added by the transaction it waited for
+    sqlx::query_as!(
+        DeviceQuota,
+        r#"
+        SELECT p.max_devices AS "max_devices?",
+            (SELECT COUNT(*) FROM devices WHERE client_id = c.id) AS "devices!"
+        FROM clients c
+        LEFT JOIN plans p ON p.id = c.plan_id
+        WHERE c.id = $1
+        "#,
+        client_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+}
+
+/// Features outside the ones plans know, in the order given.
+pub fn unknown_features(features: &[String]) -> Vec<&str> {
+    features
+        .iter()
+        .map(String::as_str)
+        .filter(|feature| !FEATURES.contains(feature))
+        .collect()
+}
+
+/// Deletes positions and telemetry older than the plan of their client keeps.
+pub async fn run_retention_sweep(db: Pool<Postgres>) {
+    let mut interval = tokio::time::interval(RETENTION_SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = sqlx::query!(
+            r#"
+            DELETE FROM positions
+            USING devices d, clients c, plans p
+            WHERE positions.device_id = d.id AND d.client_id = c.id AND c.plan_id = p.id
+                AND positions.recorded_at < now() - make_interval(days => p.retention_days)
+            "#
+        )
+        .execute(&db)
+        .await
+        {
+            log::error!("Position retention sweep failed: {:?}", error);
+        }
+
+        if let Err(error) = sqlx::query!(
+            r#"
+            DELETE FROM telemetry
+            USING devices d, clients c, plans p
+            WHERE telemetry.device_id = d.id AND d.client_id = c.id AND c.plan_id = p.id
+                AND telemetry.recorded_at < now() - make_interval(days => p.retention_days)
+            "#
+        )
+        .execute(&db)
+        .await
+        {
+            log::error!("Telemetry retention sweep failed: {:?}", error);
+        }
+    }
+}
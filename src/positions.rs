@@ -0,0 +1,144 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    events::{EventHub, FleetEventKind},
+    model::{DeviceModel, PositionModel},
+    trips::track_positions,
+};
+
+/// A decoded fix, as produced by any of the ingestion gateways.
+#[derive(Debug, Clone, Default)]
+pub struct NewPosition {
+    pub recorded_at: DateTime<Utc>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+    pub speed: Option<f64>,
+    pub course: Option<f64>,
+    pub satellites: Option<i32>,
+    pub ignition: Option<bool>,
+    pub io: Option<serde_json::Value>,
+}
+
+pub async fn find_device_by_imei(
+    db: &Pool<Postgres>,
+    imei: &str,
+) -> Result<Option<DeviceModel>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceModel,
+        "SELECT * FROM devices WHERE imei = $1",
+        imei
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Stores the positions of a device, advances `upload_gps` (and `upload_data`
+/// when IO data came along) and its trips, and publishes them to the live feeds.
+pub async fn store_positions(
+    db: &Pool<Postgres>,
+    events: &EventHub,
+    device: &DeviceModel,
+    positions: &[NewPosition],
+) -> Result<Vec<PositionModel>, sqlx::Error> {
+    if positions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut tx = db.begin().await?;
+    let stored = insert_positions(&mut tx, device, positions).await?;
+    tx.commit().await?;
+
+    publish_positions(events, device, &stored);
+
+    Ok(stored)
+}
+
+/// The writes of `store_positions` inside the caller's transaction, for
+/// gateways that store other data along with the positions. Publish what it
+/// returns with `publish_positions` once committed.
+pub async fn insert_positions(
+    tx: &mut Transaction<'_, Postgres>,
+    device: &DeviceModel,
+    positions: &[NewPosition],
+) -> Result<Vec<PositionModel>, sqlx::Error> {
+    let latest = match positions.iter().map(|position| position.recorded_at).max() {
+        Some(latest) => latest,
+        None => return Ok(Vec::new()),
+    };
+    let has_io = positions.iter().any(|position| position.io.is_some());
+
+    let mut stored = Vec::with_capacity(positions.len());
+
+    for position in positions {
+        let row = sqlx::query_as!(
+            PositionModel,
+            r#"
+            INSERT INTO positions
+                (device_id, recorded_at, latitude, longitude, altitude, speed, course, satellites, ignition, io)
+            VALUES
+                ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING *
+            "#,
+            device.id,
+            position.recorded_at,
+            position.latitude,
+            position.longitude,
+            position.altitude,
+            position.speed,
+            position.course,
+            position.satellites,
+            position.ignition,
+            position.io
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        stored.push(row);
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE devices
+        SET upload_gps = GREATEST(upload_gps, $1),
+            upload_data = CASE WHEN $2 THEN GREATEST(upload_data, $1) ELSE upload_data END
+        WHERE id = $3
+        "#,
+        latest,
+        has_io,
+        device.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // After the update above, which holds the device row until commit
+    track_positions(tx, device.id, &stored).await?;
+
+    Ok(stored)
+}
+
+/// Publishes committed positions to the live feeds.
+pub fn publish_positions(events: &EventHub, device: &DeviceModel, positions: &[PositionModel]) {
+    for position in positions {
+        events.publish(FleetEventKind::Position, device.client_id, device.id, position);
+    }
+}
+
+/// Advances `upload_data` for data that came without a usable fix.
+pub async fn touch_upload_data(
+    db: impl PgExecutor<'_>,
+    device_id: Uuid,
+    received_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE devices SET upload_data = GREATEST(upload_data, $1) WHERE id = $2",
+        received_at,
+        device_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
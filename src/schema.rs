@@ -1,5 +1,18 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Tells an explicit `null` (`Some(None)`) apart from a missing field (`None`).
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct CreateClientSchema {
@@ -8,6 +21,14 @@ pub struct CreateClientSchema {
 
     #[schema(example = "active")]
     pub status: String,
+
+    /// Reseller the client is sold through
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub parent_id: Option<Uuid>,
+
+    /// Subscription plan, no limits without one
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub plan_id: Option<Uuid>,
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
@@ -17,6 +38,16 @@ pub struct UpdateClientSchema {
 
     #[schema(example = "inactive")]
     pub status: Option<String>,
+
+    /// New reseller, `null` makes it a direct client
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Uuid>, example = "f47ac10b-58cc-4372-a567-0e02b2c3d479")]
+    pub parent_id: Option<Option<Uuid>>,
+
+    /// New subscription plan, `null` lifts the limits
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Uuid>, example = "f47ac10b-58cc-4372-a567-0e02b2c3d479")]
+    pub plan_id: Option<Option<Uuid>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
@@ -27,6 +58,7 @@ pub struct CreateDeviceSchema {
     #[schema(example = "123456789012345")]
     pub imei: String,
 
+    /// Name of a `device_models` catalog entry
     #[schema(example = "Model X")]
     pub model: String,
 
@@ -41,6 +73,10 @@ pub struct CreateDeviceSchema {
 
     #[schema(example = "active")]
     pub status: String,
+
+    /// Checked against the client's metadata schema when it has one
+    #[schema(example = json!({"plate": "ABC1D23", "region": "south"}))]
+    pub metadata: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
@@ -62,6 +98,10 @@ pub struct UpdateDeviceSchema {
 
     #[schema(example = "inactive")]
     pub status: Option<String>,
+
+    /// Replaces the whole metadata object
+    #[schema(example = json!({"plate": "ABC1D23", "region": "south"}))]
+    pub metadata: Option<serde_json::Value>,
 }
 
 #[derive(Deserialize, Serialize, Debug, ToSchema)]
@@ -72,3 +112,540 @@ pub struct FilterOptions {
     #[schema(example = 1)]
     pub page: Option<usize>,
 }
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ClientFilterOptions {
+    #[schema(example = 10)]
+    pub limit: Option<usize>,
+
+    #[schema(example = 1)]
+    pub page: Option<usize>,
+
+    /// Only the sub-clients of this reseller
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub parent_id: Option<Uuid>,
+
+    /// With parent_id, also the sub-clients of its sub-clients at any depth
+    #[schema(example = true)]
+    pub include_descendants: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct DeviceFilterOptions {
+    #[schema(example = 10)]
+    pub limit: Option<usize>,
+
+    #[schema(example = 1)]
+    pub page: Option<usize>,
+
+    /// Comma separated tags the devices must all carry
+    #[schema(example = "truck,south")]
+    pub tag: Option<String>,
+
+    /// JSON object the device metadata must contain
+    #[schema(example = r#"{"region":"south"}"#)]
+    pub metadata: Option<String>,
+
+    /// Only the devices of this client
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub client_id: Option<Uuid>,
+
+    /// With client_id, also the devices of its sub-clients at any depth
+    #[schema(example = true)]
+    pub include_descendants: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct DeviceTagsSchema {
+    #[schema(example = json!(["truck", "south"]))]
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UsageOptions {
+    /// Month to bill, YYYY-MM
+    #[schema(example = "2026-09")]
+    pub month: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct StreamOptions {
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub client_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct LiveOptions {
+    /// Browsers cannot set headers on a WebSocket handshake
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LiveCommandSchema {
+    Subscribe {
+        #[serde(default)]
+        device_ids: Vec<Uuid>,
+        #[serde(default)]
+        client_ids: Vec<Uuid>,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        device_ids: Vec<Uuid>,
+        #[serde(default)]
+        client_ids: Vec<Uuid>,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GpsPayloadSchema {
+    #[schema(example = "2025-07-18T12:35:56Z", format = "date-time")]
+    pub timestamp: Option<DateTime<Utc>>,
+
+    #[schema(example = -23.5505)]
+    pub latitude: f64,
+
+    #[schema(example = -46.6333)]
+    pub longitude: f64,
+
+    #[schema(example = 760.0)]
+    pub altitude: Option<f64>,
+
+    #[schema(example = 42.0)]
+    pub speed: Option<f64>,
+
+    #[schema(example = 90.0)]
+    pub course: Option<f64>,
+
+    #[schema(example = 9)]
+    pub satellites: Option<i32>,
+
+    #[schema(example = true)]
+    pub ignition: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct TelemetryPayloadSchema {
+    #[schema(example = "2025-07-18T12:34:56Z", format = "date-time")]
+    pub timestamp: Option<DateTime<Utc>>,
+
+    /// Readings by metric name, 1 to 100 letters, digits, `_`, `.` or `-`
+    #[schema(example = json!({"temperature": 21.5, "battery_voltage": 12.6}))]
+    pub metrics: HashMap<String, f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct TelemetryQueryOptions {
+    #[schema(example = "temperature")]
+    pub metric: Option<String>,
+
+    #[schema(example = "2025-07-18T00:00:00Z", format = "date-time")]
+    pub from: Option<DateTime<Utc>>,
+
+    #[schema(example = "2025-07-19T00:00:00Z", format = "date-time")]
+    pub to: Option<DateTime<Utc>>,
+
+    #[schema(example = "1h")]
+    pub bucket: Option<String>,
+
+    #[schema(example = 1000)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct TripQueryOptions {
+    /// Trips started at or after, defaults to 24h before `to`
+    #[schema(example = "2026-10-17T00:00:00-03:00", format = "date-time")]
+    pub from: Option<DateTime<Utc>>,
+
+    /// Trips started before, defaults to now
+    #[schema(example = "2026-10-18T00:00:00-03:00", format = "date-time")]
+    pub to: Option<DateTime<Utc>>,
+
+    #[schema(example = 10)]
+    pub limit: Option<usize>,
+
+    #[schema(example = 1)]
+    pub page: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreateCommandSchema {
+    /// reboot, set_interval, cut_engine, restore_engine or custom
+    #[schema(example = "set_interval")]
+    pub command: String,
+
+    #[schema(example = json!({"interval": 60}))]
+    pub payload: Option<serde_json::Value>,
+
+    /// Seconds until an undelivered command expires, 1 to 30 days, one day by default
+    #[schema(example = 3600)]
+    pub ttl_seconds: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CommandAckSchema {
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub id: Uuid,
+
+    #[serde(default = "default_true")]
+    #[schema(example = true)]
+    pub success: bool,
+
+    #[schema(example = "Interval set to 60s")]
+    pub response: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UpdateDeviceConfigSchema {
+    #[schema(example = json!({"report_interval": 60, "apn": "zap.vivo.com.br"}))]
+    pub config: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct DeviceAckSchema {
+    #[serde(default)]
+    pub commands: Vec<CommandAckSchema>,
+
+    /// Configuration version the device has applied
+    #[schema(example = 3)]
+    pub config_version: Option<i32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreateInventoryDeviceSchema {
+    #[schema(example = "123456789012345")]
+    pub imei: String,
+
+    #[schema(example = "Model X")]
+    pub model: String,
+
+    #[schema(example = "SN123456789")]
+    pub serial_number: String,
+
+    /// Printed on the box, generated when missing
+    #[schema(example = "K7QM4TZ9PA")]
+    pub claim_code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct InventoryFilterOptions {
+    #[schema(example = 10)]
+    pub limit: Option<usize>,
+
+    #[schema(example = 1)]
+    pub page: Option<usize>,
+
+    #[schema(example = false)]
+    pub claimed: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ClaimDeviceSchema {
+    #[schema(example = "K7QM-4TZ9-PA")]
+    pub claim_code: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct FirmwareUploadOptions {
+    /// Up to 50 letters, digits, `.`, `_`, `-` or `+`
+    #[schema(example = "03.28.07")]
+    pub version: String,
+
+    /// Comma separated device models
+    #[schema(example = "FMB920,FMB120")]
+    pub models: String,
+
+    /// Expected SHA-256 of the image, checked after the upload
+    pub checksum: Option<String>,
+
+    pub notes: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreateCampaignSchema {
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub firmware_id: Uuid,
+
+    /// Client whose devices the campaign updates, a campaign targets one client
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub client_id: Uuid,
+
+    /// Cumulative percentages ending at 100, defaults to a single stage
+    #[schema(example = json!([5, 25, 100]))]
+    pub stages: Option<Vec<i32>>,
+
+    /// Failed share of finished updates that pauses the campaign, defaults to 0.2
+    #[schema(example = 0.2)]
+    pub failure_threshold: Option<f64>,
+
+    /// Only enroll the client's devices carrying this tag
+    #[schema(example = "pilot")]
+    pub tag: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct OtaProgressSchema {
+    /// downloading, installed or failed
+    #[schema(example = "installed")]
+    pub status: String,
+
+    #[schema(example = "Checksum mismatch")]
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UpdateCampaignSchema {
+    /// active to resume, paused or cancelled
+    #[schema(example = "paused")]
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CreateCatalogModelSchema {
+    #[schema(example = "FMB920")]
+    pub name: String,
+
+    #[schema(example = "Teltonika")]
+    pub manufacturer: String,
+
+    /// teltonika, gt06, nmea, mqtt, http or unknown
+    #[schema(example = "teltonika")]
+    pub protocol: String,
+
+    #[schema(example = json!(["gps", "ignition", "engine_cut"]))]
+    pub capabilities: Option<Vec<String>>,
+
+    #[schema(example = 30)]
+    pub gps_interval_seconds: Option<i32>,
+
+    #[schema(example = 300)]
+    pub data_interval_seconds: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UpdateCatalogModelSchema {
+    #[schema(example = "FMB920")]
+    pub name: Option<String>,
+
+    #[schema(example = "Teltonika")]
+    pub manufacturer: Option<String>,
+
+    #[schema(example = "teltonika")]
+    pub protocol: Option<String>,
+
+    #[schema(example = json!(["gps", "ignition"]))]
+    pub capabilities: Option<Vec<String>>,
+
+    #[schema(example = 60)]
+    pub gps_interval_seconds: Option<i32>,
+
+    #[schema(example = 600)]
+    pub data_interval_seconds: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CreatePlanSchema {
+    #[schema(example = "Fleet 100")]
+    pub name: String,
+
+    /// Devices a client may have, no limit when omitted
+    #[schema(example = 100)]
+    pub max_devices: Option<i32>,
+
+    /// Days positions and telemetry are kept
+    #[schema(example = 90)]
+    pub retention_days: i32,
+
+    /// geofences and webhooks
+    #[schema(example = json!(["geofences"]))]
+    pub features: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UpdatePlanSchema {
+    #[schema(example = "Fleet 250")]
+    pub name: Option<String>,
+
+    /// `null` removes the device limit
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>, example = 250)]
+    pub max_devices: Option<Option<i32>>,
+
+    #[schema(example = 180)]
+    pub retention_days: Option<i32>,
+
+    #[schema(example = json!(["geofences", "webhooks"]))]
+    pub features: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct CreateSimCardSchema {
+    #[schema(example = "8955031234567890129")]
+    pub iccid: String,
+
+    #[schema(example = "5511987654321")]
+    pub msisdn: Option<String>,
+
+    #[schema(example = "Vivo")]
+    pub carrier: String,
+
+    #[schema(example = "M2M 50MB")]
+    pub data_plan: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UpdateSimCardSchema {
+    #[schema(example = "5511987654321")]
+    pub msisdn: Option<String>,
+
+    #[schema(example = "Claro")]
+    pub carrier: Option<String>,
+
+    #[schema(example = "M2M 100MB")]
+    pub data_plan: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AssignSimSchema {
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub device_id: Uuid,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ImportOptions {
+    /// Validate and report without creating anything
+    #[schema(example = true)]
+    pub dry_run: Option<bool>,
+}
+
+/// One CSV row of a device import.
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ImportDeviceRow {
+    #[schema(example = "356307042441013")]
+    pub imei: String,
+
+    #[schema(example = "FMB920")]
+    pub model: String,
+
+    #[schema(example = "SN123456789")]
+    pub serial_number: String,
+
+    #[schema(example = "active")]
+    pub status: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct ExportOptions {
+    /// Page size, every row when omitted
+    #[schema(example = 1000)]
+    pub limit: Option<usize>,
+
+    #[schema(example = 1)]
+    pub page: Option<usize>,
+
+    /// Comma separated columns, in output order
+    #[schema(example = "imei,model,status")]
+    pub fields: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct BulkDeviceSchema {
+    /// Devices to act on, or use `filter`
+    pub device_ids: Option<Vec<Uuid>>,
+
+    pub filter: Option<BulkDeviceFilter>,
+
+    pub operation: BulkOperationSchema,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct BulkDeviceFilter {
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub client_id: Option<Uuid>,
+
+    #[schema(example = "active")]
+    pub status: Option<String>,
+
+    #[schema(example = "FMB920")]
+    pub model: Option<String>,
+
+    /// Devices of the group and its subgroups
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub group_id: Option<Uuid>,
+
+    /// Tags the devices must all carry
+    #[schema(example = json!(["truck"]))]
+    pub tags: Option<Vec<String>>,
+
+    /// Object the device metadata must contain
+    #[schema(example = json!({"region": "south"}))]
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum BulkOperationSchema {
+    SetStatus {
+        #[schema(example = "suspended")]
+        status: String,
+    },
+    /// Moves the devices to another client
+    Transfer {
+        #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+        client_id: Uuid,
+    },
+    AddTag {
+        #[schema(example = "truck")]
+        tag: String,
+    },
+    RemoveTag {
+        #[schema(example = "truck")]
+        tag: String,
+    },
+    Delete,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct CreateGroupSchema {
+    #[schema(example = "Trucks South Region")]
+    pub name: String,
+
+    /// Group to nest the new one in
+    #[schema(example = "f47ac10b-58cc-4372-a567-0e02b2c3d479", format = "uuid")]
+    pub parent_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct UpdateGroupSchema {
+    #[schema(example = "Trucks North Region")]
+    pub name: Option<String>,
+
+    /// New enclosing group, `null` makes it a top-level group
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<Uuid>, example = "f47ac10b-58cc-4372-a567-0e02b2c3d479")]
+    pub parent_id: Option<Option<Uuid>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GroupMembersSchema {
+    #[schema(example = json!(["f47ac10b-58cc-4372-a567-0e02b2c3d479"]))]
+    pub device_ids: Vec<Uuid>,
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
+pub struct GroupDevicesOptions {
+    #[schema(example = 10)]
+    pub limit: Option<usize>,
+
+    #[schema(example = 1)]
+    pub page: Option<usize>,
+
+    /// Include the devices of subgroups, true by default
+    #[schema(example = true)]
+    pub recursive: Option<bool>,
+}
@@ -1,40 +1,73 @@
 use actix_web::{
-    web::{Json, Path, Data, Query},
+    web::{Json, Path, Data, Query, ReqData},
     get, post, patch, delete, HttpResponse, Responder,
 };
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
-    schema::{CreateClientSchema, FilterOptions, UpdateClientSchema},
+    schema::{ClientFilterOptions, CreateClientSchema, UpdateClientSchema},
     model::ClientModel,
-    AppState,
+    AppState, TokenClaims,
 };
 #[allow(unused_imports)]
 use utoipa::ToSchema;
 
+fn client_forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "status": "error",
+        "message": "Client not accessible with this token"
+    }))
+}
+
+fn operator_only(message: &str) -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "status": "error",
+        "message": message
+    }))
+}
+
 #[utoipa::path(
     request_body = CreateClientSchema,
     responses(
-        (status = 200, description = "Create a new client.", body = ClientModel),
+        (status = 200, description = "Create a new client, optionally as a sub-client of a reseller. Clients created by a reseller get the reseller's plan.", body = ClientModel),
+        (status = 403, description = "Only operators create direct clients or choose the plan, resellers create below the clients they reach."),
+        (status = 404, description = "Parent client or plan not found."),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Clientes"
 )]
-#[post("/clients")]
+#[post("/clients", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn create_client(
     body: Json<CreateClientSchema>,
+    claims: ReqData<TokenClaims>,
     data: Data<AppState>
 ) -> impl Responder {
+    if !claims.is_operator() {
+        match body.parent_id {
+            Some(parent_id) if claims.can_access_client(parent_id) => (),
+            Some(_) => return client_forbidden(),
+            None => return operator_only("Only operators create direct clients"),
+        }
+        if body.plan_id.is_some() {
+            return operator_only("Only operators choose the plan of a client");
+        }
+    }
+
+    // Without a plan a client has no limits, so a reseller's clients take its plan
     match sqlx::query_as!(
         ClientModel,
         r#"
-        INSERT INTO clients (name, status)
-        VALUES ($1, $2)
+        INSERT INTO clients (name, status, parent_id, plan_id)
+        VALUES ($1, $2, $3, CASE WHEN $5 THEN $4 ELSE (SELECT plan_id FROM clients WHERE id = $3) END)
         RETURNING *
         "#,
         body.name,
-        body.status
+        body.status,
+        body.parent_id,
+        body.plan_id,
+        claims.is_operator()
     )
     .fetch_one(&data.db)
     .await {
@@ -42,6 +75,12 @@ pub async fn create_client(
             "status": "success",
             "client": client,
         })),
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23503") => {
+            HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Parent client or plan not found"
+            }))
+        }
         Err(error) => HttpResponse::InternalServerError().json(json!({
             "status": "error",
             "message": format!("{:?}", error)
@@ -50,25 +89,63 @@ pub async fn create_client(
 }
 
 #[utoipa::path(
+    params(
+        ("limit" = Option<usize>, Query, description = "Page size"),
+        ("page" = Option<usize>, Query, description = "Page number"),
+        ("parent_id" = Option<Uuid>, Query, description = "Only the sub-clients of this reseller"),
+        ("include_descendants" = Option<bool>, Query, description = "With parent_id, also the sub-clients of its sub-clients at any depth")
+    ),
     responses(
-        (status = 200, description = "Get all clients.", body = [ClientModel]),
+        (status = 200, description = "Get all clients the token reaches.", body = [ClientModel]),
+        (status = 400, description = "include_descendants without parent_id"),
+        (status = 403, description = "Parent client not accessible with this token"),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Clientes"
 )]
-#[get("/clients")]
+#[get("/clients", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn get_all_clients(
-    opts: Query<FilterOptions>,
+    opts: Query<ClientFilterOptions>,
+    claims: ReqData<TokenClaims>,
     data: Data<AppState>
 ) -> impl Responder {
     let limit = opts.limit.unwrap_or(10);
     let offset = (opts.page.unwrap_or(1) - 1) * limit;
+    let include_descendants = opts.include_descendants.unwrap_or(false);
+
+    if include_descendants && opts.parent_id.is_none() {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "include_descendants needs a parent_id"
+        }));
+    }
+
+    if let Some(parent_id) = opts.parent_id {
+        if !claims.can_access_client(parent_id) {
+            return client_forbidden();
+        }
+    }
 
     match sqlx::query_as!(
         ClientModel,
-        "SELECT * FROM clients ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+        r#"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM clients WHERE parent_id = $1
+            UNION
+            SELECT c.id FROM clients c JOIN subtree s ON c.parent_id = s.id AND $2
+        )
+        SELECT * FROM clients
+        WHERE ($1::UUID IS NULL OR id IN (SELECT id FROM subtree))
+            AND ($5::UUID[] IS NULL OR id = ANY($5))
+        ORDER BY created_at DESC
+        LIMIT $3 OFFSET $4
+        "#,
+        opts.parent_id,
+        include_descendants,
         limit as i32,
-        offset as i32
+        offset as i32,
+        claims.client_scope()
     )
     .fetch_all(&data.db)
     .await {
@@ -87,18 +164,25 @@ pub async fn get_all_clients(
 #[utoipa::path(
     responses(
         (status = 200, description = "Get client by ID.", body = ClientModel),
+        (status = 403, description = "Client not accessible with this token"),
         (status = 404, description = "Client not found."),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Clientes"
 )]
-#[get("/clients/{id}")]
+#[get("/clients/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn get_client_by_id(
     path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
     data: Data<AppState>
 ) -> impl Responder {
     let client_id = path.into_inner();
 
+    if !claims.can_access_client(client_id) {
+        return client_forbidden();
+    }
+
     match sqlx::query_as!(
         ClientModel,
         "SELECT * FROM clients WHERE id = $1",
@@ -120,20 +204,38 @@ pub async fn get_client_by_id(
 #[utoipa::path(
     request_body = UpdateClientSchema,
     responses(
-        (status = 200, description = "Update client by ID.", body = ClientModel),
-        (status = 404, description = "Client not found."),
+        (status = 200, description = "Update client by ID, `parent_id: null` makes it a direct client and `plan_id: null` lifts its limits.", body = ClientModel),
+        (status = 400, description = "Client moved below itself or one of its sub-clients."),
+        (status = 403, description = "Client or new parent not accessible with this token, or a change of plan or to a direct client without an operator token."),
+        (status = 404, description = "Client, parent client or plan not found."),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Clientes"
 )]
-#[patch("/clients/{id}")]
+#[patch("/clients/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn update_client_by_id(
     path: Path<Uuid>,
     body: Json<UpdateClientSchema>,
+    claims: ReqData<TokenClaims>,
     data: Data<AppState>
 ) -> impl Responder {
     let client_id = path.into_inner();
 
+    if !claims.can_access_client(client_id) {
+        return client_forbidden();
+    }
+    if !claims.is_operator() {
+        if body.plan_id.is_some() {
+            return operator_only("Only operators change the plan of a client");
+        }
+        match body.parent_id {
+            Some(Some(parent_id)) if !claims.can_access_client(parent_id) => return client_forbidden(),
+            Some(None) => return operator_only("Only operators make a client direct"),
+            _ => (),
+        }
+    }
+
     let existing_client = sqlx::query_as!(
         ClientModel,
         "SELECT * FROM clients WHERE id = $1",
@@ -151,16 +253,86 @@ pub async fn update_client_by_id(
 
     let client = existing_client.unwrap();
 
-    match sqlx::query_as!(
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if let Some(Some(parent_id)) = body.parent_id {
+        // Moves are serialized so two of them cannot close a cycle together
+        if let Err(error) = sqlx::query!("SELECT pg_advisory_xact_lock(hashtext('clients.parent_id'))")
+            .execute(&mut tx)
+            .await
+        {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+
+        match sqlx::query_scalar!(
+            r#"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM clients WHERE id = $1
+                UNION
+                SELECT c.id FROM clients c JOIN subtree s ON c.parent_id = s.id
+            )
+            SELECT EXISTS (SELECT 1 FROM subtree WHERE id = $2) AS "cycle!"
+            "#,
+            client_id,
+            parent_id
+        )
+        .fetch_one(&mut tx)
+        .await {
+            Ok(true) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": "A client cannot be moved below itself or its sub-clients"
+                }));
+            }
+            Ok(false) => (),
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        }
+    }
+
+    let updated_client = match sqlx::query_as!(
         ClientModel,
-        "UPDATE clients SET name = $1, status = $2 WHERE id = $3 RETURNING *",
+        "UPDATE clients SET name = $1, status = $2, parent_id = $3, plan_id = $4 WHERE id = $5 RETURNING *",
         body.name.clone().unwrap_or(client.name),
         body.status.clone().unwrap_or(client.status),
+        body.parent_id.unwrap_or(client.parent_id),
+        body.plan_id.unwrap_or(client.plan_id),
         client_id
     )
-    .fetch_one(&data.db)
+    .fetch_one(&mut tx)
     .await {
-        Ok(updated_client) => HttpResponse::Ok().json(json!({
+        Ok(updated_client) => updated_client,
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23503") => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Parent client or plan not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
             "status": "success",
             "client": updated_client,
         })),
@@ -174,18 +346,26 @@ pub async fn update_client_by_id(
 #[utoipa::path(
     responses(
         (status = 204, description = "Delete client by ID."),
+        (status = 403, description = "Client not accessible with this token"),
         (status = 404, description = "Client not found."),
+        (status = 409, description = "The client still has sub-clients."),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Clientes"
 )]
-#[delete("/clients/{id}")]
+#[delete("/clients/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn delete_client_by_id(
     path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
     data: Data<AppState>
 ) -> impl Responder {
     let client_id = path.into_inner();
 
+    if !claims.can_access_client(client_id) {
+        return client_forbidden();
+    }
+
     match sqlx::query!(
         "DELETE FROM clients WHERE id = $1",
         client_id
@@ -193,6 +373,12 @@ pub async fn delete_client_by_id(
     .execute(&data.db)
     .await {
         Ok(_) => HttpResponse::NoContent().finish(),
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23503") => {
+            HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "Move or delete the sub-clients first"
+            }))
+        }
         Err(error) => HttpResponse::InternalServerError().json(json!({
             "status": "error",
             "message": format!("{:?}", error)
@@ -0,0 +1,253 @@
+use actix_web::{
+    web::{Json, Path, Data, Query, ReqData},
+    get, post, HttpResponse, Responder,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    commands::COMMAND_NAMES,
+    schema::{CreateCommandSchema, FilterOptions},
+    model::{CommandModel, DeviceModel},
+    AppState, TokenClaims,
+};
+
+const DEFAULT_COMMAND_TTL_SECONDS: i64 = 24 * 60 * 60;
+/// Commands still undelivered after this long are of no use to anyone.
+const MAX_COMMAND_TTL_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+fn client_not_accessible() -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "status": "error",
+        "message": "Client not accessible with this token"
+    }))
+}
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Device id")
+    ),
+    request_body = CreateCommandSchema,
+    responses(
+        (status = 200, description = "Queue a command for the device.", body = CommandModel),
+        (status = 400, description = "Invalid command or ttl_seconds not between 1 and 30 days"),
+        (status = 403, description = "Group token, or device of a client not accessible with this token"),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Comandos"
+)]
+#[post("/devices/{id}/commands", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn create_command(
+    path: Path<Uuid>,
+    body: Json<CreateCommandSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if claims.group_id.is_some() {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Group tokens are read-only"
+        }));
+    }
+
+    let device_id = path.into_inner();
+
+    if !COMMAND_NAMES.contains(&body.command.as_str()) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("Invalid command, use one of {}", COMMAND_NAMES.join(", "))
+        }));
+    }
+
+    let has_text = body
+        .payload
+        .as_ref()
+        .and_then(|payload| payload.get("text"))
+        .is_some_and(|text| text.is_string());
+    if body.command == "custom" && !has_text {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Custom commands need a payload.text"
+        }));
+    }
+
+    let ttl = body.ttl_seconds.unwrap_or(DEFAULT_COMMAND_TTL_SECONDS);
+    if !(1..=MAX_COMMAND_TTL_SECONDS).contains(&ttl) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("ttl_seconds must be between 1 and {}", MAX_COMMAND_TTL_SECONDS)
+        }));
+    }
+
+    let device = match sqlx::query_as!(
+        DeviceModel,
+        "SELECT * FROM devices WHERE id = $1",
+        device_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(device) => device,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if !claims.can_access_client(device.client_id) {
+        return client_not_accessible();
+    }
+
+    match sqlx::query_as!(
+        CommandModel,
+        r#"
+        INSERT INTO commands (device_id, command, payload, expires_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#,
+        device_id,
+        body.command,
+        body.payload,
+        Utc::now() + Duration::seconds(ttl)
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(command) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "command": command,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Device id")
+    ),
+    responses(
+        (status = 200, description = "List the commands of a device, newest first.", body = [CommandModel]),
+        (status = 400, description = "limit or page below 1"),
+        (status = 403, description = "Device of a client not accessible with this token"),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Comandos"
+)]
+#[get("/devices/{id}/commands", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_device_commands(
+    path: Path<Uuid>,
+    opts: Query<FilterOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let device_id = path.into_inner();
+    let limit = opts.limit.unwrap_or(10);
+    let page = opts.page.unwrap_or(1);
+    if limit == 0 || page == 0 {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "limit and page must be at least 1"
+        }));
+    }
+    let offset = (page - 1).saturating_mul(limit);
+
+    match sqlx::query_scalar!("SELECT client_id FROM devices WHERE id = $1", device_id)
+        .fetch_optional(&data.db)
+        .await
+    {
+        Ok(Some(client_id)) if claims.can_access_client(client_id) => (),
+        Ok(Some(_)) => return client_not_accessible(),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Device not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match sqlx::query_as!(
+        CommandModel,
+        "SELECT * FROM commands WHERE device_id = $1 ORDER BY created_at DESC LIMIT $2 OFFSET $3",
+        device_id,
+        limit.min(i64::MAX as usize) as i64,
+        offset.min(i64::MAX as usize) as i64
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(commands) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": commands.len(),
+            "commands": commands,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get command status by ID.", body = CommandModel),
+        (status = 403, description = "Device of a client not accessible with this token"),
+        (status = 404, description = "Command not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Comandos"
+)]
+#[get("/commands/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_command_by_id(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let command_id = path.into_inner();
+
+    let command = match sqlx::query_as!(
+        CommandModel,
+        "SELECT * FROM commands WHERE id = $1",
+        command_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(command) => command,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match sqlx::query_scalar!("SELECT client_id FROM devices WHERE id = $1", command.device_id)
+        .fetch_one(&data.db)
+        .await
+    {
+        Ok(client_id) if claims.can_access_client(client_id) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "command": command,
+        })),
+        Ok(_) => client_not_accessible(),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
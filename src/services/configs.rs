@@ -0,0 +1,165 @@
+use actix_web::{
+    web::{Json, Path, Data, ReqData},
+    get, put, HttpResponse, Responder,
+};
+use serde_json::json;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    schema::UpdateDeviceConfigSchema,
+    model::{DeviceConfigModel, DeviceModel},
+    AppState, TokenClaims,
+};
+
+fn client_not_accessible() -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "status": "error",
+        "message": "Client not accessible with this token"
+    }))
+}
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Device id")
+    ),
+    request_body = UpdateDeviceConfigSchema,
+    responses(
+        (status = 200, description = "Set the desired configuration, bumping its version.", body = DeviceConfigModel),
+        (status = 400, description = "Configuration is not a JSON object"),
+        (status = 403, description = "Group token, or device of a client not accessible with this token"),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Comandos"
+)]
+#[put("/devices/{id}/config", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn update_device_config(
+    path: Path<Uuid>,
+    body: Json<UpdateDeviceConfigSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if claims.group_id.is_some() {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Group tokens are read-only"
+        }));
+    }
+
+    let device_id = path.into_inner();
+
+    if !body.config.is_object() {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Configuration must be a JSON object"
+        }));
+    }
+
+    let device = match sqlx::query_as!(
+        DeviceModel,
+        "SELECT * FROM devices WHERE id = $1",
+        device_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(device) => device,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if !claims.can_access_client(device.client_id) {
+        return client_not_accessible();
+    }
+
+    match sqlx::query_as!(
+        DeviceConfigModel,
+        r#"
+        INSERT INTO device_configs (device_id, config)
+        VALUES ($1, $2)
+        ON CONFLICT (device_id) DO UPDATE
+        SET config = EXCLUDED.config,
+            version = device_configs.version + 1,
+            updated_at = now()
+        RETURNING *
+        "#,
+        device_id,
+        body.config
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(config) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "config": config,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Device id")
+    ),
+    responses(
+        (status = 200, description = "Desired configuration and the version the device reported.", body = DeviceConfigModel),
+        (status = 403, description = "Device of a client not accessible with this token"),
+        (status = 404, description = "Device not found or no configuration set for it"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Comandos"
+)]
+#[get("/devices/{id}/config", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_device_config(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let device_id = path.into_inner();
+
+    match sqlx::query_scalar!("SELECT client_id FROM devices WHERE id = $1", device_id)
+        .fetch_optional(&data.db)
+        .await
+    {
+        Ok(Some(client_id)) if claims.can_access_client(client_id) => (),
+        Ok(Some(_)) => return client_not_accessible(),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Device not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match sqlx::query_as!(
+        DeviceConfigModel,
+        "SELECT * FROM device_configs WHERE device_id = $1",
+        device_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(config) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "config": config,
+        })),
+        Err(error) => HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::{
+    web::{Json, Path, Data, ReqData},
+    get, post, HttpResponse, Responder,
+};
+use serde_json::{json, Value};
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    bulk::{self, BulkError, MAX_SYNC_DEVICES},
+    schema::{BulkDeviceSchema, BulkOperationSchema},
+    model::{BulkJobModel, ClientModel},
+    services::find_group,
+    tenant,
+    validation::{normalize_tag, MAX_TAG_LENGTH},
+    AppState, TokenClaims,
+};
+
+#[utoipa::path(
+    request_body = BulkDeviceSchema,
+    responses(
+        (status = 200, description = "Apply the operation to every device in one transaction, with the outcome per device."),
+        (status = 202, description = "More devices than answered in the request, the operation runs as a job.", body = BulkJobModel),
+        (status = 400, description = "Neither or both of device_ids and filter, empty filter, status or tag, or unknown devices and nothing applied"),
+        (status = 402, description = "The transfer would take the target client past the device limit of its plan, nothing applied"),
+        (status = 403, description = "Client or group not accessible with this token, or a read-only group token"),
+        (status = 404, description = "Transfer target client or filter group not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Dispositivos"
+)]
+#[post("/devices/bulk", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn bulk_devices(
+    body: Json<BulkDeviceSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if claims.group_id.is_some() {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Group tokens are read-only"
+        }));
+    }
+
+    let mut body = body.into_inner();
+
+    match &mut body.operation {
+        BulkOperationSchema::SetStatus { status } if status.trim().is_empty() => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": "status is required"
+            }));
+        }
+        BulkOperationSchema::AddTag { tag } | BulkOperationSchema::RemoveTag { tag } => {
+            match normalize_tag(tag) {
+                Some(normalized) => *tag = normalized,
+                None => {
+                    return HttpResponse::BadRequest().json(json!({
+                        "status": "error",
+                        "message": format!("Tags must have between 1 and {} characters", MAX_TAG_LENGTH)
+                    }));
+                }
+            }
+        }
+        BulkOperationSchema::Transfer { client_id } => {
+            if !claims.can_access_client(*client_id) {
+                return HttpResponse::Forbidden().json(json!({
+                    "status": "error",
+                    "message": "Client not accessible with this token"
+                }));
+            }
+
+            if let Err(error) = sqlx::query_as!(
+                ClientModel,
+                "SELECT * FROM clients WHERE id = $1",
+                *client_id
+            )
+            .fetch_one(&data.db)
+            .await
+            {
+                return HttpResponse::NotFound().json(json!({
+                    "status": "not found",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        }
+        _ => (),
+    }
+
+    let device_ids = match (body.device_ids, body.filter) {
+        (Some(ids), None) => {
+            // Results follow the order the ids were sent in
+            let mut seen = HashSet::new();
+            let ids: Vec<Uuid> = ids.into_iter().filter(|id| seen.insert(*id)).collect();
+
+            let owners: HashMap<Uuid, Uuid> = match sqlx::query!(
+                "SELECT id, client_id FROM devices WHERE id = ANY($1)",
+                &ids
+            )
+            .fetch_all(&data.db)
+            .await {
+                Ok(rows) => rows.into_iter().map(|row| (row.id, row.client_id)).collect(),
+                Err(error) => {
+                    return HttpResponse::InternalServerError().json(json!({
+                        "status": "error",
+                        "message": format!("{:?}", error)
+                    }));
+                }
+            };
+
+            // Devices of other clients are reported like missing ones
+            let missing: Vec<Value> = ids
+                .iter()
+                .filter(|id| !owners.get(id).is_some_and(|owner| claims.can_access_client(*owner)))
+                .map(|id| json!({ "device_id": id, "status": "not found" }))
+                .collect();
+            if !missing.is_empty() {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": format!("{} of {} devices not found, nothing applied", missing.len(), ids.len()),
+                    "results": missing,
+                }));
+            }
+
+            ids
+        }
+        (None, Some(filter)) => {
+            let tags: Option<Vec<String>> = filter
+                .tags
+                .map(|tags| tags.iter().filter_map(|tag| normalize_tag(tag)).collect())
+                .filter(|tags: &Vec<String>| !tags.is_empty());
+            if filter.metadata.as_ref().is_some_and(|metadata| !metadata.is_object()) {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": "The metadata filter must be a JSON object"
+                }));
+            }
+            if filter.client_id.is_none()
+                && filter.status.is_none()
+                && filter.model.is_none()
+                && filter.group_id.is_none()
+                && tags.is_none()
+                && filter.metadata.is_none()
+            {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": "The filter needs at least one of client_id, status, model, group_id, tags or metadata"
+                }));
+            }
+            if let Some(group_id) = filter.group_id {
+                match find_group(&data.db, group_id).await {
+                    Ok(Some(group)) if claims.can_access_client(group.client_id) => (),
+                    Ok(Some(_)) => {
+                        return HttpResponse::Forbidden().json(json!({
+                            "status": "error",
+                            "message": "Group not accessible with this token"
+                        }));
+                    }
+                    Ok(None) => {
+                        return HttpResponse::NotFound().json(json!({
+                            "status": "not found",
+                            "message": "Group not found"
+                        }));
+                    }
+                    Err(error) => {
+                        return HttpResponse::InternalServerError().json(json!({
+                            "status": "error",
+                            "message": format!("{:?}", error)
+                        }));
+                    }
+                }
+            }
+            if let Some(client_id) = filter.client_id {
+                if !claims.can_access_client(client_id) {
+                    return HttpResponse::Forbidden().json(json!({
+                        "status": "error",
+                        "message": "Client not accessible with this token"
+                    }));
+                }
+            }
+
+            match sqlx::query_scalar!(
+                r#"
+                SELECT id FROM devices
+                WHERE ($1::UUID[] IS NULL OR client_id = ANY($1))
+                    AND ($2::UUID IS NULL OR client_id = $2)
+                    AND ($3::VARCHAR IS NULL OR status = $3)
+                    AND ($4::VARCHAR IS NULL OR model = $4)
+                    AND ($5::VARCHAR[] IS NULL OR (
+                        SELECT COUNT(*) FROM device_tags dt JOIN tags t ON t.id = dt.tag_id
+                        WHERE dt.device_id = devices.id AND t.name = ANY($5)
+                    ) = cardinality($5))
+                    AND ($6::JSONB IS NULL OR metadata @> $6)
+                    AND ($7::UUID IS NULL OR id IN (
+                        WITH RECURSIVE subtree AS (
+                            SELECT $7::UUID AS id
+                            UNION
+                            SELECT g.id FROM device_groups g JOIN subtree s ON g.parent_id = s.id
+                        )
+                        SELECT m.device_id FROM device_group_members m JOIN subtree s ON s.id = m.group_id
+                    ))
+                ORDER BY created_at
+                "#,
+                claims.client_scope(),
+                filter.client_id,
+                filter.status,
+                filter.model,
+                tags.as_deref(),
+                filter.metadata,
+                filter.group_id
+            )
+            .fetch_all(&data.db)
+            .await {
+                Ok(ids) => ids,
+                Err(error) => {
+                    return HttpResponse::InternalServerError().json(json!({
+                        "status": "error",
+                        "message": format!("{:?}", error)
+                    }));
+                }
+            }
+        }
+        _ => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": "Send either device_ids or filter"
+            }));
+        }
+    };
+
+    if device_ids.len() > MAX_SYNC_DEVICES {
+        let job = match sqlx::query_as!(
+            BulkJobModel,
+            "INSERT INTO bulk_jobs (client_id, operation, total) VALUES ($1, $2, $3) RETURNING *",
+            claims.client_id,
+            json!(body.operation),
+            device_ids.len() as i32
+        )
+        .fetch_one(&data.db)
+        .await {
+            Ok(job) => job,
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        };
+
+        actix_web::rt::spawn(tenant::inherit(bulk::run_job(
+            data.db.clone(),
+            data.events.clone(),
+            job.id,
+            device_ids,
+            body.operation,
+        )));
+
+        return HttpResponse::Accepted().json(json!({
+            "status": "accepted",
+            "job": job,
+        }));
+    }
+
+    match bulk::apply(&data.db, &data.events, &device_ids, &body.operation).await {
+        Ok(results) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": results.len(),
+            "results": results,
+        })),
+        Err(BulkError::QuotaExceeded { quota, adding }) => quota.exceeded(adding),
+        Err(BulkError::Database(error)) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Job returned by POST /devices/bulk")
+    ),
+    responses(
+        (status = 200, description = "Get a bulk job with its per-device results once completed.", body = BulkJobModel),
+        (status = 404, description = "Job not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Dispositivos"
+)]
+#[get("/devices/bulk/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_bulk_job(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let job_id = path.into_inner();
+
+    // Group tokens are read-only, no job is theirs
+    if claims.group_id.is_some() {
+        return HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": "Bulk job not found"
+        }));
+    }
+
+    // Client tokens only see the jobs they started
+    match sqlx::query_as!(
+        BulkJobModel,
+        "SELECT * FROM bulk_jobs WHERE id = $1 AND ($2::UUID IS NULL OR client_id = $2)",
+        job_id,
+        claims.client_id
+    )
+    .fetch_optional(&data.db)
+    .await {
+        Ok(Some(job)) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "job": job,
+        })),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": "Bulk job not found"
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
@@ -0,0 +1,362 @@
+use actix_web::{
+    web::{Bytes, Path, Data},
+    get, post, HttpRequest, HttpResponse, Responder,
+};
+use chrono::Utc;
+use futures_util::stream;
+use serde_json::json;
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    commands::{complete, drain_pending, DeliveryChannel},
+    device_auth::authenticate_device,
+    ota::{firmware_path, record_progress},
+    schema::{DeviceAckSchema, OtaProgressSchema},
+    model::{CommandModel, DeviceConfigModel, OtaUpdateModel},
+    positions::touch_upload_data,
+    AppState,
+};
+
+/// Commands handed out per poll, the rest wait for the next one.
+const POLL_BATCH: i64 = 10;
+const FIRMWARE_CHUNK_SIZE: usize = 64 * 1024;
+
+#[utoipa::path(
+    params(
+        ("imei" = String, Path, description = "IMEI of the polling device"),
+        ("X-Device-Timestamp" = i64, Header, description = "Unix time the request was signed at"),
+        ("X-Device-Signature" = String, Header, description = "Hex HMAC-SHA256 of `{method}\\n{path}\\n{imei}\\n{timestamp}\\n{body}` keyed with SHA-256 of the device secret")
+    ),
+    responses(
+        (status = 200, description = "Commands to run, the desired configuration version and a firmware update to install. Also records a heartbeat.", body = [CommandModel]),
+        (status = 401, description = "Invalid device signature or replayed request"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    tag = "Gateway HTTP"
+)]
+#[get("/device-gateway/{imei}/pending")]
+pub async fn get_pending_work(
+    req: HttpRequest,
+    path: Path<String>,
+    data: Data<AppState>
+) -> impl Responder {
+    let imei = path.into_inner();
+
+    let device = match authenticate_device(&req, &data.db, &imei, &[]).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    if let Err(error) = touch_upload_data(&data.db, device.id, Utc::now()).await {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    let config = match sqlx::query_as!(
+        DeviceConfigModel,
+        "SELECT * FROM device_configs WHERE device_id = $1",
+        device.id
+    )
+    .fetch_optional(&data.db)
+    .await {
+        Ok(config) => config,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    // Updates of paused or cancelled campaigns wait
+    let firmware = match sqlx::query!(
+        r#"
+        SELECT u.id, f.version, f.checksum, f.size
+        FROM ota_updates u
+        JOIN ota_campaigns c ON c.id = u.campaign_id
+        JOIN firmwares f ON f.id = c.firmware_id
+        WHERE u.device_id = $1 AND u.status IN ('pending', 'downloading') AND c.status = 'active'
+        ORDER BY u.created_at
+        LIMIT 1
+        "#,
+        device.id
+    )
+    .fetch_optional(&data.db)
+    .await {
+        Ok(firmware) => firmware.map(|firmware| json!({
+            "update_id": firmware.id,
+            "version": firmware.version,
+            "checksum": firmware.checksum,
+            "size": firmware.size,
+            "url": format!("/api/device-gateway/{}/firmware/{}", imei, firmware.id),
+        })),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match drain_pending(&data.db, device.id, DeliveryChannel::Http, POLL_BATCH).await {
+        Ok(commands) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "config_version": config.as_ref().map(|config| config.version),
+            // Only sent while the device is behind
+            "config": config
+                .filter(|config| config.reported_version != Some(config.version))
+                .map(|config| config.config),
+            "firmware": firmware,
+            "result": commands.len(),
+            "commands": commands,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("imei" = String, Path, description = "IMEI of the polling device"),
+        ("X-Device-Timestamp" = i64, Header, description = "Unix time the request was signed at"),
+        ("X-Device-Signature" = String, Header, description = "Hex HMAC-SHA256 of `{method}\\n{path}\\n{imei}\\n{timestamp}\\n{body}` keyed with SHA-256 of the device secret")
+    ),
+    request_body = DeviceAckSchema,
+    responses(
+        (status = 200, description = "Commands acknowledged and commands that were not awaiting an answer.", body = [CommandModel]),
+        (status = 400, description = "Invalid payload"),
+        (status = 401, description = "Invalid device signature or replayed request"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    tag = "Gateway HTTP"
+)]
+#[post("/device-gateway/{imei}/ack")]
+pub async fn acknowledge_work(
+    req: HttpRequest,
+    path: Path<String>,
+    body: Bytes,
+    data: Data<AppState>
+) -> impl Responder {
+    let imei = path.into_inner();
+
+    let device = match authenticate_device(&req, &data.db, &imei, &body).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    let body = match serde_json::from_slice::<DeviceAckSchema>(&body) {
+        Ok(body) => body,
+        Err(error) => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Invalid payload: {}", error)
+            }));
+        }
+    };
+
+    let mut acknowledged = Vec::new();
+    let mut ignored = Vec::new();
+
+    for ack in &body.commands {
+        match complete(&data.db, device.id, ack.id, ack.success, ack.response.as_deref()).await {
+            Ok(Some(command)) => acknowledged.push(command),
+            Ok(None) => ignored.push(ack.id),
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        }
+    }
+
+    if let Some(version) = body.config_version {
+        if let Err(error) = sqlx::query!(
+            r#"
+            UPDATE device_configs SET reported_version = $2, reported_at = now()
+            WHERE device_id = $1 AND version >= $2
+            "#,
+            device.id,
+            version
+        )
+        .execute(&data.db)
+        .await
+        {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    HttpResponse::Ok().json(json!({
+        "status": "success",
+        "result": acknowledged.len(),
+        "commands": acknowledged,
+        "ignored": ignored,
+    }))
+}
+
+#[utoipa::path(
+    params(
+        ("imei" = String, Path, description = "IMEI of the device"),
+        ("update_id" = Uuid, Path, description = "Update offered by the pending endpoint"),
+        ("X-Device-Timestamp" = i64, Header, description = "Unix time the request was signed at"),
+        ("X-Device-Signature" = String, Header, description = "Hex HMAC-SHA256 of `{method}\\n{path}\\n{imei}\\n{timestamp}\\n` keyed with SHA-256 of the device secret")
+    ),
+    responses(
+        (status = 200, description = "Firmware image of the update, which moves to downloading.", content_type = "application/octet-stream"),
+        (status = 401, description = "Invalid device signature or replayed request"),
+        (status = 404, description = "No such update in progress for the device"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    tag = "Gateway HTTP"
+)]
+#[get("/device-gateway/{imei}/firmware/{update_id}")]
+pub async fn download_firmware(
+    req: HttpRequest,
+    path: Path<(String, Uuid)>,
+    data: Data<AppState>
+) -> impl Responder {
+    let (imei, update_id) = path.into_inner();
+
+    let device = match authenticate_device(&req, &data.db, &imei, &[]).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    let firmware = match sqlx::query!(
+        r#"
+        SELECT f.id, f.checksum, f.size
+        FROM ota_updates u
+        JOIN ota_campaigns c ON c.id = u.campaign_id
+        JOIN firmwares f ON f.id = c.firmware_id
+        WHERE u.id = $1 AND u.device_id = $2 AND u.status IN ('pending', 'downloading') AND c.status = 'active'
+        "#,
+        update_id,
+        device.id
+    )
+    .fetch_optional(&data.db)
+    .await {
+        Ok(Some(firmware)) => firmware,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "No such update in progress for the device"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let file = match tokio::fs::File::open(firmware_path(firmware.id)).await {
+        Ok(file) => file,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("Firmware image unavailable: {}", error)
+            }));
+        }
+    };
+
+    if let Err(error) = record_progress(&data.db, device.id, update_id, "downloading", None).await {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    let body = stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buffer = vec![0; FIRMWARE_CHUNK_SIZE];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok::<_, std::io::Error>(Bytes::from(buffer)), Some(file)))
+            }
+            Err(error) => Some((Err(error), None)),
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header(("X-Checksum-SHA256", firmware.checksum))
+        .no_chunking(firmware.size as u64)
+        .streaming(body)
+}
+
+#[utoipa::path(
+    params(
+        ("imei" = String, Path, description = "IMEI of the device"),
+        ("update_id" = Uuid, Path, description = "Update being reported"),
+        ("X-Device-Timestamp" = i64, Header, description = "Unix time the request was signed at"),
+        ("X-Device-Signature" = String, Header, description = "Hex HMAC-SHA256 of `{method}\\n{path}\\n{imei}\\n{timestamp}\\n{body}` keyed with SHA-256 of the device secret")
+    ),
+    request_body = OtaProgressSchema,
+    responses(
+        (status = 200, description = "Record the progress of a firmware update.", body = OtaUpdateModel),
+        (status = 400, description = "Invalid payload or status"),
+        (status = 401, description = "Invalid device signature or replayed request"),
+        (status = 409, description = "Update already finished"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    tag = "Gateway HTTP"
+)]
+#[post("/device-gateway/{imei}/firmware/{update_id}")]
+pub async fn report_firmware_progress(
+    req: HttpRequest,
+    path: Path<(String, Uuid)>,
+    body: Bytes,
+    data: Data<AppState>
+) -> impl Responder {
+    let (imei, update_id) = path.into_inner();
+
+    let device = match authenticate_device(&req, &data.db, &imei, &body).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    let body = match serde_json::from_slice::<OtaProgressSchema>(&body) {
+        Ok(body) => body,
+        Err(error) => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Invalid payload: {}", error)
+            }));
+        }
+    };
+
+    if !["downloading", "installed", "failed"].contains(&body.status.as_str()) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Invalid status, use downloading, installed or failed"
+        }));
+    }
+
+    match record_progress(&data.db, device.id, update_id, &body.status, body.error.as_deref()).await {
+        Ok(Some(update)) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "update": update,
+        })),
+        Ok(None) => HttpResponse::Conflict().json(json!({
+            "status": "error",
+            "message": "Update already finished"
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_web::{
+    web::{Bytes, Path, Data, Query, ReqData},
+    post, HttpResponse, Responder,
+};
+use chrono::Utc;
+use serde_json::{json, Value};
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    device_auth::issue_secret,
+    events::FleetEventKind,
+    plans::lock_device_quota,
+    schema::{ImportDeviceRow, ImportOptions},
+    model::{ClientModel, DeviceModel},
+    services::{device_nickname, registered_imeis, resolve_model_name},
+    validation::valid_imei,
+    AppState, TokenClaims,
+};
+
+const REQUIRED_COLUMNS: [&str; 4] = ["imei", "model", "serial_number", "status"];
+/// Largest file imported in one transaction.
+const MAX_IMPORT_ROWS: usize = 5000;
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Client receiving the devices"),
+        ("dry_run" = Option<bool>, Query, description = "Validate and report without creating anything")
+    ),
+    request_body(content = String, content_type = "text/csv", description = "CSV with a header row and the columns imei, model, serial_number and status"),
+    responses(
+        (status = 200, description = "Per-row report. Without dry_run every row was created, each with its signing secret."),
+        (status = 400, description = "Malformed file, or rows with errors and nothing imported"),
+        (status = 402, description = "The client's plan does not allow that many more devices, nothing imported"),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Client not found"),
+        (status = 409, description = "An IMEI was taken while importing, nothing imported"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Dispositivos"
+)]
+#[post("/clients/{id}/devices/import", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn import_devices(
+    path: Path<Uuid>,
+    opts: Query<ImportOptions>,
+    body: Bytes,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    let client = match sqlx::query_as!(
+        ClientModel,
+        "SELECT * FROM clients WHERE id = $1",
+        client_id
+    )
+    .fetch_one(&data.db)
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_ref());
+
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(error) => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Unreadable CSV header: {}", error)
+            }));
+        }
+    };
+    let missing: Vec<&str> = REQUIRED_COLUMNS
+        .into_iter()
+        .filter(|column| !headers.iter().any(|header| header == *column))
+        .collect();
+    if !missing.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("Missing CSV columns: {}", missing.join(", "))
+        }));
+    }
+
+    // Line numbers count the header as line 1
+    let rows: Vec<(u64, Result<ImportDeviceRow, String>)> = reader
+        .deserialize::<ImportDeviceRow>()
+        .enumerate()
+        .map(|(index, row)| {
+            (index as u64 + 2, row.map_err(|error| format!("Malformed row: {}", error)))
+        })
+        .collect();
+
+    if rows.is_empty() || rows.len() > MAX_IMPORT_ROWS {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("The file must have between 1 and {} rows", MAX_IMPORT_ROWS)
+        }));
+    }
+
+    let mut lines_by_imei: HashMap<&str, Vec<u64>> = HashMap::new();
+    for (line, row) in &rows {
+        if let Ok(row) = row {
+            lines_by_imei.entry(row.imei.as_str()).or_default().push(*line);
+        }
+    }
+
+    let imeis: Vec<String> = lines_by_imei.keys().map(|imei| imei.to_string()).collect();
+    let taken: HashSet<String> = match registered_imeis(&data.db, &imeis).await {
+        Ok(taken) => taken.into_iter().collect(),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let mut catalog: HashMap<&str, Option<String>> = HashMap::new();
+    for (_, row) in &rows {
+        if let Ok(row) = row {
+            if catalog.contains_key(row.model.as_str()) {
+                continue;
+            }
+            match resolve_model_name(&data.db, &row.model).await {
+                Ok(name) => catalog.insert(row.model.as_str(), name),
+                Err(error) => {
+                    return HttpResponse::InternalServerError().json(json!({
+                        "status": "error",
+                        "message": format!("{:?}", error)
+                    }));
+                }
+            };
+        }
+    }
+
+    let mut report: Vec<Value> = Vec::with_capacity(rows.len());
+    let mut valid: Vec<(usize, &ImportDeviceRow, &str)> = Vec::with_capacity(rows.len());
+
+    for (line, row) in &rows {
+        let row = match row {
+            Ok(row) => row,
+            Err(error) => {
+                report.push(json!({ "line": line, "status": "error", "errors": [error] }));
+                continue;
+            }
+        };
+
+        let mut errors = Vec::new();
+        if !valid_imei(&row.imei) {
+            errors.push("IMEI must be 15 digits with a valid check digit".to_string());
+        }
+        let lines = &lines_by_imei[row.imei.as_str()];
+        if lines.len() > 1 {
+            let others: Vec<String> = lines
+                .iter()
+                .filter(|other| *other != line)
+                .map(|other| other.to_string())
+                .collect();
+            let noun = if others.len() == 1 { "line" } else { "lines" };
+            errors.push(format!("IMEI repeated on {} {}", noun, others.join(", ")));
+        }
+        if taken.contains(&row.imei) {
+            errors.push("IMEI already registered".to_string());
+        }
+        let model = catalog[row.model.as_str()].as_deref();
+        if model.is_none() {
+            errors.push(format!("Unknown device model {}", row.model));
+        }
+        if row.serial_number.is_empty() {
+            errors.push("serial_number is required".to_string());
+        }
+        if row.status.is_empty() {
+            errors.push("status is required".to_string());
+        }
+
+        if errors.is_empty() {
+            valid.push((report.len(), row, model.unwrap_or_default()));
+            report.push(json!({ "line": line, "imei": row.imei, "status": "ok" }));
+        } else {
+            report.push(json!({ "line": line, "imei": row.imei, "status": "error", "errors": errors }));
+        }
+    }
+
+    let failed = rows.len() - valid.len();
+    if failed > 0 {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("{} of {} rows have errors, nothing imported", failed, rows.len()),
+            "rows": report,
+        }));
+    }
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match lock_device_quota(&mut tx, client.id).await {
+        Ok(Some(quota)) if !quota.allows(valid.len()) => return quota.exceeded(valid.len()),
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Client not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    if opts.dry_run.unwrap_or(false) {
+        return HttpResponse::Ok().json(json!({
+            "status": "success",
+            "dry_run": true,
+            "result": valid.len(),
+            "rows": report,
+        }));
+    }
+
+    let now = Utc::now();
+    let nicknames: Vec<String> = valid
+        .iter()
+        .map(|(_, row, _)| device_nickname(&client, &row.serial_number))
+        .collect();
+    let imeis: Vec<String> = valid.iter().map(|(_, row, _)| row.imei.clone()).collect();
+    let models: Vec<String> = valid.iter().map(|(_, _, model)| model.to_string()).collect();
+    let serial_numbers: Vec<String> = valid.iter().map(|(_, row, _)| row.serial_number.clone()).collect();
+    let statuses: Vec<String> = valid.iter().map(|(_, row, _)| row.status.clone()).collect();
+
+    let devices = match sqlx::query_as!(
+        DeviceModel,
+        r#"
+        INSERT INTO devices
+            (client_id, nickname, imei, model, serial_number, upload_data, upload_gps, status)
+        SELECT $1, nickname, imei, model, serial_number, $2, $2, status
+        FROM UNNEST($3::VARCHAR[], $4::VARCHAR[], $5::VARCHAR[], $6::VARCHAR[], $7::VARCHAR[])
+            AS rows (nickname, imei, model, serial_number, status)
+        RETURNING *
+        "#,
+        client.id,
+        now,
+        &nicknames,
+        &imeis,
+        &models,
+        &serial_numbers,
+        &statuses
+    )
+    .fetch_all(&mut tx)
+    .await {
+        Ok(devices) => devices,
+        // unique_violation, an IMEI was registered since the check
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            return HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "An IMEI was registered while importing, nothing imported"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let mut secrets = HashMap::with_capacity(devices.len());
+    for device in &devices {
+        match issue_secret(&mut tx, device.id).await {
+            Ok(secret) => secrets.insert(device.imei.as_str(), (device.id, secret)),
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        };
+    }
+
+    if let Err(error) = tx.commit().await {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    for device in &devices {
+        data.events.publish(FleetEventKind::DeviceCreated, device.client_id, device.id, device);
+    }
+
+    for (index, row, _) in &valid {
+        let (device_id, secret) = &secrets[row.imei.as_str()];
+        report[*index] = json!({
+            "line": report[*index]["line"],
+            "imei": row.imei,
+            "status": "created",
+            "device_id": device_id,
+            // Shown once, only the salt it is derived from is stored
+            "secret": secret,
+        });
+    }
+
+    HttpResponse::Ok().json(json!({
+        "status": "success",
+        "dry_run": false,
+        "result": devices.len(),
+        "rows": report,
+    }))
+}
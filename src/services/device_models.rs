@@ -0,0 +1,427 @@
+use actix_web::{
+    web::{Json, Path, Data, Query, ReqData},
+    get, post, patch, delete, HttpResponse, Responder,
+};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    schema::{CreateCatalogModelSchema, FilterOptions, UpdateCatalogModelSchema},
+    model::CatalogModel,
+    AppState, TokenClaims,
+};
+
+fn operators_only() -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "status": "error",
+        "message": "The model catalog is managed with operator tokens"
+    }))
+}
+
+/// "unknown" for models whose protocol is not known yet, as the ones the
+/// catalog was started with from the models devices already used.
+pub const PROTOCOLS: [&str; 6] = ["teltonika", "gt06", "nmea", "mqtt", "http", "unknown"];
+
+/// Catalog name matching `model` once case and punctuation are ignored,
+/// so "model-x" resolves to "Model X".
+pub async fn resolve_model_name(
+    db: &Pool<Postgres>,
+    model: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT name FROM device_models
+        WHERE lower(regexp_replace(name, '[^a-zA-Z0-9]', '', 'g'))
+            = lower(regexp_replace($1, '[^a-zA-Z0-9]', '', 'g'))
+        ORDER BY name = $1 DESC
+        LIMIT 1
+        "#,
+        model
+    )
+    .fetch_optional(db)
+    .await
+}
+
+#[utoipa::path(
+    request_body = CreateCatalogModelSchema,
+    responses(
+        (status = 200, description = "Add a device model to the catalog.", body = CatalogModel),
+        (status = 400, description = "Invalid protocol"),
+        (status = 403, description = "Not an operator token"),
+        (status = 409, description = "A model with an equivalent name exists"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Modelos"
+)]
+#[post("/device-models", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn create_device_model(
+    body: Json<CreateCatalogModelSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if !claims.is_operator() {
+        return operators_only();
+    }
+
+    if !PROTOCOLS.contains(&body.protocol.as_str()) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("Invalid protocol, use one of {}", PROTOCOLS.join(", "))
+        }));
+    }
+
+    match resolve_model_name(&data.db, &body.name).await {
+        Ok(None) => (),
+        Ok(Some(existing)) => {
+            return HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": format!("Equivalent to the existing model {}", existing)
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match sqlx::query_as!(
+        CatalogModel,
+        r#"
+        INSERT INTO device_models
+            (name, manufacturer, protocol, capabilities, gps_interval_seconds, data_interval_seconds)
+        VALUES
+            ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+        "#,
+        body.name,
+        body.manufacturer,
+        body.protocol,
+        &body.capabilities.clone().unwrap_or_default(),
+        body.gps_interval_seconds,
+        body.data_interval_seconds
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(model) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "model": model,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List the device model catalog.", body = [CatalogModel]),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Modelos"
+)]
+#[get("/device-models", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_all_device_models(
+    opts: Query<FilterOptions>,
+    data: Data<AppState>
+) -> impl Responder {
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        CatalogModel,
+        "SELECT * FROM device_models ORDER BY name LIMIT $1 OFFSET $2",
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(models) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": models.len(),
+            "models": models,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get device model by ID.", body = CatalogModel),
+        (status = 404, description = "Device model not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Modelos"
+)]
+#[get("/device-models/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_device_model_by_id(
+    path: Path<Uuid>,
+    data: Data<AppState>
+) -> impl Responder {
+    let model_id = path.into_inner();
+
+    match sqlx::query_as!(
+        CatalogModel,
+        "SELECT * FROM device_models WHERE id = $1",
+        model_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(model) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "model": model,
+        })),
+        Err(error) => HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = UpdateCatalogModelSchema,
+    responses(
+        (status = 200, description = "Update device model by ID. A new name is carried over to devices, inventory and firmware.", body = CatalogModel),
+        (status = 400, description = "Invalid protocol"),
+        (status = 403, description = "Not an operator token"),
+        (status = 404, description = "Device model not found"),
+        (status = 409, description = "A model with an equivalent name exists"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Modelos"
+)]
+#[patch("/device-models/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn update_device_model_by_id(
+    path: Path<Uuid>,
+    body: Json<UpdateCatalogModelSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if !claims.is_operator() {
+        return operators_only();
+    }
+
+    let model_id = path.into_inner();
+
+    let existing_model = sqlx::query_as!(
+        CatalogModel,
+        "SELECT * FROM device_models WHERE id = $1",
+        model_id
+    )
+    .fetch_one(&data.db)
+    .await;
+
+    if let Err(error) = existing_model {
+        return HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    let model = existing_model.unwrap();
+
+    if let Some(protocol) = &body.protocol {
+        if !PROTOCOLS.contains(&protocol.as_str()) {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Invalid protocol, use one of {}", PROTOCOLS.join(", "))
+            }));
+        }
+    }
+
+    if let Some(name) = &body.name {
+        match resolve_model_name(&data.db, name).await {
+            Ok(Some(existing)) if existing != model.name => {
+                return HttpResponse::Conflict().json(json!({
+                    "status": "error",
+                    "message": format!("Equivalent to the existing model {}", existing)
+                }));
+            }
+            Ok(_) => (),
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        }
+    }
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    // Devices and inventory follow through ON UPDATE CASCADE
+    let updated_model = match sqlx::query_as!(
+        CatalogModel,
+        r#"
+        UPDATE device_models
+        SET name = $1, manufacturer = $2, protocol = $3, capabilities = $4,
+            gps_interval_seconds = $5, data_interval_seconds = $6
+        WHERE id = $7
+        RETURNING *
+        "#,
+        body.name.clone().unwrap_or(model.name.clone()),
+        body.manufacturer.clone().unwrap_or(model.manufacturer),
+        body.protocol.clone().unwrap_or(model.protocol),
+        &body.capabilities.clone().unwrap_or(model.capabilities),
+        body.gps_interval_seconds.or(model.gps_interval_seconds),
+        body.data_interval_seconds.or(model.data_interval_seconds),
+        model_id
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(updated_model) => updated_model,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if updated_model.name != model.name {
+        if let Err(error) = sqlx::query!(
+            "UPDATE firmwares SET models = array_replace(models, $1, $2) WHERE $1 = ANY(models)",
+            model.name,
+            updated_model.name
+        )
+        .execute(&mut tx)
+        .await
+        {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "model": updated_model,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 204, description = "Delete device model by ID."),
+        (status = 403, description = "Not an operator token"),
+        (status = 409, description = "Devices, inventory or firmware still use the model"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Modelos"
+)]
+#[delete("/device-models/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn delete_device_model_by_id(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if !claims.is_operator() {
+        return operators_only();
+    }
+
+    let model_id = path.into_inner();
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    // Firmware uploads hold a share lock on the models they list
+    let name = match sqlx::query_scalar!("SELECT name FROM device_models WHERE id = $1 FOR UPDATE", model_id)
+        .fetch_optional(&mut tx)
+        .await
+    {
+        Ok(Some(name)) => name,
+        Ok(None) => return HttpResponse::NoContent().finish(),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    // firmwares.models is an array, no foreign key keeps the model there
+    match sqlx::query_scalar!(
+        "SELECT version FROM firmwares WHERE $1 = ANY(models) ORDER BY created_at",
+        name
+    )
+    .fetch_all(&mut tx)
+    .await {
+        Ok(versions) if versions.is_empty() => (),
+        Ok(versions) => {
+            return HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": format!("Firmware {} still lists the model", versions.join(", "))
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match sqlx::query!(
+        "DELETE FROM device_models WHERE id = $1",
+        model_id
+    )
+    .execute(&mut tx)
+    .await {
+        Ok(_) => (),
+        // foreign_key_violation
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23503") => {
+            return HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "Devices or inventory still use the model"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
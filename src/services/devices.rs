@@ -1,31 +1,53 @@
 use actix_web::{
-    web::{Json, Path, Data, Query},
+    web::{Json, Path, Data, Query, ReqData},
     get, post, patch, delete, HttpResponse, Responder,
 };
 use serde_json::json;
+use sqlx::{Pool, Postgres};
 use uuid::Uuid;
 #[allow(unused_imports)]
 use utoipa::ToSchema;
 
 use crate::{
-    schema::{CreateDeviceSchema, FilterOptions, UpdateDeviceSchema},
+    schema::{CreateDeviceSchema, DeviceFilterOptions, UpdateDeviceSchema},
     model::{ClientModel, DeviceModel},
-    AppState,
+    device_auth::issue_secret,
+    plans::lock_device_quota,
+    services::{parse_device_filter, resolve_model_name, DeviceFilter},
+    validation::metadata_errors,
+    events::FleetEventKind,
+    tenant,
+    AppState, TokenClaims,
 };
 
+/// Nickname given to new devices: the client name in lowercase followed by the serial number.
+pub fn device_nickname(client: &ClientModel, serial_number: &str) -> String {
+    format!("{}{}", client.name.to_lowercase(), serial_number)
+}
+
+/// IMEIs of the list already registered to a device. Looked up outside the
+/// request's scope, an IMEI is unique across every client.
+pub async fn registered_imeis(db: &Pool<Postgres>, imeis: &[String]) -> Result<Vec<String>, sqlx::Error> {
+    tenant::system(sqlx::query_scalar!("SELECT imei FROM devices WHERE imei = ANY($1)", imeis).fetch_all(db)).await
+}
+
 #[utoipa::path(
     request_body = CreateDeviceSchema,
     responses(
-        (status = 200, description = "Create a new device.", body = DeviceModel),
-        (status = 400, description = "Invalid client_id UUID"),
+        (status = 200, description = "Create a new device. The response carries its signing secret, which is not shown again.", body = DeviceModel),
+        (status = 400, description = "Invalid client_id UUID, unknown device model or metadata not following the client's schema"),
+        (status = 402, description = "The client's plan allows no more devices"),
+        (status = 403, description = "Client not accessible with this token"),
         (status = 404, description = "Client not found"),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Dispositivos"
 )]
-#[post("/devices")]
+#[post("/devices", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn create_device(
     body: Json<CreateDeviceSchema>,
+    claims: ReqData<TokenClaims>,
     data: Data<AppState>
 ) -> impl Responder {
     let client_id = match Uuid::parse_str(&body.client_id) {
@@ -38,6 +60,13 @@ pub async fn create_device(
         }
     };
 
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
     let client = match sqlx::query_as!(
         ClientModel,
         "SELECT * FROM clients WHERE id = $1",
@@ -55,7 +84,33 @@ pub async fn create_device(
         }
     };
 
-    let nickname = format!("{}{}", client.name.to_lowercase(), body.serial_number);
+    let nickname = device_nickname(&client, &body.serial_number);
+
+    let metadata = body.metadata.clone().unwrap_or_else(|| json!({}));
+    let errors = metadata_errors(client.metadata_schema.as_ref(), &metadata);
+    if !errors.is_empty() {
+        return HttpResponse::BadRequest().json(json!( {
+            "status": "error",
+            "message": "Invalid metadata",
+            "errors": errors
+        }));
+    }
+
+    let model = match resolve_model_name(&data.db, &body.model).await {
+        Ok(Some(model)) => model,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!( {
+                "status": "error",
+                "message": "Unknown device model, add it to the catalog first"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!( {
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
 
     let upload_data = match body.upload_data.parse::<chrono::DateTime<chrono::Utc>>() {
         Ok(dt) => dt,
@@ -73,57 +128,154 @@ pub async fn create_device(
         })),
     };
 
-    match sqlx::query_as!(
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!( {
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match lock_device_quota(&mut tx, client_id).await {
+        Ok(Some(quota)) if !quota.allows(1) => return quota.exceeded(1),
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!( {
+                "status": "error",
+                "message": "Client not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!( {
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    let device = match sqlx::query_as!(
         DeviceModel,
         r#"
         INSERT INTO devices 
-            (client_id, nickname, imei, model, serial_number, upload_data, upload_gps, status)
+            (client_id, nickname, imei, model, serial_number, upload_data, upload_gps, status, metadata)
         VALUES 
-            ($1, $2, $3, $4, $5, $6, $7, $8)
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)
         RETURNING *
         "#,
         client_id,
         nickname,
         body.imei,
-        body.model,
+        model,
         body.serial_number,
         upload_data,
         upload_gps,
-        body.status
+        body.status,
+        metadata
     )
-    .fetch_one(&data.db)
+    .fetch_one(&mut tx)
     .await {
-        Ok(device) => HttpResponse::Ok().json(json!( {
-            "status": "success",
-            "device": device,
-        })),
-        Err(error) => HttpResponse::InternalServerError().json(json!( {
+        Ok(device) => device,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!( {
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let secret = match issue_secret(&mut tx, device.id).await {
+        Ok(secret) => secret,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!( {
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if let Err(error) = tx.commit().await {
+        return HttpResponse::InternalServerError().json(json!( {
             "status": "error",
             "message": format!("{:?}", error)
-        })),
+        }));
     }
+
+    data.events.publish(FleetEventKind::DeviceCreated, device.client_id, device.id, &device);
+
+    HttpResponse::Ok().json(json!( {
+        "status": "success",
+        "device": device,
+        // Shown once, only the salt it is derived from is stored
+        "secret": secret,
+    }))
 }
 
 #[utoipa::path(
+    params(
+        ("limit" = Option<usize>, Query, description = "Page size"),
+        ("page" = Option<usize>, Query, description = "Page number"),
+        ("tag" = Option<String>, Query, description = "Comma separated tags the devices must all carry"),
+        ("metadata" = Option<String>, Query, description = "JSON object the device metadata must contain, e.g. {\"region\":\"south\"}"),
+        ("client_id" = Option<Uuid>, Query, description = "Only the devices of this client"),
+        ("include_descendants" = Option<bool>, Query, description = "With client_id, also the devices of its sub-clients at any depth")
+    ),
     responses(
         (status = 200, description = "List all devices.", body = [DeviceModel]),
+        (status = 400, description = "Invalid tag or metadata filter, or include_descendants without client_id"),
+        (status = 403, description = "Client not accessible with this token"),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Dispositivos"
 )]
-#[get("/devices")]
+#[get("/devices", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn get_all_devices(
-    opts: Query<FilterOptions>,
+    opts: Query<DeviceFilterOptions>,
+    claims: ReqData<TokenClaims>,
     data: Data<AppState>
 ) -> impl Responder {
     let limit = opts.limit.unwrap_or(10);
     let offset = (opts.page.unwrap_or(1) - 1) * limit;
 
+    let DeviceFilter { tags, metadata, include_descendants } = match parse_device_filter(&opts) {
+        Ok(filter) => filter,
+        Err(response) => return response,
+    };
+    if let Some(client_id) = opts.client_id {
+        if !claims.can_access_client(client_id) {
+            return HttpResponse::Forbidden().json(json!({
+                "status": "error",
+                "message": "Client not accessible with this token"
+            }));
+        }
+    }
+
     match sqlx::query_as!(
         DeviceModel,
-        "SELECT * FROM devices ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+        r#"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM clients WHERE id = $5
+            UNION
+            SELECT c.id FROM clients c JOIN subtree s ON c.parent_id = s.id AND $6
+        )
+        SELECT * FROM devices
+        WHERE ($1::VARCHAR[] IS NULL OR (
+                SELECT COUNT(*) FROM device_tags dt JOIN tags t ON t.id = dt.tag_id
+                WHERE dt.device_id = devices.id AND t.name = ANY($1)
+            ) = cardinality($1))
+            AND ($2::JSONB IS NULL OR metadata @> $2)
+            AND ($5::UUID IS NULL OR client_id IN (SELECT id FROM subtree))
+        ORDER BY created_at DESC
+        LIMIT $3 OFFSET $4
+        "#,
+        tags.as_deref(),
+        metadata,
         limit as i32,
-        offset as i32
+        offset as i32,
+        opts.client_id,
+        include_descendants
     )
     .fetch_all(&data.db)
     .await {
@@ -145,9 +297,10 @@ pub async fn get_all_devices(
         (status = 404, description = "Device not found"),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Dispositivos"
 )]
-#[get("/devices/{id}")]
+#[get("/devices/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn get_device_by_id(
     path: Path<Uuid>,
     data: Data<AppState>
@@ -176,12 +329,14 @@ pub async fn get_device_by_id(
     request_body = UpdateDeviceSchema,
     responses(
         (status = 200, description = "Update device by ID.", body = DeviceModel),
+        (status = 400, description = "Unknown device model or metadata not following the client's schema"),
         (status = 404, description = "Device not found"),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Dispositivos"
 )]
-#[patch("/devices/{id}")]
+#[patch("/devices/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn update_device_by_id(
     path: Path<Uuid>,
     body: Json<UpdateDeviceSchema>,
@@ -214,23 +369,83 @@ pub async fn update_device_by_id(
         .and_then(|s| s.parse::<chrono::DateTime<chrono::Utc>>().ok())
         .unwrap_or(device.upload_gps);
 
+    let model = match &body.model {
+        Some(model) => match resolve_model_name(&data.db, model).await {
+            Ok(Some(model)) => model,
+            Ok(None) => {
+                return HttpResponse::BadRequest().json(json!( {
+                    "status": "error",
+                    "message": "Unknown device model, add it to the catalog first"
+                }));
+            }
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!( {
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        },
+        None => device.model,
+    };
+
+    let metadata = match &body.metadata {
+        Some(metadata) => {
+            let schema = match sqlx::query_scalar!(
+                "SELECT metadata_schema FROM clients WHERE id = $1",
+                device.client_id
+            )
+            .fetch_one(&data.db)
+            .await {
+                Ok(schema) => schema,
+                Err(error) => {
+                    return HttpResponse::InternalServerError().json(json!( {
+                        "status": "error",
+                        "message": format!("{:?}", error)
+                    }));
+                }
+            };
+
+            let errors = metadata_errors(schema.as_ref(), metadata);
+            if !errors.is_empty() {
+                return HttpResponse::BadRequest().json(json!( {
+                    "status": "error",
+                    "message": "Invalid metadata",
+                    "errors": errors
+                }));
+            }
+
+            metadata.clone()
+        }
+        None => device.metadata,
+    };
+
     match sqlx::query_as!(
         DeviceModel,
-        "UPDATE devices SET nickname = $1, imei = $2, model = $3, upload_data = $4, upload_gps = $5, status = $6 WHERE id = $7 RETURNING *",
+        "UPDATE devices SET nickname = $1, imei = $2, model = $3, upload_data = $4, upload_gps = $5, status = $6, metadata = $7 WHERE id = $8 RETURNING *",
         body.nickname.clone().unwrap_or(device.nickname),
         body.imei.clone().unwrap_or(device.imei),
-        body.model.clone().unwrap_or(device.model),
+        model,
         upload_data,
         upload_gps,
         body.status.clone().unwrap_or(device.status),
+        metadata,
         device_id
     )
     .fetch_one(&data.db)
     .await {
-        Ok(updated_device) => HttpResponse::Ok().json(json!( {
-            "status": "success",
-            "device": updated_device,
-        })),
+        Ok(updated_device) => {
+            data.events.publish(
+                FleetEventKind::DeviceUpdated,
+                updated_device.client_id,
+                updated_device.id,
+                &updated_device,
+            );
+
+            HttpResponse::Ok().json(json!( {
+                "status": "success",
+                "device": updated_device,
+            }))
+        }
         Err(error) => HttpResponse::InternalServerError().json(json!( {
             "status": "error",
             "message": format!("{:?}", error)
@@ -243,9 +458,10 @@ pub async fn update_device_by_id(
         (status = 204, description = "Delete device by ID."),
         (status = 500, description = "Internal Server error.")
     ),
+    security(("bearer_auth" = [])),
     tag = "Dispositivos"
 )]
-#[delete("/devices/{id}")]
+#[delete("/devices/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
 pub async fn delete_device_by_id(
     path: Path<Uuid>,
     data: Data<AppState>
@@ -265,3 +481,78 @@ pub async fn delete_device_by_id(
         })),
     }
 }
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Replace the device signing secret. The new secret is not shown again."),
+        (status = 403, description = "Device not accessible with this token"),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Dispositivos"
+)]
+#[post("/devices/{id}/secret", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn rotate_device_secret(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let device_id = path.into_inner();
+
+    let device = match sqlx::query_as!(
+        DeviceModel,
+        "SELECT * FROM devices WHERE id = $1",
+        device_id
+    )
+    .fetch_one(&data.db)
+    .await
+    {
+        Ok(device) => device,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!( {
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if !claims.can_access_client(device.client_id) {
+        return HttpResponse::Forbidden().json(json!( {
+            "status": "error",
+            "message": "Device not accessible with this token"
+        }));
+    }
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!( {
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let secret = match issue_secret(&mut tx, device.id).await {
+        Ok(secret) => secret,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!( {
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!( {
+            "status": "success",
+            "device_id": device.id,
+            "secret": secret,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!( {
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
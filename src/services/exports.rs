@@ -0,0 +1,177 @@
+use actix_web::{
+    web::{Data, Query, ReqData},
+    get, HttpRequest, HttpResponse, Responder,
+};
+use serde_json::json;
+use tokio::sync::mpsc;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    export::{export_response, forward_rows, not_acceptable, select_fields, ExportFormat, EXPORT_BUFFER},
+    schema::{DeviceFilterOptions, ExportOptions},
+    model::{ClientModel, DeviceModel},
+    services::{parse_device_filter, DeviceFilter},
+    tenant, AppState, TokenClaims,
+};
+
+const CLIENT_FIELDS: [&str; 4] = ["id", "name", "status", "created_at"];
+const DEVICE_FIELDS: [&str; 11] = [
+    "id", "client_id", "nickname", "imei", "model", "serial_number",
+    "upload_data", "upload_gps", "status", "created_at", "metadata",
+];
+
+/// LIMIT and OFFSET of an export page, no limit when none was asked for.
+fn export_page(opts: &ExportOptions) -> (Option<i64>, i64) {
+    match opts.limit {
+        Some(limit) => (Some(limit as i64), ((opts.page.unwrap_or(1).max(1) - 1) * limit) as i64),
+        None => (None, 0),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("limit" = Option<usize>, Query, description = "Page size, every client when omitted"),
+        ("page" = Option<usize>, Query, description = "Page number"),
+        ("fields" = Option<String>, Query, description = "Comma separated columns among id, name, status and created_at"),
+        ("Accept" = Option<String>, Header, description = "text/csv (default), application/x-ndjson or application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+    ),
+    responses(
+        (status = 200, description = "Export the clients the token reaches as a file streamed while it is read from the database."),
+        (status = 400, description = "Unknown field"),
+        (status = 406, description = "No supported format in the Accept header"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Clientes"
+)]
+#[get("/clients/export", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn export_clients(
+    req: HttpRequest,
+    opts: Query<ExportOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let format = match ExportFormat::from_request(&req) {
+        Some(format) => format,
+        None => return not_acceptable(),
+    };
+    let fields = match select_fields(opts.fields.as_deref(), &CLIENT_FIELDS) {
+        Ok(fields) => fields,
+        Err(response) => return response,
+    };
+    let (limit, offset) = export_page(&opts);
+
+    let scope = claims.client_scope().map(<[_]>::to_vec);
+
+    let (sender, receiver) = mpsc::channel(EXPORT_BUFFER);
+    let db = data.db.clone();
+    actix_web::rt::spawn(tenant::inherit(async move {
+        let rows = sqlx::query_as!(
+            ClientModel,
+            r#"
+            SELECT * FROM clients
+            WHERE $3::UUID[] IS NULL OR id = ANY($3)
+            ORDER BY created_at DESC
+            LIMIT $1 OFFSET $2
+            "#,
+            limit,
+            offset,
+            scope.as_deref()
+        )
+        .fetch(&db);
+        forward_rows(rows, sender).await;
+    }));
+
+    export_response(format, "clients", fields, receiver)
+}
+
+#[utoipa::path(
+    params(
+        ("limit" = Option<usize>, Query, description = "Page size, every device when omitted"),
+        ("page" = Option<usize>, Query, description = "Page number"),
+        ("fields" = Option<String>, Query, description = "Comma separated columns among id, client_id, nickname, imei, model, serial_number, upload_data, upload_gps, status, created_at and metadata"),
+        ("tag" = Option<String>, Query, description = "Comma separated tags the devices must all carry"),
+        ("metadata" = Option<String>, Query, description = "JSON object the device metadata must contain"),
+        ("client_id" = Option<Uuid>, Query, description = "Only the devices of this client"),
+        ("include_descendants" = Option<bool>, Query, description = "With client_id, also the devices of its sub-clients at any depth"),
+        ("Accept" = Option<String>, Header, description = "text/csv (default), application/x-ndjson or application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+    ),
+    responses(
+        (status = 200, description = "Export the devices the token reaches as a file streamed while it is read from the database, filtered as GET /devices."),
+        (status = 400, description = "Unknown field, invalid tag or metadata filter, or include_descendants without client_id"),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 406, description = "No supported format in the Accept header"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Dispositivos"
+)]
+#[get("/devices/export", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn export_devices(
+    req: HttpRequest,
+    opts: Query<ExportOptions>,
+    filter: Query<DeviceFilterOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let format = match ExportFormat::from_request(&req) {
+        Some(format) => format,
+        None => return not_acceptable(),
+    };
+    let fields = match select_fields(opts.fields.as_deref(), &DEVICE_FIELDS) {
+        Ok(fields) => fields,
+        Err(response) => return response,
+    };
+    let DeviceFilter { tags, metadata, include_descendants } = match parse_device_filter(&filter) {
+        Ok(filter) => filter,
+        Err(response) => return response,
+    };
+    if let Some(client_id) = filter.client_id {
+        if !claims.can_access_client(client_id) {
+            return HttpResponse::Forbidden().json(json!({
+                "status": "error",
+                "message": "Client not accessible with this token"
+            }));
+        }
+    }
+    let (limit, offset) = export_page(&opts);
+    let client_id = filter.client_id;
+    let scope = claims.client_scope().map(<[_]>::to_vec);
+
+    let (sender, receiver) = mpsc::channel(EXPORT_BUFFER);
+    let db = data.db.clone();
+    actix_web::rt::spawn(tenant::inherit(async move {
+        let rows = sqlx::query_as!(
+            DeviceModel,
+            r#"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM clients WHERE id = $5
+                UNION
+                SELECT c.id FROM clients c JOIN subtree s ON c.parent_id = s.id AND $6
+            )
+            SELECT * FROM devices
+            WHERE ($1::VARCHAR[] IS NULL OR (
+                    SELECT COUNT(*) FROM device_tags dt JOIN tags t ON t.id = dt.tag_id
+                    WHERE dt.device_id = devices.id AND t.name = ANY($1)
+                ) = cardinality($1))
+                AND ($2::JSONB IS NULL OR metadata @> $2)
+                AND ($5::UUID IS NULL OR client_id IN (SELECT id FROM subtree))
+                AND ($7::UUID[] IS NULL OR client_id = ANY($7))
+            ORDER BY created_at DESC
+            LIMIT $3 OFFSET $4
+            "#,
+            tags.as_deref(),
+            metadata,
+            limit,
+            offset,
+            client_id,
+            include_descendants,
+            scope.as_deref()
+        )
+        .fetch(&db);
+        forward_rows(rows, sender).await;
+    }));
+
+    export_response(format, "devices", fields, receiver)
+}
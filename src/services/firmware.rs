@@ -0,0 +1,715 @@
+use actix_web::{
+    web::{Json, Path, Data, Payload, Query, ReqData},
+    get, post, patch, HttpResponse, Responder,
+};
+use futures_util::StreamExt;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    ota::{enroll_stage, firmware_dir, firmware_path},
+    schema::{CreateCampaignSchema, FilterOptions, FirmwareUploadOptions, UpdateCampaignSchema},
+    model::{FirmwareModel, OtaCampaignModel, OtaUpdateModel},
+    services::resolve_model_name,
+    validation::{normalize_tag, valid_firmware_version, MAX_FIRMWARE_VERSION_LENGTH, MAX_TAG_LENGTH},
+    AppState, TokenClaims,
+};
+
+/// Largest firmware image accepted, in bytes.
+const MAX_FIRMWARE_SIZE: usize = 64 * 1024 * 1024;
+const DEFAULT_FAILURE_THRESHOLD: f64 = 0.2;
+
+#[utoipa::path(
+    params(
+        ("version" = String, Query, description = "Firmware version"),
+        ("models" = String, Query, description = "Comma separated device models the image fits"),
+        ("checksum" = Option<String>, Query, description = "Expected SHA-256 of the image"),
+        ("notes" = Option<String>, Query, description = "Release notes")
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "Firmware image"),
+    responses(
+        (status = 200, description = "Store a firmware image in the catalog.", body = FirmwareModel),
+        (status = 400, description = "Invalid version, missing or unknown models, checksum mismatch or image too large"),
+        (status = 403, description = "Only operators can upload firmware"),
+        (status = 409, description = "Version already in the catalog"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Firmware"
+)]
+#[post("/firmwares", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn upload_firmware(
+    mut payload: Payload,
+    opts: Query<FirmwareUploadOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if claims.client_id.is_some() {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Only operators can upload firmware"
+        }));
+    }
+
+    if !valid_firmware_version(&opts.version) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!(
+                "Invalid version, use 1 to {} letters, digits, '.', '_', '-' or '+'",
+                MAX_FIRMWARE_VERSION_LENGTH
+            )
+        }));
+    }
+
+    let mut models = Vec::new();
+    for model in opts.models.split(',').map(str::trim).filter(|model| !model.is_empty()) {
+        match resolve_model_name(&data.db, model).await {
+            Ok(Some(model)) if models.contains(&model) => (),
+            Ok(Some(model)) => models.push(model),
+            Ok(None) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": format!("Unknown device model {}, add it to the catalog first", model)
+                }));
+            }
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        }
+    }
+    if models.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "At least one compatible model is required"
+        }));
+    }
+
+    match sqlx::query_scalar!("SELECT id FROM firmwares WHERE version = $1", opts.version)
+        .fetch_optional(&data.db)
+        .await
+    {
+        Ok(None) => (),
+        Ok(Some(_)) => {
+            return HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "Version already in the catalog"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    let id = Uuid::new_v4();
+    let path = firmware_path(id);
+    let partial = path.with_extension("part");
+
+    // Written to a partial file while hashing, renamed only once complete and verified
+    let written: Result<(String, usize), String> = async {
+        tokio::fs::create_dir_all(firmware_dir())
+            .await
+            .map_err(|error| error.to_string())?;
+        let mut file = tokio::fs::File::create(&partial)
+            .await
+            .map_err(|error| error.to_string())?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.map_err(|error| error.to_string())?;
+            size += chunk.len();
+            if size > MAX_FIRMWARE_SIZE {
+                return Err(format!("Image larger than {} bytes", MAX_FIRMWARE_SIZE));
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk).await.map_err(|error| error.to_string())?;
+        }
+
+        file.flush().await.map_err(|error| error.to_string())?;
+        Ok((hex::encode(hasher.finalize()), size))
+    }
+    .await;
+
+    let (checksum, size) = match written {
+        Ok((_, 0)) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": "Empty image"
+            }));
+        }
+        Ok(written) => written,
+        Err(error) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": error
+            }));
+        }
+    };
+
+    if let Some(expected) = &opts.checksum {
+        if !expected.eq_ignore_ascii_case(&checksum) {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Checksum mismatch, image hashes to {}", checksum)
+            }));
+        }
+    }
+
+    if let Err(error) = tokio::fs::rename(&partial, &path).await {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": error.to_string()
+        }));
+    }
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    // Held until the firmware is stored so the models cannot be deleted meanwhile
+    match sqlx::query_scalar!("SELECT name FROM device_models WHERE name = ANY($1) FOR SHARE", &models)
+        .fetch_all(&mut tx)
+        .await
+    {
+        Ok(locked) if locked.len() == models.len() => (),
+        Ok(_) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": "A compatible model was removed from the catalog during the upload"
+            }));
+        }
+        Err(error) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    let firmware = match sqlx::query_as!(
+        FirmwareModel,
+        r#"
+        INSERT INTO firmwares (id, version, models, checksum, size, notes)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+        "#,
+        id,
+        opts.version,
+        &models,
+        checksum,
+        size as i64,
+        opts.notes
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(firmware) => firmware,
+        Err(error) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "firmware": firmware,
+        })),
+        Err(error) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }))
+        }
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List the firmware catalog, newest first.", body = [FirmwareModel]),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Firmware"
+)]
+#[get("/firmwares", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_all_firmwares(
+    opts: Query<FilterOptions>,
+    data: Data<AppState>
+) -> impl Responder {
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        FirmwareModel,
+        "SELECT * FROM firmwares ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(firmwares) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": firmwares.len(),
+            "firmwares": firmwares,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = CreateCampaignSchema,
+    responses(
+        (status = 200, description = "Start a rollout campaign and enroll its first stage. A campaign targets the devices of one client whose model the firmware fits, optionally only those carrying a tag of that client.", body = OtaCampaignModel),
+        (status = 400, description = "Invalid stages, threshold or tag"),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Firmware or client not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Firmware"
+)]
+#[post("/ota/campaigns", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn create_campaign(
+    body: Json<CreateCampaignSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if !claims.can_access_client(body.client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    let stages = body.stages.clone().unwrap_or_else(|| vec![100]);
+    let increasing = stages.windows(2).all(|pair| pair[0] < pair[1]);
+    if stages.is_empty() || !increasing || stages[0] < 1 || stages.last() != Some(&100) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Stages must be increasing percentages ending at 100"
+        }));
+    }
+
+    let failure_threshold = body.failure_threshold.unwrap_or(DEFAULT_FAILURE_THRESHOLD);
+    if !(0.0..=1.0).contains(&failure_threshold) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "failure_threshold must be between 0 and 1"
+        }));
+    }
+
+    let tag = match body.tag.as_deref().map(normalize_tag) {
+        Some(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Tags must have between 1 and {} characters", MAX_TAG_LENGTH)
+            }));
+        }
+        Some(tag) => tag,
+        None => None,
+    };
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let campaign = match sqlx::query_as!(
+        OtaCampaignModel,
+        r#"
+        INSERT INTO ota_campaigns (firmware_id, client_id, stages, failure_threshold, tag)
+        SELECT f.id, c.id, $3, $4, $5 FROM firmwares f, clients c
+        WHERE f.id = $1 AND c.id = $2
+        RETURNING *
+        "#,
+        body.firmware_id,
+        body.client_id,
+        &stages,
+        failure_threshold,
+        tag
+    )
+    .fetch_optional(&mut tx)
+    .await {
+        Ok(Some(campaign)) => campaign,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Firmware or client not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let enrolled = match enroll_stage(&mut tx, &campaign).await {
+        Ok(enrolled) => enrolled,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "enrolled": enrolled,
+            "campaign": campaign,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get a campaign with its update counts by state.", body = OtaCampaignModel),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Campaign not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Firmware"
+)]
+#[get("/ota/campaigns/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_campaign_by_id(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let campaign_id = path.into_inner();
+
+    let campaign = match sqlx::query_as!(
+        OtaCampaignModel,
+        "SELECT * FROM ota_campaigns WHERE id = $1",
+        campaign_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(campaign) => campaign,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if !claims.can_access_client(campaign.client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    match sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) AS "count!"
+        FROM ota_updates WHERE campaign_id = $1
+        GROUP BY status
+        "#,
+        campaign_id
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(rows) => {
+            let progress: serde_json::Map<String, serde_json::Value> = rows
+                .into_iter()
+                .map(|row| (row.status, json!(row.count)))
+                .collect();
+
+            HttpResponse::Ok().json(json!({
+                "status": "success",
+                "campaign": campaign,
+                "progress": progress,
+            }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List the per-device updates of a campaign.", body = [OtaUpdateModel]),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Campaign not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Firmware"
+)]
+#[get("/ota/campaigns/{id}/updates", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_campaign_updates(
+    path: Path<Uuid>,
+    opts: Query<FilterOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let campaign_id = path.into_inner();
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_scalar!("SELECT client_id FROM ota_campaigns WHERE id = $1", campaign_id)
+        .fetch_one(&data.db)
+        .await
+    {
+        Ok(client_id) if !claims.can_access_client(client_id) => {
+            return HttpResponse::Forbidden().json(json!({
+                "status": "error",
+                "message": "Client not accessible with this token"
+            }));
+        }
+        Ok(_) => (),
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match sqlx::query_as!(
+        OtaUpdateModel,
+        "SELECT * FROM ota_updates WHERE campaign_id = $1 ORDER BY created_at, id LIMIT $2 OFFSET $3",
+        campaign_id,
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(updates) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": updates.len(),
+            "updates": updates,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = UpdateCampaignSchema,
+    responses(
+        (status = 200, description = "Pause, resume or cancel a campaign.", body = OtaCampaignModel),
+        (status = 400, description = "Invalid status"),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Campaign not found"),
+        (status = 409, description = "Campaign already finished"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Firmware"
+)]
+#[patch("/ota/campaigns/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn update_campaign_by_id(
+    path: Path<Uuid>,
+    body: Json<UpdateCampaignSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let campaign_id = path.into_inner();
+
+    if !["active", "paused", "cancelled"].contains(&body.status.as_str()) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Invalid status, use active, paused or cancelled"
+        }));
+    }
+
+    let campaign = match sqlx::query_as!(
+        OtaCampaignModel,
+        "SELECT * FROM ota_campaigns WHERE id = $1",
+        campaign_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(campaign) => campaign,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if !claims.can_access_client(campaign.client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    match sqlx::query_as!(
+        OtaCampaignModel,
+        r#"
+        UPDATE ota_campaigns
+        SET status = $2::VARCHAR,
+            paused_reason = CASE WHEN $2::VARCHAR = 'paused' THEN 'Paused by an operator' END,
+            updated_at = now()
+        WHERE id = $1 AND status IN ('active', 'paused')
+        RETURNING *
+        "#,
+        campaign_id,
+        body.status
+    )
+    .fetch_optional(&data.db)
+    .await {
+        Ok(Some(campaign)) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "campaign": campaign,
+        })),
+        Ok(None) => HttpResponse::Conflict().json(json!({
+            "status": "error",
+            "message": format!("Campaign already {}", campaign.status)
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Move an active campaign to its next stage and enroll the extra devices.", body = OtaCampaignModel),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Campaign not found"),
+        (status = 409, description = "Campaign not active or already at its last stage"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Firmware"
+)]
+#[post("/ota/campaigns/{id}/advance", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn advance_campaign(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let campaign_id = path.into_inner();
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let campaign = match sqlx::query_as!(
+        OtaCampaignModel,
+        "SELECT * FROM ota_campaigns WHERE id = $1 FOR UPDATE",
+        campaign_id
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(campaign) => campaign,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if !claims.can_access_client(campaign.client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    if campaign.status != "active" || campaign.current_stage as usize + 1 >= campaign.stages.len() {
+        return HttpResponse::Conflict().json(json!({
+            "status": "error",
+            "message": "Only active campaigns before their last stage can advance"
+        }));
+    }
+
+    let campaign = match sqlx::query_as!(
+        OtaCampaignModel,
+        "UPDATE ota_campaigns SET current_stage = current_stage + 1, updated_at = now() WHERE id = $1 RETURNING *",
+        campaign_id
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(campaign) => campaign,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let enrolled = match enroll_stage(&mut tx, &campaign).await {
+        Ok(enrolled) => enrolled,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "enrolled": enrolled,
+            "campaign": campaign,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
@@ -0,0 +1,700 @@
+use actix_web::{
+    web::{Json, Path, Data, Query, ReqData},
+    get, post, patch, delete, HttpResponse, Responder,
+};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    schema::{CreateGroupSchema, GroupDevicesOptions, GroupMembersSchema, UpdateGroupSchema},
+    model::{DeviceGroupModel, DeviceModel, PositionModel},
+    AppState, TokenClaims,
+};
+
+pub async fn find_group(db: &Pool<Postgres>, group_id: Uuid) -> Result<Option<DeviceGroupModel>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceGroupModel,
+        "SELECT * FROM device_groups WHERE id = $1",
+        group_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// The group and every group nested below it, at any depth.
+async fn group_subtree(db: &Pool<Postgres>, group_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM device_groups WHERE id = $1
+            UNION
+            SELECT g.id FROM device_groups g JOIN subtree s ON g.parent_id = s.id
+        )
+        SELECT id AS "id!" FROM subtree
+        "#,
+        group_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// The group when the token can read it. Group tokens read their own group
+/// and its subgroups, other tokens every group of the clients they access.
+async fn find_visible_group(
+    db: &Pool<Postgres>,
+    claims: &TokenClaims,
+    group_id: Uuid,
+) -> Result<Option<DeviceGroupModel>, sqlx::Error> {
+    let group = match find_group(db, group_id).await? {
+        Some(group) => group,
+        None => return Ok(None),
+    };
+
+    let visible = match claims.group_id {
+        Some(root) => {
+            claims.client_id == Some(group.client_id)
+                && group_subtree(db, root).await?.contains(&group.id)
+        }
+        None => claims.can_access_client(group.client_id),
+    };
+
+    Ok(visible.then_some(group))
+}
+
+fn group_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({
+        "status": "not found",
+        "message": "Group not found"
+    }))
+}
+
+fn read_only() -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "status": "error",
+        "message": "Group not writable with this token"
+    }))
+}
+
+#[utoipa::path(
+    request_body = CreateGroupSchema,
+    responses(
+        (status = 200, description = "Create a device group in the client, optionally nested in another of its groups.", body = DeviceGroupModel),
+        (status = 400, description = "Empty name or parent group of another client"),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Client not found"),
+        (status = 409, description = "The client already has a group with this name"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[post("/clients/{id}/groups", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn create_group(
+    path: Path<Uuid>,
+    body: Json<CreateGroupSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    let name = body.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "name is required"
+        }));
+    }
+
+    if let Some(parent_id) = body.parent_id {
+        match find_group(&data.db, parent_id).await {
+            Ok(Some(parent)) if parent.client_id == client_id => (),
+            Ok(_) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": "Parent group not found in this client"
+                }));
+            }
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        }
+    }
+
+    match sqlx::query_as!(
+        DeviceGroupModel,
+        "INSERT INTO device_groups (client_id, parent_id, name) VALUES ($1, $2, $3) RETURNING *",
+        client_id,
+        body.parent_id,
+        name
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(group) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "group": group,
+        })),
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "The client already has a group with this name"
+            }))
+        }
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23503") => {
+            HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Client not found"
+            }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List the groups of the client by name. Group tokens only see their group and its subgroups.", body = [DeviceGroupModel]),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[get("/clients/{id}/groups", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn get_client_groups(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    let visible = match claims.group_id {
+        Some(_) => claims.client_id == Some(client_id),
+        None => claims.can_access_client(client_id),
+    };
+    if !visible {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    match sqlx::query_as!(
+        DeviceGroupModel,
+        r#"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM device_groups WHERE id = $2
+            UNION
+            SELECT g.id FROM device_groups g JOIN subtree s ON g.parent_id = s.id
+        )
+        SELECT * FROM device_groups
+        WHERE client_id = $1 AND ($2::UUID IS NULL OR id IN (SELECT id FROM subtree))
+        ORDER BY name
+        "#,
+        client_id,
+        claims.group_id
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(groups) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": groups.len(),
+            "groups": groups,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get a device group.", body = DeviceGroupModel),
+        (status = 404, description = "Group not found or not visible with this token"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[get("/device-groups/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn get_group_by_id(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    match find_visible_group(&data.db, &claims, path.into_inner()).await {
+        Ok(Some(group)) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "group": group,
+        })),
+        Ok(None) => group_not_found(),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = UpdateGroupSchema,
+    responses(
+        (status = 200, description = "Rename a group or move it under another group of the client, `parent_id: null` makes it top-level.", body = DeviceGroupModel),
+        (status = 400, description = "Empty name, parent group of another client, or a move into its own subgroups"),
+        (status = 403, description = "Group not writable with this token"),
+        (status = 404, description = "Group not found"),
+        (status = 409, description = "The client already has a group with this name"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[patch("/device-groups/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn update_group_by_id(
+    path: Path<Uuid>,
+    body: Json<UpdateGroupSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let group_id = path.into_inner();
+
+    let name = body.name.as_deref().map(str::trim);
+    if name.is_some_and(str::is_empty) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "name cannot be empty"
+        }));
+    }
+
+    let group = match find_group(&data.db, group_id).await {
+        Ok(Some(group)) => group,
+        Ok(None) => return group_not_found(),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+    if !claims.can_access_client(group.client_id) {
+        return read_only();
+    }
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    // Moves within a client are serialized so two of them cannot close a cycle together
+    if let Err(error) = sqlx::query!("SELECT id FROM clients WHERE id = $1 FOR UPDATE", group.client_id)
+        .fetch_one(&mut tx)
+        .await
+    {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    if let Some(Some(parent_id)) = body.parent_id {
+        match sqlx::query!(
+            r#"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM device_groups WHERE id = $1
+                UNION
+                SELECT g.id FROM device_groups g JOIN subtree s ON g.parent_id = s.id
+            )
+            SELECT
+                EXISTS (SELECT 1 FROM device_groups WHERE id = $2 AND client_id = $3) AS "same_client!",
+                EXISTS (SELECT 1 FROM subtree WHERE id = $2) AS "cycle!"
+            "#,
+            group_id,
+            parent_id,
+            group.client_id
+        )
+        .fetch_one(&mut tx)
+        .await {
+            Ok(parent) if !parent.same_client => {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": "Parent group not found in this client"
+                }));
+            }
+            Ok(parent) if parent.cycle => {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": "A group cannot be moved into itself or its subgroups"
+                }));
+            }
+            Ok(_) => (),
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        }
+    }
+
+    let group = match sqlx::query_as!(
+        DeviceGroupModel,
+        r#"
+        UPDATE device_groups
+        SET name = COALESCE($1, name),
+            parent_id = CASE WHEN $2 THEN $3 ELSE parent_id END
+        WHERE id = $4
+        RETURNING *
+        "#,
+        name,
+        body.parent_id.is_some(),
+        body.parent_id.flatten(),
+        group_id
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(group) => group,
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            return HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "The client already has a group with this name"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "group": group,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 204, description = "Delete a group, its devices stay in the client."),
+        (status = 403, description = "Group not writable with this token"),
+        (status = 404, description = "Group not found"),
+        (status = 409, description = "The group still has subgroups"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[delete("/device-groups/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn delete_group_by_id(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let group_id = path.into_inner();
+
+    match find_group(&data.db, group_id).await {
+        Ok(Some(group)) if claims.can_access_client(group.client_id) => (),
+        Ok(Some(_)) => return read_only(),
+        Ok(None) => return group_not_found(),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match sqlx::query!("DELETE FROM device_groups WHERE id = $1", group_id)
+        .execute(&data.db)
+        .await
+    {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23503") => {
+            HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "Move or delete the subgroups first"
+            }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = GroupMembersSchema,
+    responses(
+        (status = 200, description = "Add devices of the group's client to the group, devices already in it are kept."),
+        (status = 400, description = "Devices not found in the group's client, nothing added"),
+        (status = 403, description = "Group not writable with this token"),
+        (status = 404, description = "Group not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[post("/device-groups/{id}/devices", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn add_group_devices(
+    path: Path<Uuid>,
+    body: Json<GroupMembersSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let group = match find_group(&data.db, path.into_inner()).await {
+        Ok(Some(group)) if claims.can_access_client(group.client_id) => group,
+        Ok(Some(_)) => return read_only(),
+        Ok(None) => return group_not_found(),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let found = match sqlx::query_scalar!(
+        "SELECT id FROM devices WHERE id = ANY($1) AND client_id = $2",
+        &body.device_ids,
+        group.client_id
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(found) => found,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let missing: Vec<&Uuid> = body.device_ids.iter().filter(|id| !found.contains(id)).collect();
+    if !missing.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Devices not found in the group's client, nothing added",
+            "device_ids": missing,
+        }));
+    }
+
+    match sqlx::query!(
+        r#"
+        INSERT INTO device_group_members (group_id, device_id)
+        SELECT $1, UNNEST($2::UUID[])
+        ON CONFLICT DO NOTHING
+        "#,
+        group.id,
+        &found
+    )
+    .execute(&data.db)
+    .await {
+        Ok(result) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "added": result.rows_affected(),
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 204, description = "Remove a device from the group."),
+        (status = 403, description = "Group not writable with this token"),
+        (status = 404, description = "Group not found or device not in it"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[delete("/device-groups/{id}/devices/{device_id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn remove_group_device(
+    path: Path<(Uuid, Uuid)>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let (group_id, device_id) = path.into_inner();
+
+    match find_group(&data.db, group_id).await {
+        Ok(Some(group)) if claims.can_access_client(group.client_id) => (),
+        Ok(Some(_)) => return read_only(),
+        Ok(None) => return group_not_found(),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match sqlx::query!(
+        "DELETE FROM device_group_members WHERE group_id = $1 AND device_id = $2",
+        group_id,
+        device_id
+    )
+    .execute(&data.db)
+    .await {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": "Device not in this group"
+        })),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("limit" = Option<usize>, Query, description = "Page size"),
+        ("page" = Option<usize>, Query, description = "Page number"),
+        ("recursive" = Option<bool>, Query, description = "Include the devices of subgroups, true by default")
+    ),
+    responses(
+        (status = 200, description = "List the devices of the group.", body = [DeviceModel]),
+        (status = 404, description = "Group not found or not visible with this token"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[get("/device-groups/{id}/devices", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn get_group_devices(
+    path: Path<Uuid>,
+    opts: Query<GroupDevicesOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let group = match find_visible_group(&data.db, &claims, path.into_inner()).await {
+        Ok(Some(group)) => group,
+        Ok(None) => return group_not_found(),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1).max(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        DeviceModel,
+        r#"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM device_groups WHERE id = $1
+            UNION
+            SELECT g.id FROM device_groups g JOIN subtree s ON g.parent_id = s.id AND $2
+        )
+        SELECT * FROM devices
+        WHERE id IN (
+            SELECT m.device_id FROM device_group_members m JOIN subtree s ON s.id = m.group_id
+        )
+        ORDER BY created_at DESC
+        LIMIT $3 OFFSET $4
+        "#,
+        group.id,
+        opts.recursive.unwrap_or(true),
+        limit as i64,
+        offset as i64
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(devices) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": devices.len(),
+            "devices": devices,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Last known position of every device in the group and its subgroups, devices without positions are left out.", body = [PositionModel]),
+        (status = 404, description = "Group not found or not visible with this token"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Grupos"
+)]
+#[get("/device-groups/{id}/positions", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::group_validator)")]
+pub async fn get_group_positions(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let group = match find_visible_group(&data.db, &claims, path.into_inner()).await {
+        Ok(Some(group)) => group,
+        Ok(None) => return group_not_found(),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match sqlx::query_as!(
+        PositionModel,
+        r#"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM device_groups WHERE id = $1
+            UNION
+            SELECT g.id FROM device_groups g JOIN subtree s ON g.parent_id = s.id
+        )
+        SELECT DISTINCT ON (device_id) * FROM positions
+        WHERE device_id IN (
+            SELECT m.device_id FROM device_group_members m JOIN subtree s ON s.id = m.group_id
+        )
+        ORDER BY device_id, recorded_at DESC
+        "#,
+        group.id
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(positions) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": positions.len(),
+            "positions": positions,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
@@ -0,0 +1,130 @@
+use actix_web::{
+    web::{Bytes, Data, Path},
+    post, HttpRequest, HttpResponse, Responder,
+};
+use serde_json::json;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    gateways::nmea::{date_reference, parse_sentences},
+    model::PositionModel,
+    device_auth::authenticate_device,
+    positions::store_positions,
+    schema::TelemetryPayloadSchema,
+    telemetry::{invalid_metric, store_telemetry},
+    AppState,
+};
+
+#[utoipa::path(
+    params(
+        ("imei" = String, Path, description = "IMEI of the reporting device"),
+        ("X-Device-Timestamp" = i64, Header, description = "Unix time the request was signed at"),
+        ("X-Device-Signature" = String, Header, description = "Hex HMAC-SHA256 of `{method}\\n{path}\\n{imei}\\n{timestamp}\\n{body}` keyed with SHA-256 of the device secret")
+    ),
+    request_body(content = String, content_type = "text/plain", description = "NMEA 0183 sentences ($GPRMC, $GPGGA, $GPVTG), one per line"),
+    responses(
+        (status = 200, description = "Positions stored from the sentences.", body = [PositionModel]),
+        (status = 400, description = "Invalid payload"),
+        (status = 401, description = "Invalid device signature or replayed request"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    tag = "Ingestão"
+)]
+#[post("/ingest/nmea/{imei}")]
+pub async fn ingest_nmea(
+    req: HttpRequest,
+    path: Path<String>,
+    body: Bytes,
+    data: Data<AppState>
+) -> impl Responder {
+    let imei = path.into_inner();
+
+    let device = match authenticate_device(&req, &data.db, &imei, &body).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    let text = match std::str::from_utf8(&body) {
+        Ok(text) => text,
+        Err(_) => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": "Sentences must be UTF-8 text"
+            }));
+        }
+    };
+
+    let batch = parse_sentences(text, date_reference(&device));
+
+    match store_positions(&data.db, &data.events, &device, &batch.positions).await {
+        Ok(positions) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": positions.len(),
+            "rejected": batch.rejected,
+            "positions": positions,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("imei" = String, Path, description = "IMEI of the reporting device"),
+        ("X-Device-Timestamp" = i64, Header, description = "Unix time the request was signed at"),
+        ("X-Device-Signature" = String, Header, description = "Hex HMAC-SHA256 of `{method}\\n{path}\\n{imei}\\n{timestamp}\\n{body}` keyed with SHA-256 of the device secret")
+    ),
+    request_body = [TelemetryPayloadSchema],
+    responses(
+        (status = 200, description = "Number of readings stored."),
+        (status = 400, description = "Invalid payload or metric name"),
+        (status = 401, description = "Invalid device signature or replayed request"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    tag = "Ingestão"
+)]
+#[post("/ingest/telemetry/{imei}")]
+pub async fn ingest_telemetry(
+    req: HttpRequest,
+    path: Path<String>,
+    body: Bytes,
+    data: Data<AppState>
+) -> impl Responder {
+    let imei = path.into_inner();
+
+    let device = match authenticate_device(&req, &data.db, &imei, &body).await {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+
+    let readings = match serde_json::from_slice::<Vec<TelemetryPayloadSchema>>(&body) {
+        Ok(readings) => readings,
+        Err(error) => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Invalid payload: {}", error)
+            }));
+        }
+    };
+
+    if let Some(metric) = invalid_metric(&readings) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("Invalid metric name {:?}, use 1 to 100 letters, digits, '_', '.' or '-'", metric)
+        }));
+    }
+
+    match store_telemetry(&data.db, device.id, &readings).await {
+        Ok(stored) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": stored,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashSet;
+
+use actix_web::{
+    web::{Json, Path, Data, Query, ReqData},
+    get, post, HttpResponse, Responder,
+};
+use chrono::Utc;
+use rand::Rng;
+use serde_json::json;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    device_auth::issue_secret,
+    plans::lock_device_quota,
+    events::FleetEventKind,
+    schema::{ClaimDeviceSchema, CreateInventoryDeviceSchema, InventoryFilterOptions},
+    model::{ClientModel, DeviceModel, InventoryDeviceModel},
+    services::{device_nickname, resolve_model_name},
+    tenant,
+    AppState, TokenClaims,
+};
+
+/// Letters and digits that cannot be mistaken for each other on a printed label.
+const CLAIM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CLAIM_CODE_LENGTH: usize = 10;
+
+fn generate_claim_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..CLAIM_CODE_LENGTH)
+        .map(|_| CLAIM_CODE_ALPHABET[rng.gen_range(0..CLAIM_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Codes are compared without the dashes and spaces used to print them.
+fn normalize_claim_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[utoipa::path(
+    request_body = [CreateInventoryDeviceSchema],
+    responses(
+        (status = 200, description = "Load unclaimed devices into the factory inventory.", body = [InventoryDeviceModel]),
+        (status = 400, description = "Empty batch, unknown model or repeated IMEI / claim code"),
+        (status = 403, description = "Only operators can load the inventory"),
+        (status = 409, description = "IMEI or claim code already registered"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Inventário"
+)]
+#[post("/inventory", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn create_inventory_devices(
+    body: Json<Vec<CreateInventoryDeviceSchema>>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if claims.client_id.is_some() {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Only operators can load the inventory"
+        }));
+    }
+
+    if body.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "No devices to load"
+        }));
+    }
+
+    let imeis: Vec<String> = body.iter().map(|item| item.imei.clone()).collect();
+    let mut models = Vec::with_capacity(body.len());
+    for item in body.iter() {
+        match resolve_model_name(&data.db, &item.model).await {
+            Ok(Some(model)) => models.push(model),
+            Ok(None) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": format!("Unknown device model {}, add it to the catalog first", item.model)
+                }));
+            }
+            Err(error) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "status": "error",
+                    "message": format!("{:?}", error)
+                }));
+            }
+        }
+    }
+    let serial_numbers: Vec<String> = body.iter().map(|item| item.serial_number.clone()).collect();
+    let claim_codes: Vec<String> = body
+        .iter()
+        .map(|item| match &item.claim_code {
+            Some(code) => normalize_claim_code(code),
+            None => generate_claim_code(),
+        })
+        .collect();
+
+    if claim_codes.iter().any(|code| code.is_empty()) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Claim codes must contain letters or digits"
+        }));
+    }
+
+    if imeis.iter().collect::<HashSet<_>>().len() != imeis.len()
+        || claim_codes.iter().collect::<HashSet<_>>().len() != claim_codes.len()
+    {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Repeated IMEI or claim code in the batch"
+        }));
+    }
+
+    // Devices of every client, the IMEI is unique across them
+    let taken = match tenant::system(
+        sqlx::query_scalar!(
+            r#"
+            SELECT imei AS "imei!" FROM devices WHERE imei = ANY($1)
+            UNION
+            SELECT imei FROM device_inventory WHERE imei = ANY($1)
+            UNION
+            SELECT claim_code FROM device_inventory WHERE claim_code = ANY($2)
+            "#,
+            &imeis,
+            &claim_codes
+        )
+        .fetch_all(&data.db),
+    )
+    .await {
+        Ok(taken) => taken,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if !taken.is_empty() {
+        return HttpResponse::Conflict().json(json!({
+            "status": "error",
+            "message": "IMEI or claim code already registered",
+            "conflicts": taken,
+        }));
+    }
+
+    match sqlx::query_as!(
+        InventoryDeviceModel,
+        r#"
+        INSERT INTO device_inventory (imei, model, serial_number, claim_code)
+        SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::VARCHAR[], $4::VARCHAR[])
+        RETURNING *
+        "#,
+        &imeis,
+        &models,
+        &serial_numbers,
+        &claim_codes
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(devices) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": devices.len(),
+            "devices": devices,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("limit" = Option<usize>, Query, description = "Page size"),
+        ("page" = Option<usize>, Query, description = "Page number"),
+        ("claimed" = Option<bool>, Query, description = "Only claimed or only unclaimed devices")
+    ),
+    responses(
+        (status = 200, description = "List the factory inventory.", body = [InventoryDeviceModel]),
+        (status = 403, description = "Only operators can read the inventory"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Inventário"
+)]
+#[get("/inventory", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_inventory_devices(
+    opts: Query<InventoryFilterOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if claims.client_id.is_some() {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Only operators can read the inventory"
+        }));
+    }
+
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        InventoryDeviceModel,
+        r#"
+        SELECT * FROM device_inventory
+        WHERE $1::BOOLEAN IS NULL OR (device_id IS NOT NULL) = $1
+        ORDER BY created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        opts.claimed,
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(devices) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": devices.len(),
+            "devices": devices,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Client claiming the device")
+    ),
+    request_body = ClaimDeviceSchema,
+    responses(
+        (status = 200, description = "Attach an inventory device to the client. The response carries its signing secret, which is not shown again.", body = DeviceModel),
+        (status = 402, description = "The client's plan allows no more devices"),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Client not found or claim code unknown or already used"),
+        (status = 409, description = "The IMEI of the unit is already registered to a device"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Inventário"
+)]
+#[post("/clients/{id}/devices/claim", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn claim_device(
+    path: Path<Uuid>,
+    body: Json<ClaimDeviceSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    let client = match sqlx::query_as!(
+        ClientModel,
+        "SELECT * FROM clients WHERE id = $1",
+        client_id
+    )
+    .fetch_one(&data.db)
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match lock_device_quota(&mut tx, client.id).await {
+        Ok(Some(quota)) if !quota.allows(1) => return quota.exceeded(1),
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Client not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    // Locks the unit so two clients cannot claim it at once
+    let unit = match sqlx::query_as!(
+        InventoryDeviceModel,
+        "SELECT * FROM device_inventory WHERE claim_code = $1 AND device_id IS NULL FOR UPDATE",
+        normalize_claim_code(&body.claim_code)
+    )
+    .fetch_optional(&mut tx)
+    .await {
+        Ok(Some(unit)) => unit,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Claim code unknown or already used"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    // Never reported yet, the upload times start at the claim
+    let now = Utc::now();
+
+    let device = match sqlx::query_as!(
+        DeviceModel,
+        r#"
+        INSERT INTO devices
+            (client_id, nickname, imei, model, serial_number, upload_data, upload_gps, status)
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $6, 'active')
+        RETURNING *
+        "#,
+        client.id,
+        device_nickname(&client, &unit.serial_number),
+        unit.imei,
+        unit.model,
+        unit.serial_number,
+        now
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(device) => device,
+        // unique_violation on imei, a device of some client already has it
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            return HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "The IMEI of the unit is already registered to a device"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if let Err(error) = sqlx::query!(
+        "UPDATE device_inventory SET device_id = $1, claimed_at = $2 WHERE id = $3",
+        device.id,
+        now,
+        unit.id
+    )
+    .execute(&mut tx)
+    .await
+    {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    let secret = match issue_secret(&mut tx, device.id).await {
+        Ok(secret) => secret,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if let Err(error) = tx.commit().await {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    data.events.publish(FleetEventKind::DeviceCreated, device.client_id, device.id, &device);
+
+    HttpResponse::Ok().json(json!({
+        "status": "success",
+        "device": device,
+        // Shown once, only the salt it is derived from is stored
+        "secret": secret,
+    }))
+}
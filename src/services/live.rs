@@ -0,0 +1,290 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use actix::{Actor, ActorContext, ActorFutureExt, AsyncContext, StreamHandler, WrapFuture};
+use actix_web::{
+    http::header,
+    web::{Data, Payload, Query},
+    get, Error, HttpRequest, HttpResponse,
+};
+use actix_web_actors::ws;
+use futures_util::stream;
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    auth::{decode_token, load_client_scope},
+    events::FleetEvent,
+    schema::{LiveCommandSchema, LiveOptions},
+    tenant, AppState, TokenClaims,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Times a session may fall behind the event hub before it is disconnected.
+const MAX_LAGS: u32 = 3;
+
+enum LiveUpdate {
+    Event(FleetEvent),
+    Lagged(u64),
+}
+
+struct LiveSession {
+    db: Pool<Postgres>,
+    claims: TokenClaims,
+    receiver: Option<broadcast::Receiver<FleetEvent>>,
+    device_ids: HashSet<Uuid>,
+    client_ids: HashSet<Uuid>,
+    heartbeat: Instant,
+    lags: u32,
+}
+
+impl LiveSession {
+    fn is_subscribed(&self, event: &FleetEvent) -> bool {
+        self.claims.can_access_client(event.client_id)
+            && (self.device_ids.contains(&event.device_id)
+                || self.client_ids.contains(&event.client_id))
+    }
+
+    fn handle_command(&mut self, command: LiveCommandSchema, ctx: &mut ws::WebsocketContext<Self>) {
+        match command {
+            LiveCommandSchema::Subscribe { device_ids, client_ids } => {
+                let (allowed, denied): (Vec<Uuid>, Vec<Uuid>) = client_ids
+                    .into_iter()
+                    .partition(|id| self.claims.can_access_client(*id));
+
+                if !denied.is_empty() {
+                    ctx.text(json!({
+                        "type": "error",
+                        "message": "Client not accessible with this token",
+                        "client_ids": denied,
+                    }).to_string());
+                }
+
+                self.client_ids.extend(allowed.iter().copied());
+
+                // The devices' clients are looked up before anything else the
+                // session receives, ids the token cannot see are refused
+                let db = self.db.clone();
+                let lookup = tenant::system(async move {
+                    let devices = sqlx::query!(
+                        "SELECT id, client_id FROM devices WHERE id = ANY($1)",
+                        &device_ids
+                    )
+                    .fetch_all(&db)
+                    .await;
+                    (device_ids, devices)
+                });
+
+                ctx.wait(lookup.into_actor(self).map(move |(device_ids, devices), session, ctx| {
+                    let visible: HashSet<Uuid> = match devices {
+                        Ok(devices) => devices
+                            .into_iter()
+                            .filter(|device| session.claims.can_access_client(device.client_id))
+                            .map(|device| device.id)
+                            .collect(),
+                        Err(error) => {
+                            log::error!("Live subscription lookup failed: {:?}", error);
+                            HashSet::new()
+                        }
+                    };
+                    let (device_ids, denied): (Vec<Uuid>, Vec<Uuid>) =
+                        device_ids.into_iter().partition(|id| visible.contains(id));
+
+                    if !denied.is_empty() {
+                        ctx.text(json!({
+                            "type": "error",
+                            "message": "Device not found or not accessible with this token",
+                            "device_ids": denied,
+                        }).to_string());
+                    }
+
+                    session.device_ids.extend(device_ids.iter().copied());
+
+                    ctx.text(json!({
+                        "type": "subscribed",
+                        "device_ids": device_ids,
+                        "client_ids": allowed,
+                    }).to_string());
+                }));
+            }
+            LiveCommandSchema::Unsubscribe { device_ids, client_ids } => {
+                for id in &device_ids {
+                    self.device_ids.remove(id);
+                }
+                for id in &client_ids {
+                    self.client_ids.remove(id);
+                }
+
+                ctx.text(json!({
+                    "type": "unsubscribed",
+                    "device_ids": device_ids,
+                    "client_ids": client_ids,
+                }).to_string());
+            }
+        }
+    }
+}
+
+impl Actor for LiveSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |session, ctx| {
+            if Instant::now().duration_since(session.heartbeat) > CLIENT_TIMEOUT {
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+
+        if let Some(receiver) = self.receiver.take() {
+            ctx.add_stream(stream::unfold(receiver, |mut receiver| async move {
+                match receiver.recv().await {
+                    Ok(event) => Some((LiveUpdate::Event(event), receiver)),
+                    Err(RecvError::Lagged(skipped)) => Some((LiveUpdate::Lagged(skipped), receiver)),
+                    Err(RecvError::Closed) => None,
+                }
+            }));
+        }
+    }
+}
+
+// The session is only polled while the socket accepts writes, so a slow
+// consumer stops draining its receiver and falls behind the hub instead of
+// buffering without bound on the server.
+impl StreamHandler<LiveUpdate> for LiveSession {
+    fn handle(&mut self, update: LiveUpdate, ctx: &mut Self::Context) {
+        match update {
+            LiveUpdate::Event(event) => {
+                if self.is_subscribed(&event) {
+                    ctx.text(json!({
+                        "type": event.kind.as_str(),
+                        "id": event.id,
+                        "client_id": event.client_id,
+                        "device_id": event.device_id,
+                        "created_at": event.created_at,
+                        "data": event.data,
+                    }).to_string());
+                }
+            }
+            LiveUpdate::Lagged(skipped) => {
+                self.lags += 1;
+
+                if self.lags > MAX_LAGS {
+                    ctx.close(Some(ws::CloseReason {
+                        code: ws::CloseCode::Again,
+                        description: Some("Slow consumer".to_string()),
+                    }));
+                    ctx.stop();
+                    return;
+                }
+
+                ctx.text(json!({
+                    "type": "lagged",
+                    "skipped": skipped,
+                }).to_string());
+            }
+        }
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for LiveSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(_) => {
+                ctx.stop();
+                return;
+            }
+        };
+
+        match msg {
+            ws::Message::Ping(payload) => {
+                self.heartbeat = Instant::now();
+                ctx.pong(&payload);
+            }
+            ws::Message::Pong(_) => {
+                self.heartbeat = Instant::now();
+            }
+            ws::Message::Text(text) => match serde_json::from_str::<LiveCommandSchema>(&text) {
+                Ok(command) => self.handle_command(command, ctx),
+                Err(error) => ctx.text(json!({
+                    "type": "error",
+                    "message": format!("Invalid command: {}", error),
+                }).to_string()),
+            },
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => (),
+        }
+    }
+}
+
+#[utoipa::path(
+    params(
+        ("token" = Option<String>, Query, description = "JWT, for clients that cannot send the Authorization header")
+    ),
+    responses(
+        (status = 101, description = "WebSocket upgrade. Send `{\"action\": \"subscribe\", \"device_ids\": [...], \"client_ids\": [...]}` to receive position and status updates, ids the token cannot see are answered with an error.", body = LiveCommandSchema),
+        (status = 401, description = "Missing or invalid token.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Tempo real"
+)]
+#[get("/live/ws")]
+pub async fn live_tracking(
+    req: HttpRequest,
+    payload: Payload,
+    opts: Query<LiveOptions>,
+    data: Data<AppState>
+) -> Result<HttpResponse, Error> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or_else(|| opts.token.clone());
+
+    let mut claims = match token.as_deref().map(|token| decode_token(token, &data.jwt_secret)) {
+        Some(Ok(claims)) => claims,
+        _ => {
+            return Ok(HttpResponse::Unauthorized().json(json!({
+                "status": "error",
+                "message": "Missing or invalid token"
+            })));
+        }
+    };
+
+    if let Err(error) = load_client_scope(&data.db, &mut claims).await {
+        return Ok(HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })));
+    }
+
+    let (_, receiver) = data.events.subscribe(None);
+
+    ws::start(
+        LiveSession {
+            db: data.db.clone(),
+            claims,
+            receiver: Some(receiver),
+            device_ids: HashSet::new(),
+            client_ids: HashSet::new(),
+            heartbeat: Instant::now(),
+            lags: 0,
+        },
+        &req,
+        payload,
+    )
+}
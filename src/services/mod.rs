@@ -2,10 +2,50 @@ use actix_web::{web::{scope, ServiceConfig}, get, HttpResponse, Responder};
 use serde_json::json;
 
 pub mod clients;
+pub mod commands;
+pub mod configs;
+pub mod device_bulk;
+pub mod device_gateway;
+pub mod device_import;
+pub mod device_models;
 pub mod devices;
+pub mod exports;
+pub mod firmware;
+pub mod groups;
+pub mod ingest;
+pub mod inventory;
+pub mod live;
+pub mod plans;
+pub mod sim_cards;
+pub mod stream;
+pub mod summary;
+pub mod tags;
+pub mod telemetry;
+pub mod trips;
+pub mod usage;
 
 pub use clients::*;
+pub use commands::*;
+pub use configs::*;
+pub use device_bulk::*;
+pub use device_gateway::*;
+pub use device_import::*;
+pub use device_models::*;
 pub use devices::*;
+pub use exports::*;
+pub use firmware::*;
+pub use groups::*;
+pub use ingest::*;
+pub use inventory::*;
+pub use live::*;
+pub use plans::*;
+pub use sim_cards::*;
+pub use stream::*;
+pub use summary::*;
+pub use tags::*;
+pub use telemetry::*;
+pub use trips::*;
+pub use usage::*;
 
 /// Health check endpoint
 #[utoipa::path(
@@ -27,17 +67,99 @@ pub fn config(conf: &mut ServiceConfig) {
     let scope = scope("/api")
         .service(health_checker)
         // clientes
+        // rotas fixas antes de /clients/{id}
+        .service(export_clients)
         .service(create_client)
         .service(get_all_clients)
         .service(get_client_by_id)
         .service(update_client_by_id)
         .service(delete_client_by_id)
+        .service(get_client_tags)
+        .service(set_client_metadata_schema)
+        .service(delete_client_metadata_schema)
+        .service(get_client_quota)
+        .service(get_client_usage)
+        .service(get_client_summary)
         // devices
+        // rotas fixas antes de /devices/{id}
+        .service(get_devices_without_sim)
+        .service(export_devices)
+        .service(bulk_devices)
+        .service(get_bulk_job)
         .service(create_device)
         .service(get_all_devices)
         .service(get_device_by_id)
         .service(update_device_by_id)
-        .service(delete_device_by_id);
+        .service(delete_device_by_id)
+        .service(rotate_device_secret)
+        .service(import_devices)
+        .service(get_device_tags)
+        .service(set_device_tags)
+        // grupos
+        .service(create_group)
+        .service(get_client_groups)
+        .service(get_group_by_id)
+        .service(update_group_by_id)
+        .service(delete_group_by_id)
+        .service(add_group_devices)
+        .service(remove_group_device)
+        .service(get_group_devices)
+        .service(get_group_positions)
+        // sim cards
+        .service(create_sim_card)
+        .service(get_all_sim_cards)
+        .service(get_sim_cards_on_retired_devices)
+        .service(get_sim_card_by_id)
+        .service(update_sim_card_by_id)
+        .service(delete_sim_card_by_id)
+        .service(assign_sim_card)
+        .service(unassign_sim_card)
+        .service(get_sim_card_history)
+        // modelos
+        .service(create_device_model)
+        .service(get_all_device_models)
+        .service(get_device_model_by_id)
+        .service(update_device_model_by_id)
+        .service(delete_device_model_by_id)
+        // planos
+        .service(create_plan)
+        .service(get_all_plans)
+        .service(get_plan_by_id)
+        .service(update_plan_by_id)
+        .service(delete_plan_by_id)
+        // inventário
+        .service(create_inventory_devices)
+        .service(get_inventory_devices)
+        .service(claim_device)
+        // comandos
+        .service(create_command)
+        .service(get_device_commands)
+        .service(get_command_by_id)
+        .service(update_device_config)
+        .service(get_device_config)
+        // gateway http
+        .service(get_pending_work)
+        .service(acknowledge_work)
+        .service(download_firmware)
+        .service(report_firmware_progress)
+        // firmware
+        .service(upload_firmware)
+        .service(get_all_firmwares)
+        .service(create_campaign)
+        .service(get_campaign_by_id)
+        .service(get_campaign_updates)
+        .service(update_campaign_by_id)
+        .service(advance_campaign)
+        // ingestão
+        .service(ingest_nmea)
+        .service(ingest_telemetry)
+        // telemetria
+        .service(get_device_telemetry)
+        // viagens
+        .service(get_device_trips)
+        // tempo real
+        .service(stream_devices)
+        .service(live_tracking);
 
     conf.service(scope);
 }
@@ -0,0 +1,381 @@
+use actix_web::{
+    web::{Json, Path, Data, Query, ReqData},
+    get, post, patch, delete, HttpResponse, Responder,
+};
+use serde_json::json;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    plans::{unknown_features, FEATURES},
+    schema::{CreatePlanSchema, FilterOptions, UpdatePlanSchema},
+    model::PlanModel,
+    AppState, TokenClaims,
+};
+
+fn operators_only() -> HttpResponse {
+    HttpResponse::Forbidden().json(json!({
+        "status": "error",
+        "message": "Plans are managed with operator tokens"
+    }))
+}
+
+/// The reason the plan limits are rejected, if any.
+fn invalid_limits(max_devices: Option<i32>, retention_days: i32, features: &[String]) -> Option<String> {
+    if max_devices.is_some_and(|max| max < 0) {
+        return Some("max_devices cannot be negative".to_string());
+    }
+    if retention_days < 1 {
+        return Some("retention_days must be at least 1".to_string());
+    }
+    let unknown = unknown_features(features);
+    if !unknown.is_empty() {
+        return Some(format!(
+            "Unknown features {}, use {}",
+            unknown.join(", "),
+            FEATURES.join(", ")
+        ));
+    }
+    None
+}
+
+#[utoipa::path(
+    request_body = CreatePlanSchema,
+    responses(
+        (status = 200, description = "Add a subscription plan.", body = PlanModel),
+        (status = 400, description = "Negative max_devices, retention_days below 1 or unknown feature"),
+        (status = 409, description = "A plan with this name exists"),
+        (status = 403, description = "Not an operator token"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Planos"
+)]
+#[post("/plans", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn create_plan(
+    body: Json<CreatePlanSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if !claims.is_operator() {
+        return operators_only();
+    }
+
+    let mut features = body.features.clone().unwrap_or_default();
+    features.sort();
+    features.dedup();
+
+    if let Some(message) = invalid_limits(body.max_devices, body.retention_days, &features) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": message
+        }));
+    }
+
+    match sqlx::query_as!(
+        PlanModel,
+        r#"
+        INSERT INTO plans (name, max_devices, retention_days, features)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#,
+        body.name,
+        body.max_devices,
+        body.retention_days,
+        &features
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(plan) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "plan": plan,
+        })),
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "A plan with this name exists"
+            }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List the subscription plans.", body = [PlanModel]),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Planos"
+)]
+#[get("/plans", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_all_plans(
+    opts: Query<FilterOptions>,
+    data: Data<AppState>
+) -> impl Responder {
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        PlanModel,
+        "SELECT * FROM plans ORDER BY name LIMIT $1 OFFSET $2",
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(plans) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": plans.len(),
+            "plans": plans,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get plan by ID.", body = PlanModel),
+        (status = 404, description = "Plan not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Planos"
+)]
+#[get("/plans/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_plan_by_id(
+    path: Path<Uuid>,
+    data: Data<AppState>
+) -> impl Responder {
+    let plan_id = path.into_inner();
+
+    match sqlx::query_as!(
+        PlanModel,
+        "SELECT * FROM plans WHERE id = $1",
+        plan_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(plan) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "plan": plan,
+        })),
+        Err(error) => HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = UpdatePlanSchema,
+    responses(
+        (status = 200, description = "Update plan by ID. Clients already above a lowered limit keep their devices but cannot add more.", body = PlanModel),
+        (status = 400, description = "Negative max_devices, retention_days below 1 or unknown feature"),
+        (status = 404, description = "Plan not found"),
+        (status = 403, description = "Not an operator token"),
+        (status = 409, description = "A plan with this name exists"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Planos"
+)]
+#[patch("/plans/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn update_plan_by_id(
+    path: Path<Uuid>,
+    body: Json<UpdatePlanSchema>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if !claims.is_operator() {
+        return operators_only();
+    }
+
+    let plan_id = path.into_inner();
+
+    let plan = match sqlx::query_as!(
+        PlanModel,
+        "SELECT * FROM plans WHERE id = $1",
+        plan_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(plan) => plan,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let max_devices = body.max_devices.unwrap_or(plan.max_devices);
+    let retention_days = body.retention_days.unwrap_or(plan.retention_days);
+    let mut features = body.features.clone().unwrap_or(plan.features);
+    features.sort();
+    features.dedup();
+
+    if let Some(message) = invalid_limits(max_devices, retention_days, &features) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": message
+        }));
+    }
+
+    match sqlx::query_as!(
+        PlanModel,
+        r#"
+        UPDATE plans
+        SET name = $1, max_devices = $2, retention_days = $3, features = $4
+        WHERE id = $5
+        RETURNING *
+        "#,
+        body.name.clone().unwrap_or(plan.name),
+        max_devices,
+        retention_days,
+        &features,
+        plan_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(plan) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "plan": plan,
+        })),
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "A plan with this name exists"
+            }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 204, description = "Delete plan by ID."),
+        (status = 403, description = "Not an operator token"),
+        (status = 409, description = "Clients are still on the plan"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Planos"
+)]
+#[delete("/plans/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn delete_plan_by_id(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    if !claims.is_operator() {
+        return operators_only();
+    }
+
+    let plan_id = path.into_inner();
+
+    match sqlx::query!(
+        "DELETE FROM plans WHERE id = $1",
+        plan_id
+    )
+    .execute(&data.db)
+    .await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        // foreign_key_violation
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23503") => {
+            HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "Clients are still on the plan"
+            }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Devices of the client against the limit of its plan, with the plan's retention and features."),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Client not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Clientes"
+)]
+#[get("/clients/{id}/quota", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_client_quota(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    let plan = match sqlx::query_as!(
+        PlanModel,
+        r#"
+        SELECT p.* FROM plans p
+        JOIN clients c ON c.plan_id = p.id
+        WHERE c.id = $1
+        "#,
+        client_id
+    )
+    .fetch_optional(&data.db)
+    .await {
+        Ok(plan) => plan,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match sqlx::query_scalar!(
+        r#"
+        SELECT (SELECT COUNT(*) FROM devices WHERE client_id = c.id) AS "devices!"
+        FROM clients c WHERE c.id = $1
+        "#,
+        client_id
+    )
+    .fetch_optional(&data.db)
+    .await {
+        Ok(Some(devices)) => {
+            let max_devices = plan.as_ref().and_then(|plan| plan.max_devices);
+            HttpResponse::Ok().json(json!({
+                "status": "success",
+                "devices": devices,
+                "max_devices": max_devices,
+                "remaining": max_devices.map(|max| (max as i64 - devices).max(0)),
+                "plan": plan,
+            }))
+        }
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": "Client not found"
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
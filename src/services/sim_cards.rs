@@ -0,0 +1,566 @@
+use actix_web::{
+    web::{Json, Path, Data, Query},
+    get, post, patch, delete, HttpResponse, Responder,
+};
+use serde_json::json;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    schema::{AssignSimSchema, CreateSimCardSchema, FilterOptions, UpdateSimCardSchema},
+    model::{DeviceModel, SimAssignmentModel, SimCardModel},
+    validation::valid_iccid,
+    AppState,
+};
+
+#[utoipa::path(
+    request_body = CreateSimCardSchema,
+    responses(
+        (status = 200, description = "Register a SIM card.", body = SimCardModel),
+        (status = 400, description = "Invalid ICCID"),
+        (status = 409, description = "ICCID already registered"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[post("/sim-cards", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn create_sim_card(
+    body: Json<CreateSimCardSchema>,
+    data: Data<AppState>
+) -> impl Responder {
+    let iccid: String = body.iccid.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if !valid_iccid(&iccid) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "Invalid ICCID, expected 19 or 20 digits starting with 89 and a valid check digit"
+        }));
+    }
+
+    match sqlx::query_as!(
+        SimCardModel,
+        r#"
+        INSERT INTO sim_cards (iccid, msisdn, carrier, data_plan)
+        VALUES ($1, $2, $3, $4)
+        RETURNING *
+        "#,
+        iccid,
+        body.msisdn,
+        body.carrier,
+        body.data_plan
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(sim_card) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "sim_card": sim_card,
+        })),
+        // unique_violation
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "ICCID already registered"
+            }))
+        }
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List all SIM cards.", body = [SimCardModel]),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[get("/sim-cards", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_all_sim_cards(
+    opts: Query<FilterOptions>,
+    data: Data<AppState>
+) -> impl Responder {
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        SimCardModel,
+        "SELECT * FROM sim_cards ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(sim_cards) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": sim_cards.len(),
+            "sim_cards": sim_cards,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List SIM cards still installed in devices with status retired.", body = [SimCardModel]),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[get("/sim-cards/retired-devices", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_sim_cards_on_retired_devices(
+    opts: Query<FilterOptions>,
+    data: Data<AppState>
+) -> impl Responder {
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        SimCardModel,
+        r#"
+        SELECT s.* FROM sim_cards s
+        JOIN devices d ON d.id = s.device_id
+        WHERE d.status = 'retired'
+        ORDER BY s.iccid
+        LIMIT $1 OFFSET $2
+        "#,
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(sim_cards) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": sim_cards.len(),
+            "sim_cards": sim_cards,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List devices with no SIM card installed, retired ones excluded.", body = [DeviceModel]),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[get("/devices/without-sim", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_devices_without_sim(
+    opts: Query<FilterOptions>,
+    data: Data<AppState>
+) -> impl Responder {
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        DeviceModel,
+        r#"
+        SELECT d.* FROM devices d
+        WHERE d.status <> 'retired'
+            AND NOT EXISTS (SELECT 1 FROM sim_cards s WHERE s.device_id = d.id)
+        ORDER BY d.created_at DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(devices) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": devices.len(),
+            "devices": devices,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get SIM card by ID.", body = SimCardModel),
+        (status = 404, description = "SIM card not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[get("/sim-cards/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_sim_card_by_id(
+    path: Path<Uuid>,
+    data: Data<AppState>
+) -> impl Responder {
+    let sim_id = path.into_inner();
+
+    match sqlx::query_as!(
+        SimCardModel,
+        "SELECT * FROM sim_cards WHERE id = $1",
+        sim_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(sim_card) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "sim_card": sim_card,
+        })),
+        Err(error) => HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = UpdateSimCardSchema,
+    responses(
+        (status = 200, description = "Update SIM card by ID.", body = SimCardModel),
+        (status = 404, description = "SIM card not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[patch("/sim-cards/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn update_sim_card_by_id(
+    path: Path<Uuid>,
+    body: Json<UpdateSimCardSchema>,
+    data: Data<AppState>
+) -> impl Responder {
+    let sim_id = path.into_inner();
+
+    let existing_sim_card = sqlx::query_as!(
+        SimCardModel,
+        "SELECT * FROM sim_cards WHERE id = $1",
+        sim_id
+    )
+    .fetch_one(&data.db)
+    .await;
+
+    if let Err(error) = existing_sim_card {
+        return HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    let sim_card = existing_sim_card.unwrap();
+
+    match sqlx::query_as!(
+        SimCardModel,
+        "UPDATE sim_cards SET msisdn = $1, carrier = $2, data_plan = $3 WHERE id = $4 RETURNING *",
+        body.msisdn.clone().or(sim_card.msisdn),
+        body.carrier.clone().unwrap_or(sim_card.carrier),
+        body.data_plan.clone().or(sim_card.data_plan),
+        sim_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(updated_sim_card) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "sim_card": updated_sim_card,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 204, description = "Delete SIM card by ID."),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[delete("/sim-cards/{id}", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn delete_sim_card_by_id(
+    path: Path<Uuid>,
+    data: Data<AppState>
+) -> impl Responder {
+    let sim_id = path.into_inner();
+
+    match sqlx::query!(
+        "DELETE FROM sim_cards WHERE id = $1",
+        sim_id
+    )
+    .execute(&data.db)
+    .await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = AssignSimSchema,
+    responses(
+        (status = 200, description = "Install the SIM card in a device.", body = SimAssignmentModel),
+        (status = 404, description = "SIM card or device not found"),
+        (status = 409, description = "SIM card already assigned or device already has a SIM"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[post("/sim-cards/{id}/assign", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn assign_sim_card(
+    path: Path<Uuid>,
+    body: Json<AssignSimSchema>,
+    data: Data<AppState>
+) -> impl Responder {
+    let sim_id = path.into_inner();
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let sim_card = match sqlx::query_as!(
+        SimCardModel,
+        "SELECT * FROM sim_cards WHERE id = $1 FOR UPDATE",
+        sim_id
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(sim_card) => sim_card,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if let Some(device_id) = sim_card.device_id {
+        return HttpResponse::Conflict().json(json!({
+            "status": "error",
+            "message": format!("SIM card already assigned to device {}", device_id)
+        }));
+    }
+
+    let device = match sqlx::query_as!(
+        DeviceModel,
+        "SELECT * FROM devices WHERE id = $1 FOR UPDATE",
+        body.device_id
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(device) => device,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match sqlx::query!(
+        "UPDATE sim_cards SET device_id = $1 WHERE id = $2",
+        body.device_id,
+        sim_id
+    )
+    .execute(&mut tx)
+    .await {
+        Ok(_) => (),
+        // unique_violation on device_id
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("23505") => {
+            return HttpResponse::Conflict().json(json!({
+                "status": "error",
+                "message": "Device already has a SIM card, unassign it first"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    let assignment = match sqlx::query_as!(
+        SimAssignmentModel,
+        "INSERT INTO sim_assignments (sim_id, device_id, imei) VALUES ($1, $2, $3) RETURNING *",
+        sim_id,
+        device.id,
+        device.imei
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(assignment) => assignment,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "assignment": assignment,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Remove the SIM card from its device.", body = SimAssignmentModel),
+        (status = 404, description = "SIM card not found"),
+        (status = 409, description = "SIM card not assigned"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[post("/sim-cards/{id}/unassign", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn unassign_sim_card(
+    path: Path<Uuid>,
+    data: Data<AppState>
+) -> impl Responder {
+    let sim_id = path.into_inner();
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let sim_card = match sqlx::query_as!(
+        SimCardModel,
+        "SELECT * FROM sim_cards WHERE id = $1 FOR UPDATE",
+        sim_id
+    )
+    .fetch_one(&mut tx)
+    .await {
+        Ok(sim_card) => sim_card,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if sim_card.device_id.is_none() {
+        return HttpResponse::Conflict().json(json!({
+            "status": "error",
+            "message": "SIM card not assigned"
+        }));
+    }
+
+    if let Err(error) = sqlx::query!(
+        "UPDATE sim_cards SET device_id = NULL WHERE id = $1",
+        sim_id
+    )
+    .execute(&mut tx)
+    .await
+    {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    let assignment = match sqlx::query_as!(
+        SimAssignmentModel,
+        r#"
+        UPDATE sim_assignments SET unassigned_at = now()
+        WHERE sim_id = $1 AND unassigned_at IS NULL
+        RETURNING *
+        "#,
+        sim_id
+    )
+    .fetch_optional(&mut tx)
+    .await {
+        Ok(assignment) => assignment,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "assignment": assignment,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List the devices the SIM card was installed in, newest first.", body = [SimAssignmentModel]),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "SIM"
+)]
+#[get("/sim-cards/{id}/history", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_sim_card_history(
+    path: Path<Uuid>,
+    opts: Query<FilterOptions>,
+    data: Data<AppState>
+) -> impl Responder {
+    let sim_id = path.into_inner();
+    let limit = opts.limit.unwrap_or(10);
+    let offset = (opts.page.unwrap_or(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        SimAssignmentModel,
+        "SELECT * FROM sim_assignments WHERE sim_id = $1 ORDER BY assigned_at DESC LIMIT $2 OFFSET $3",
+        sim_id,
+        limit as i32,
+        offset as i32
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(assignments) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": assignments.len(),
+            "assignments": assignments,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use actix_web::{
+    web::{Bytes, Data, Query, ReqData},
+    get, Error, HttpRequest, HttpResponse, Responder,
+};
+use futures_util::stream::{self, StreamExt};
+use serde_json::json;
+use tokio::{sync::broadcast::error::RecvError, time::interval};
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    events::FleetEvent,
+    schema::StreamOptions,
+    AppState, TokenClaims,
+};
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Which events a single subscriber is allowed and asked to see.
+#[derive(Clone)]
+struct StreamFilter {
+    claims: TokenClaims,
+    client_id: Option<Uuid>,
+}
+
+impl StreamFilter {
+    fn matches(&self, event: &FleetEvent) -> bool {
+        self.claims.can_access_client(event.client_id)
+            && self.client_id.is_none_or(|id| id == event.client_id)
+    }
+}
+
+fn format_event(event: &FleetEvent) -> Bytes {
+    Bytes::from(format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event.id,
+        event.kind.as_str(),
+        json!({
+            "client_id": event.client_id,
+            "device_id": event.device_id,
+            "created_at": event.created_at,
+            "data": event.data,
+        })
+    ))
+}
+
+#[utoipa::path(
+    params(
+        ("client_id" = Option<Uuid>, Query, description = "Only stream events of this client"),
+        ("Last-Event-ID" = Option<u64>, Header, description = "Resume after this event id")
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events stream of device, position and alert updates.", content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid token."),
+        (status = 403, description = "Client not accessible with this token, or a group token.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Tempo real"
+)]
+#[get("/stream/devices", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn stream_devices(
+    req: HttpRequest,
+    opts: Query<StreamOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    // The stream is scoped by client, a group token would see all of it
+    if claims.group_id.is_some() {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Group tokens cannot stream the client's events"
+        }));
+    }
+
+    if let Some(client_id) = opts.client_id {
+        if !claims.can_access_client(client_id) {
+            return HttpResponse::Forbidden().json(json!({
+                "status": "error",
+                "message": "Client not accessible with this token"
+            }));
+        }
+    }
+
+    let filter = StreamFilter {
+        claims: claims.into_inner(),
+        client_id: opts.client_id,
+    };
+    let replay_filter = filter.clone();
+
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let (replay, receiver) = data.events.subscribe(last_event_id);
+
+    let replay = stream::iter(
+        replay
+            .into_iter()
+            .filter(move |event| replay_filter.matches(event))
+            .map(|event| Ok::<_, Error>(format_event(&event))),
+    );
+
+    let live = stream::unfold(
+        (receiver, interval(KEEP_ALIVE_INTERVAL), filter),
+        move |(mut receiver, mut keep_alive, filter)| async move {
+            loop {
+                tokio::select! {
+                    received = receiver.recv() => match received {
+                        Ok(event) if filter.matches(&event) => {
+                            return Some((Ok(format_event(&event)), (receiver, keep_alive, filter)));
+                        }
+                        Ok(_) => continue,
+                        // Ending the stream makes the browser reconnect with Last-Event-ID
+                        // and pick the missed events up from the backlog
+                        Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => return None,
+                    },
+                    _ = keep_alive.tick() => {
+                        return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), (receiver, keep_alive, filter)));
+                    }
+                }
+            }
+        },
+    );
+
+    let retry = stream::once(async { Ok::<_, Error>(Bytes::from_static(b"retry: 3000\n\n")) });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(retry.chain(replay).chain(live))
+}
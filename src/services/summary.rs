@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use actix_web::{
+    web::{Path, Data, ReqData},
+    get, HttpResponse, Responder,
+};
+use serde_json::json;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{AppState, TokenClaims};
+
+/// Devices whose last upload is newer than this are online.
+const ONLINE_MINUTES: i32 = 10;
+/// Devices not online but uploading within this are stale, older ones offline.
+const STALE_HOURS: i32 = 24;
+const RECENT_DAYS: i32 = 30;
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Fleet summary of the client: devices by status and by model, online (upload within 10 minutes), stale (within 24 hours) and offline devices by the latest of upload_gps and upload_data, devices created over the last 30 days and open alerts by kind."),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Client not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Clientes"
+)]
+#[get("/clients/{id}/summary", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_client_summary(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    let totals = match sqlx::query!(
+        r#"
+        SELECT
+            COUNT(d.id) AS "devices!",
+            COUNT(d.id) FILTER (
+                WHERE GREATEST(d.upload_gps, d.upload_data) >= now() - make_interval(mins => $2)
+            ) AS "online!",
+            COUNT(d.id) FILTER (
+                WHERE GREATEST(d.upload_gps, d.upload_data) < now() - make_interval(mins => $2)
+                    AND GREATEST(d.upload_gps, d.upload_data) >= now() - make_interval(hours => $3)
+            ) AS "stale!",
+            COUNT(d.id) FILTER (
+                WHERE GREATEST(d.upload_gps, d.upload_data) < now() - make_interval(hours => $3)
+            ) AS "offline!",
+            COUNT(d.id) FILTER (
+                WHERE d.created_at >= now() - make_interval(days => $4)
+            ) AS "created_recently!"
+        FROM clients c
+        LEFT JOIN devices d ON d.client_id = c.id
+        WHERE c.id = $1
+        GROUP BY c.id
+        "#,
+        client_id,
+        ONLINE_MINUTES,
+        STALE_HOURS,
+        RECENT_DAYS
+    )
+    .fetch_optional(&data.db)
+    .await {
+        Ok(Some(totals)) => totals,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Client not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    // Both breakdowns in one pass over the client's devices
+    let groups = match sqlx::query!(
+        r#"
+        SELECT status AS "status?", model AS "model?", GROUPING(status) = 1 AS "by_model!", COUNT(*) AS "devices!"
+        FROM devices
+        WHERE client_id = $1
+        GROUP BY GROUPING SETS ((status), (model))
+        "#,
+        client_id
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(groups) => groups,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let alerts = match sqlx::query!(
+        r#"
+        SELECT a.kind, COUNT(*) AS "alerts!"
+        FROM alerts a
+        JOIN devices d ON d.id = a.device_id
+        WHERE d.client_id = $1 AND a.resolved_at IS NULL
+        GROUP BY a.kind
+        "#,
+        client_id
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(alerts) => alerts,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let mut by_status = BTreeMap::new();
+    let mut by_model = BTreeMap::new();
+    for group in groups {
+        if group.by_model {
+            by_model.insert(group.model.unwrap_or_default(), group.devices);
+        } else {
+            by_status.insert(group.status.unwrap_or_default(), group.devices);
+        }
+    }
+    let open_alerts: i64 = alerts.iter().map(|alert| alert.alerts).sum();
+    let alerts_by_kind: BTreeMap<_, _> = alerts.into_iter().map(|alert| (alert.kind, alert.alerts)).collect();
+
+    HttpResponse::Ok().json(json!({
+        "status": "success",
+        "devices": totals.devices,
+        "by_status": by_status,
+        "by_model": by_model,
+        "connectivity": {
+            "online": totals.online,
+            "stale": totals.stale,
+            "offline": totals.offline,
+        },
+        "created_last_30_days": totals.created_recently,
+        "open_alerts": open_alerts,
+        "open_alerts_by_kind": alerts_by_kind,
+    }))
+}
@@ -0,0 +1,421 @@
+use actix_web::{
+    web::{Json, Path, Data, ReqData},
+    get, put, delete, HttpResponse, Responder,
+};
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    schema::{DeviceFilterOptions, DeviceTagsSchema},
+    model::{ClientModel, TagCountModel},
+    validation::{normalize_tag, MAX_TAG_LENGTH},
+    AppState, TokenClaims,
+};
+
+/// Parsed form of the device list filter.
+pub struct DeviceFilter {
+    pub tags: Option<Vec<String>>,
+    pub metadata: Option<Value>,
+    pub include_descendants: bool,
+}
+
+/// Tags, metadata and sub-client inclusion of a device list filter, or the
+/// response rejecting them. Shared by the list and export endpoints.
+///
+/// `tag` is a comma separated list and `metadata` a JSON object the device
+/// metadata must contain.
+pub fn parse_device_filter(
+    opts: &DeviceFilterOptions,
+) -> Result<DeviceFilter, HttpResponse> {
+    let tags = match opts.tag.as_deref() {
+        Some(tag) => {
+            let tags: Vec<String> = tag.split(',').filter_map(normalize_tag).collect();
+            (!tags.is_empty()).then_some(tags)
+        }
+        None => None,
+    };
+
+    let metadata = match opts.metadata.as_deref() {
+        Some(metadata) => match serde_json::from_str::<Value>(metadata) {
+            Ok(metadata) if metadata.is_object() => Some(metadata),
+            _ => {
+                return Err(HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": "metadata must be a JSON object, e.g. {\"region\":\"south\"}"
+                })));
+            }
+        },
+        None => None,
+    };
+
+    let include_descendants = opts.include_descendants.unwrap_or(false);
+    if include_descendants && opts.client_id.is_none() {
+        return Err(HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": "include_descendants needs a client_id"
+        })));
+    }
+
+    Ok(DeviceFilter { tags, metadata, include_descendants })
+}
+
+/// Tag names of the device in alphabetical order.
+pub async fn device_tags(db: &Pool<Postgres>, device_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT t.name FROM device_tags dt
+        JOIN tags t ON t.id = dt.tag_id
+        WHERE dt.device_id = $1
+        ORDER BY t.name
+        "#,
+        device_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get the tags of a device.", body = [String]),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Dispositivos"
+)]
+#[get("/devices/{id}/tags", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_device_tags(
+    path: Path<Uuid>,
+    data: Data<AppState>
+) -> impl Responder {
+    let device_id = path.into_inner();
+
+    match sqlx::query_scalar!("SELECT id FROM devices WHERE id = $1", device_id)
+        .fetch_optional(&data.db)
+        .await
+    {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Device not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match device_tags(&data.db, device_id).await {
+        Ok(tags) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "tags": tags,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body = DeviceTagsSchema,
+    responses(
+        (status = 200, description = "Replace the tags of a device, creating the new ones.", body = [String]),
+        (status = 400, description = "Empty or too long tag"),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Dispositivos"
+)]
+#[put("/devices/{id}/tags", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn set_device_tags(
+    path: Path<Uuid>,
+    body: Json<DeviceTagsSchema>,
+    data: Data<AppState>
+) -> impl Responder {
+    let device_id = path.into_inner();
+
+    let mut tags = Vec::with_capacity(body.tags.len());
+    for tag in &body.tags {
+        match normalize_tag(tag) {
+            Some(tag) => tags.push(tag),
+            None => {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": format!("Tags must have between 1 and {} characters", MAX_TAG_LENGTH)
+                }));
+            }
+        }
+    }
+    tags.sort();
+    tags.dedup();
+
+    let mut tx = match data.db.begin().await {
+        Ok(tx) => tx,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    match sqlx::query_scalar!("SELECT id FROM devices WHERE id = $1 FOR UPDATE", device_id)
+        .fetch_optional(&mut tx)
+        .await
+    {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Device not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    if let Err(error) = sqlx::query!(
+        "INSERT INTO tags (name) SELECT UNNEST($1::VARCHAR[]) ON CONFLICT (name) DO NOTHING",
+        &tags
+    )
+    .execute(&mut tx)
+    .await
+    {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    if let Err(error) = sqlx::query!(
+        r#"
+        DELETE FROM device_tags
+        WHERE device_id = $1 AND tag_id NOT IN (SELECT id FROM tags WHERE name = ANY($2))
+        "#,
+        device_id,
+        &tags
+    )
+    .execute(&mut tx)
+    .await
+    {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    if let Err(error) = sqlx::query!(
+        r#"
+        INSERT INTO device_tags (device_id, tag_id)
+        SELECT $1, id FROM tags WHERE name = ANY($2)
+        ON CONFLICT DO NOTHING
+        "#,
+        device_id,
+        &tags
+    )
+    .execute(&mut tx)
+    .await
+    {
+        return HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "tags": tags,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List the tags used by the client's devices, with how many devices carry each.", body = [TagCountModel]),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Clientes"
+)]
+#[get("/clients/{id}/tags", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_client_tags(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    match sqlx::query_as!(
+        TagCountModel,
+        r#"
+        SELECT t.name, COUNT(*) AS "devices!" FROM tags t
+        JOIN device_tags dt ON dt.tag_id = t.id
+        JOIN devices d ON d.id = dt.device_id
+        WHERE d.client_id = $1
+        GROUP BY t.name
+        ORDER BY t.name
+        "#,
+        client_id
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(tags) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": tags.len(),
+            "tags": tags,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
+
+#[utoipa::path(
+    request_body(content = Object, description = "JSON Schema for the metadata of the client's devices"),
+    responses(
+        (status = 200, description = "Set the JSON Schema checked whenever device metadata of the client is written. Existing devices are not changed, the response counts those that do not follow it.", body = ClientModel),
+        (status = 400, description = "Not a valid JSON Schema"),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Client not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Clientes"
+)]
+#[put("/clients/{id}/metadata-schema", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn set_client_metadata_schema(
+    path: Path<Uuid>,
+    body: Json<Value>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+    let schema = body.into_inner();
+
+    let validator = match jsonschema::meta::validate(&schema)
+        .map_err(|error| error.to_string())
+        .and_then(|_| jsonschema::validator_for(&schema).map_err(|error| error.to_string()))
+    {
+        Ok(validator) => validator,
+        Err(error) => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Invalid JSON Schema: {}", error)
+            }));
+        }
+    };
+
+    let client = match sqlx::query_as!(
+        ClientModel,
+        "UPDATE clients SET metadata_schema = $1 WHERE id = $2 RETURNING *",
+        schema,
+        client_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(client) => client,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    let metadata = match sqlx::query_scalar!(
+        "SELECT metadata FROM devices WHERE client_id = $1",
+        client_id
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    HttpResponse::Ok().json(json!({
+        "status": "success",
+        "client": client,
+        "nonconforming_devices": metadata.iter().filter(|metadata| !validator.is_valid(metadata)).count(),
+    }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Stop checking device metadata of the client.", body = ClientModel),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Client not found"),
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Clientes"
+)]
+#[delete("/clients/{id}/metadata-schema", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn delete_client_metadata_schema(
+    path: Path<Uuid>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    match sqlx::query_as!(
+        ClientModel,
+        "UPDATE clients SET metadata_schema = NULL WHERE id = $1 RETURNING *",
+        client_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(client) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "client": client,
+        })),
+        Err(error) => HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
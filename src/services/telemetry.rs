@@ -0,0 +1,170 @@
+use actix_web::{
+    web::{Data, Path, Query, ReqData},
+    get, HttpResponse, Responder,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    schema::TelemetryQueryOptions,
+    model::{DeviceModel, TelemetryBucketModel, TelemetryModel},
+    AppState, TokenClaims,
+};
+
+/// Postgres `date_trunc` unit and the widest range allowed for a bucket size.
+fn bucket_settings(bucket: &str) -> Option<(&'static str, Duration)> {
+    match bucket {
+        "1m" => Some(("minute", Duration::days(7))),
+        "1h" => Some(("hour", Duration::days(90))),
+        "1d" => Some(("day", Duration::days(730))),
+        _ => None,
+    }
+}
+
+/// Widest range served as raw readings.
+const RAW_MAX_RANGE_DAYS: i64 = 1;
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Device id"),
+        ("metric" = Option<String>, Query, description = "Only this metric"),
+        ("from" = Option<String>, Query, description = "Range start, defaults to 24h before `to`"),
+        ("to" = Option<String>, Query, description = "Range end, defaults to now"),
+        ("bucket" = Option<String>, Query, description = "Downsample into `1m`, `1h` or `1d` buckets (min/max/avg/last)"),
+        ("limit" = Option<usize>, Query, description = "Maximum raw readings, ignored with `bucket`")
+    ),
+    responses(
+        (status = 200, description = "Raw readings or downsampled buckets.", body = [TelemetryBucketModel]),
+        (status = 400, description = "Invalid bucket or time range"),
+        (status = 403, description = "Device of a client not accessible with this token"),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Telemetria"
+)]
+#[get("/devices/{id}/telemetry", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_device_telemetry(
+    path: Path<Uuid>,
+    opts: Query<TelemetryQueryOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let device_id = path.into_inner();
+
+    let device = match sqlx::query_as!(
+        DeviceModel,
+        "SELECT * FROM devices WHERE id = $1",
+        device_id
+    )
+    .fetch_one(&data.db)
+    .await {
+        Ok(device) => device,
+        Err(error) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if !claims.can_access_client(device.client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    let to = opts.to.unwrap_or_else(Utc::now);
+    let from = opts.from.unwrap_or(to - Duration::days(1));
+
+    let (unit, max_range) = match opts.bucket.as_deref() {
+        Some(bucket) => match bucket_settings(bucket) {
+            Some((unit, max_range)) => (Some(unit), max_range),
+            None => {
+                return HttpResponse::BadRequest().json(json!({
+                    "status": "error",
+                    "message": "Invalid bucket, use 1m, 1h or 1d"
+                }));
+            }
+        },
+        None => (None, Duration::days(RAW_MAX_RANGE_DAYS)),
+    };
+
+    if from >= to || to - from > max_range {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("Invalid time range, at most {} days for this bucket", max_range.num_days())
+        }));
+    }
+
+    match unit {
+        Some(unit) => match sqlx::query_as!(
+            TelemetryBucketModel,
+            r#"
+            SELECT
+                metric,
+                date_trunc($2, recorded_at, 'UTC') AS "bucket!",
+                MIN(value) AS "min!",
+                MAX(value) AS "max!",
+                AVG(value) AS "avg!",
+                (ARRAY_AGG(value ORDER BY recorded_at DESC))[1] AS "last!",
+                COUNT(*) AS "count!"
+            FROM telemetry
+            WHERE device_id = $1
+                AND ($3::VARCHAR IS NULL OR metric = $3)
+                AND recorded_at >= $4 AND recorded_at < $5
+            GROUP BY metric, 2
+            ORDER BY metric, 2
+            "#,
+            device_id,
+            unit,
+            opts.metric,
+            from,
+            to
+        )
+        .fetch_all(&data.db)
+        .await {
+            Ok(buckets) => HttpResponse::Ok().json(json!({
+                "status": "success",
+                "result": buckets.len(),
+                "buckets": buckets,
+            })),
+            Err(error) => HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            })),
+        },
+        None => match sqlx::query_as!(
+            TelemetryModel,
+            r#"
+            SELECT * FROM telemetry
+            WHERE device_id = $1
+                AND ($2::VARCHAR IS NULL OR metric = $2)
+                AND recorded_at >= $3 AND recorded_at < $4
+            ORDER BY recorded_at DESC
+            LIMIT $5
+            "#,
+            device_id,
+            opts.metric,
+            from,
+            to,
+            opts.limit.unwrap_or(1000) as i64
+        )
+        .fetch_all(&data.db)
+        .await {
+            Ok(readings) => HttpResponse::Ok().json(json!({
+                "status": "success",
+                "result": readings.len(),
+                "readings": readings,
+            })),
+            Err(error) => HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            })),
+        },
+    }
+}
@@ -0,0 +1,98 @@
+use actix_web::{
+    web::{Data, Path, Query},
+    get, HttpResponse, Responder,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    schema::TripQueryOptions,
+    model::{DeviceModel, TripModel},
+    AppState,
+};
+
+/// Widest range of trip start times served at once.
+const MAX_RANGE_DAYS: i64 = 93;
+
+#[utoipa::path(
+    params(
+        ("id" = Uuid, Path, description = "Device id"),
+        ("from" = Option<String>, Query, description = "Trips started at or after, defaults to 24h before `to`"),
+        ("to" = Option<String>, Query, description = "Trips started before, defaults to now"),
+        ("limit" = Option<usize>, Query, description = "Page size"),
+        ("page" = Option<usize>, Query, description = "Page number")
+    ),
+    responses(
+        (status = 200, description = "Trips of the device, newest first, segmented from its positions as they arrive. A trip ends 2 minutes after the ignition goes off, or after 5 minutes stopped or silent on a device without ignition readings; idling with the ignition on, or a shorter stop on a device without ignition readings, counts as idle time. The trip in progress has no end yet.", body = [TripModel]),
+        (status = 400, description = "Invalid time range"),
+        (status = 404, description = "Device not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Viagens"
+)]
+#[get("/devices/{id}/trips", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_device_trips(
+    path: Path<Uuid>,
+    opts: Query<TripQueryOptions>,
+    data: Data<AppState>
+) -> impl Responder {
+    let device_id = path.into_inner();
+
+    if let Err(error) = sqlx::query_as!(
+        DeviceModel,
+        "SELECT * FROM devices WHERE id = $1",
+        device_id
+    )
+    .fetch_one(&data.db)
+    .await
+    {
+        return HttpResponse::NotFound().json(json!({
+            "status": "not found",
+            "message": format!("{:?}", error)
+        }));
+    }
+
+    let to = opts.to.unwrap_or_else(Utc::now);
+    let from = opts.from.unwrap_or(to - Duration::days(1));
+    if from >= to || to - from > Duration::days(MAX_RANGE_DAYS) {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("Invalid time range, at most {} days", MAX_RANGE_DAYS)
+        }));
+    }
+
+    let limit = opts.limit.unwrap_or(100);
+    let offset = (opts.page.unwrap_or(1).max(1) - 1) * limit;
+
+    match sqlx::query_as!(
+        TripModel,
+        r#"
+        SELECT * FROM trips
+        WHERE device_id = $1 AND started_at >= $2 AND started_at < $3
+        ORDER BY started_at DESC
+        LIMIT $4 OFFSET $5
+        "#,
+        device_id,
+        from,
+        to,
+        limit as i64,
+        offset as i64
+    )
+    .fetch_all(&data.db)
+    .await {
+        Ok(trips) => HttpResponse::Ok().json(json!({
+            "status": "success",
+            "result": trips.len(),
+            "distance_m": trips.iter().map(|trip| trip.distance_m).sum::<f64>(),
+            "trips": trips,
+        })),
+        Err(error) => HttpResponse::InternalServerError().json(json!({
+            "status": "error",
+            "message": format!("{:?}", error)
+        })),
+    }
+}
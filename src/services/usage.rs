@@ -0,0 +1,152 @@
+use actix_web::{
+    http::header::ACCEPT,
+    web::{Path, Data, Query, ReqData},
+    get, HttpRequest, HttpResponse, Responder,
+};
+use futures_util::stream;
+use serde_json::json;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+#[allow(unused_imports)]
+use utoipa::ToSchema;
+
+use crate::{
+    export::{export_response, forward_rows, ExportFormat},
+    schema::UsageOptions,
+    model::DeviceUsageModel,
+    usage::{closed_usage, current_month, first_month, live_usage, parse_month},
+    AppState, TokenClaims,
+};
+
+const USAGE_FIELDS: [&str; 3] = ["device_id", "imei", "active_days"];
+
+/// CSV only when asked for by name, JSON stays the default of the API.
+fn wants_csv(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.to_lowercase().contains("text/csv"))
+}
+
+#[utoipa::path(
+    params(
+        ("month" = String, Query, description = "Month to bill, YYYY-MM"),
+        ("Accept" = Option<String>, Header, description = "application/json (default) or text/csv")
+    ),
+    responses(
+        (status = 200, description = "Active device-days of the client in the month: days each device had the active status and sent data. Closed months come from the snapshots the server takes as they close; the running month, and a closed one not snapshotted yet, are computed so far and marked not final.", body = [DeviceUsageModel]),
+        (status = 400, description = "Month not in YYYY-MM, still to come or before usage was recorded"),
+        (status = 403, description = "Client not accessible with this token"),
+        (status = 404, description = "Client not found"),
+        (status = 500, description = "Internal Server error.")
+    ),
+    security(("bearer_auth" = [])),
+    tag = "Clientes"
+)]
+#[get("/clients/{id}/usage", wrap = "actix_web_httpauth::middleware::HttpAuthentication::bearer(crate::auth::validator)")]
+pub async fn get_client_usage(
+    req: HttpRequest,
+    path: Path<Uuid>,
+    opts: Query<UsageOptions>,
+    claims: ReqData<TokenClaims>,
+    data: Data<AppState>
+) -> impl Responder {
+    let client_id = path.into_inner();
+
+    if !claims.can_access_client(client_id) {
+        return HttpResponse::Forbidden().json(json!({
+            "status": "error",
+            "message": "Client not accessible with this token"
+        }));
+    }
+
+    let month = match parse_month(&opts.month) {
+        Some(month) => month,
+        None => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": "month must be given as YYYY-MM"
+            }));
+        }
+    };
+    let running = current_month();
+    if month > running {
+        return HttpResponse::BadRequest().json(json!({
+            "status": "error",
+            "message": format!("{} has not started yet", opts.month)
+        }));
+    }
+
+    match first_month(&data.db).await {
+        Ok(first) if month < first => {
+            return HttpResponse::BadRequest().json(json!({
+                "status": "error",
+                "message": format!("Usage is recorded from {} on", first.format("%Y-%m"))
+            }));
+        }
+        Ok(_) => (),
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    match sqlx::query_scalar!("SELECT id FROM clients WHERE id = $1", client_id)
+        .fetch_optional(&data.db)
+        .await
+    {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({
+                "status": "not found",
+                "message": "Client not found"
+            }));
+        }
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    }
+
+    // A closed month not snapshotted yet is still computed and not final
+    let snapshot = if month < running {
+        closed_usage(&data.db, client_id, month).await
+    } else {
+        Ok(None)
+    };
+    let usage = match snapshot {
+        Ok(Some(devices)) => Ok((devices, true)),
+        Ok(None) => live_usage(&data.db, client_id, month).await.map(|devices| (devices, false)),
+        Err(error) => Err(error),
+    };
+    let (devices, closed) = match usage {
+        Ok(usage) => usage,
+        Err(error) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "status": "error",
+                "message": format!("{:?}", error)
+            }));
+        }
+    };
+
+    if wants_csv(&req) {
+        let (sender, receiver) = mpsc::channel(devices.len() + 1);
+        actix_web::rt::spawn(forward_rows(stream::iter(devices.into_iter().map(Ok)), sender));
+        let fields = USAGE_FIELDS.iter().map(|field| field.to_string()).collect();
+        let sheet = format!("usage-{}-{}", client_id, month.format("%Y-%m"));
+        return export_response(ExportFormat::Csv, &sheet, fields, receiver);
+    }
+
+    HttpResponse::Ok().json(json!({
+        "status": "success",
+        "month": month.format("%Y-%m").to_string(),
+        "final": closed,
+        "devices_billed": devices.len(),
+        "active_device_days": devices.iter().map(|device| device.active_days as i64).sum::<i64>(),
+        "devices": devices,
+    }))
+}
@@ -0,0 +1,87 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{schema::TelemetryPayloadSchema, validation::valid_metric};
+
+/// First metric name of the payloads that cannot be stored, if any.
+pub fn invalid_metric(payloads: &[TelemetryPayloadSchema]) -> Option<&str> {
+    payloads
+        .iter()
+        .flat_map(|payload| payload.metrics.keys())
+        .map(String::as_str)
+        .find(|metric| !valid_metric(metric))
+}
+
+/// Flattens telemetry payloads into `(metric, value, recorded_at)` columns,
+/// readings without a timestamp are taken as received now.
+fn flatten(payloads: &[TelemetryPayloadSchema]) -> (Vec<String>, Vec<f64>, Vec<DateTime<Utc>>) {
+    let now = Utc::now();
+    let mut metrics = Vec::new();
+    let mut values = Vec::new();
+    let mut recorded_at = Vec::new();
+
+    for payload in payloads {
+        for (metric, value) in &payload.metrics {
+            metrics.push(metric.clone());
+            values.push(*value);
+            recorded_at.push(payload.timestamp.unwrap_or(now));
+        }
+    }
+
+    (metrics, values, recorded_at)
+}
+
+/// Stores sensor readings of a device and advances its `upload_data`.
+pub async fn store_telemetry(
+    db: &Pool<Postgres>,
+    device_id: Uuid,
+    payloads: &[TelemetryPayloadSchema],
+) -> Result<u64, sqlx::Error> {
+    if payloads.iter().all(|payload| payload.metrics.is_empty()) {
+        return Ok(0);
+    }
+
+    let mut tx = db.begin().await?;
+    let inserted = insert_telemetry(&mut tx, device_id, payloads).await?;
+    tx.commit().await?;
+
+    Ok(inserted)
+}
+
+/// The writes of `store_telemetry` inside the caller's transaction.
+pub async fn insert_telemetry(
+    tx: &mut Transaction<'_, Postgres>,
+    device_id: Uuid,
+    payloads: &[TelemetryPayloadSchema],
+) -> Result<u64, sqlx::Error> {
+    let (metrics, values, recorded_at) = flatten(payloads);
+    let latest = match recorded_at.iter().max() {
+        Some(latest) => *latest,
+        None => return Ok(0),
+    };
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO telemetry (device_id, metric, value, recorded_at)
+        SELECT $1, * FROM UNNEST($2::VARCHAR[], $3::DOUBLE PRECISION[], $4::TIMESTAMPTZ[])
+        "#,
+        device_id,
+        &metrics,
+        &values,
+        &recorded_at
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        "UPDATE devices SET upload_data = GREATEST(upload_data, $1) WHERE id = $2",
+        latest,
+        device_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(inserted)
+}
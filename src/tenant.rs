@@ -0,0 +1,358 @@
+use std::{cell::RefCell, future::Future};
+
+use futures_util::FutureExt;
+use sqlx::{postgres::PgPoolOptions, PgConnection};
+use uuid::Uuid;
+
+/// Role without BYPASSRLS the connections switch to, so the row-level
+/// security policies also hold when the database user is a superuser.
+const TENANT_ROLE: &str = "app_tenant";
+
+/// Tenant rows the connections taken by a task may reach.
+#[derive(Clone)]
+enum Scope {
+    /// Requests whose token was not checked, and work run outside any scope
+    Nothing,
+    /// Client tokens, as the `{uuid,...}` array literal of `app.client_ids`
+    Clients(String),
+    /// Operator tokens and the server's own jobs, sets `app.bypass`
+    All,
+}
+
+impl Scope {
+    fn clients(clients: &[Uuid]) -> Scope {
+        let ids: Vec<String> = clients.iter().map(Uuid::to_string).collect();
+        Scope::Clients(format!("{{{}}}", ids.join(",")))
+    }
+}
+
+tokio::task_local! {
+    static CLIENT_SCOPE: RefCell<Scope>;
+}
+
+/// Runs a request that reaches no tenant rows until its token grants some.
+pub fn scope_request<F: Future>(request: F) -> impl Future<Output = F::Output> {
+    CLIENT_SCOPE.scope(RefCell::new(Scope::Nothing), request)
+}
+
+/// Scope of the current request from then on: the given clients, or every
+/// client for none.
+pub fn restrict(clients: Option<&[Uuid]>) {
+    let scope = clients.map_or(Scope::All, Scope::clients);
+    let _ = CLIENT_SCOPE.try_with(|current| *current.borrow_mut() = scope);
+}
+
+/// Runs work of the server itself over every tenant: gateways, background
+/// jobs and the lookups that authenticate a caller before its scope is known.
+pub fn system<F: Future>(work: F) -> impl Future<Output = F::Output> {
+    CLIENT_SCOPE.scope(RefCell::new(Scope::All), work)
+}
+
+/// Keeps the scope of the current task for work spawned from it.
+pub fn inherit<F: Future>(work: F) -> impl Future<Output = F::Output> {
+    let scope = current();
+    CLIENT_SCOPE.scope(RefCell::new(scope), work)
+}
+
+fn current() -> Scope {
+    CLIENT_SCOPE
+        .try_with(|scope| scope.borrow().clone())
+        .unwrap_or(Scope::Nothing)
+}
+
+async fn set_scope(conn: &mut PgConnection, scope: Scope) -> Result<(), sqlx::Error> {
+    let (client_ids, bypass) = match scope {
+        Scope::Nothing => (String::new(), ""),
+        Scope::Clients(client_ids) => (client_ids, ""),
+        Scope::All => (String::new(), "on"),
+    };
+
+    sqlx::query!(
+        "SELECT set_config('app.client_ids', $1, false) AS client_ids, set_config('app.bypass', $2, false) AS bypass",
+        client_ids,
+        bypass
+    )
+    .fetch_one(conn)
+    .await
+    .map(|_| ())
+}
+
+/// Pool whose connections carry the client scope of the task taking them.
+/// Setting it on every acquire also replaces the ping that checks idle connections.
+pub fn pool_options() -> PgPoolOptions {
+    PgPoolOptions::new()
+        .test_before_acquire(false)
+        .after_connect(|conn, _| {
+            let scope = current();
+            async move {
+                sqlx::query!("SELECT set_config('role', $1, false)", TENANT_ROLE)
+                    .fetch_one(&mut *conn)
+                    .await?;
+                set_scope(conn, scope).await
+            }
+            .boxed()
+        })
+        .before_acquire(|conn, _| {
+            let scope = current();
+            async move { set_scope(conn, scope).await.map(|_| true) }.boxed()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Acquire, Pool, Postgres, Transaction};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::services::registered_imeis;
+
+    /// Pool on the development database, none when it is not configured.
+    async fn pool() -> Option<Pool<Postgres>> {
+        dotenv::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").ok()?;
+        let pool = pool_options()
+            .max_connections(1)
+            .connect(&url)
+            .await
+            .expect("Failed to connect to the database");
+        Some(pool)
+    }
+
+    struct Tenant {
+        client_id: Uuid,
+        device_id: Uuid,
+    }
+
+    /// A client with one device, a position and a trip of it.
+    async fn seed(tx: &mut Transaction<'_, Postgres>, name: &str) -> Tenant {
+        let client_id = sqlx::query_scalar!(
+            "INSERT INTO clients (name, status) VALUES ($1, 'active') RETURNING id",
+            name
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+
+        let device_id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO devices (client_id, nickname, imei, model, serial_number, upload_data, upload_gps, status)
+            VALUES ($1, $2, $3, 'RLS Test', $2, now(), now(), 'active')
+            RETURNING id
+            "#,
+            client_id,
+            name,
+            &client_id.simple().to_string()[..15]
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+
+        sqlx::query!(
+            "INSERT INTO positions (device_id, recorded_at, latitude, longitude) VALUES ($1, now(), -23.5, -46.6)",
+            device_id
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        sqlx::query!(
+            "INSERT INTO trips (device_id, started_at, start_latitude, start_longitude) VALUES ($1, now(), -23.5, -46.6)",
+            device_id
+        )
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        Tenant { client_id, device_id }
+    }
+
+    /// Two tenants written with the bypass, in a transaction never committed.
+    async fn seed_tenants(pool: &Pool<Postgres>) -> (Transaction<'static, Postgres>, Tenant, Tenant) {
+        let mut tx = pool.begin().await.unwrap();
+        sqlx::query!(
+            "INSERT INTO device_models (name, manufacturer, protocol) VALUES ('RLS Test', 'Test', 'http') ON CONFLICT DO NOTHING"
+        )
+        .execute(&mut tx)
+        .await
+        .unwrap();
+        let a = seed(&mut tx, "rls-a").await;
+        let b = seed(&mut tx, "rls-b").await;
+        (tx, a, b)
+    }
+
+    /// Tenant rows of the given clients the transaction can read.
+    async fn visible(tx: &mut Transaction<'_, Postgres>, clients: &[Uuid]) -> [i64; 4] {
+        let counts = sqlx::query!(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM clients WHERE id = ANY($1)) AS "clients!",
+                (SELECT COUNT(*) FROM devices WHERE client_id = ANY($1)) AS "devices!",
+                (SELECT COUNT(*) FROM positions) AS "positions!",
+                (SELECT COUNT(*) FROM trips) AS "trips!"
+            "#,
+            clients
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        [counts.clients, counts.devices, counts.positions, counts.trips]
+    }
+
+    fn is_policy_violation<T>(result: Result<T, sqlx::Error>) -> bool {
+        matches!(result, Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("42501"))
+    }
+
+    #[actix_web::test]
+    async fn client_scope_hides_other_clients() {
+        let Some(pool) = pool().await else { return };
+
+        system(async {
+            let (mut tx, a, b) = seed_tenants(&pool).await;
+            set_scope(&mut tx, Scope::clients(&[a.client_id])).await.unwrap();
+
+            assert_eq!(visible(&mut tx, &[b.client_id]).await[..2], [0, 0]);
+            let own = visible(&mut tx, &[a.client_id]).await;
+            assert_eq!(own, [1, 1, 1, 1], "only the rows of client A are visible");
+
+            let renamed = sqlx::query!("UPDATE clients SET name = 'taken' WHERE id = $1", b.client_id)
+                .execute(&mut tx)
+                .await
+                .unwrap();
+            assert_eq!(renamed.rows_affected(), 0);
+            let updated = sqlx::query!("UPDATE devices SET nickname = 'taken' WHERE id = $1", b.device_id)
+                .execute(&mut tx)
+                .await
+                .unwrap();
+            assert_eq!(updated.rows_affected(), 0);
+            let deleted = sqlx::query!("DELETE FROM positions WHERE device_id = $1", b.device_id)
+                .execute(&mut tx)
+                .await
+                .unwrap();
+            assert_eq!(deleted.rows_affected(), 0);
+            let deleted = sqlx::query!("DELETE FROM trips WHERE device_id = $1", b.device_id)
+                .execute(&mut tx)
+                .await
+                .unwrap();
+            assert_eq!(deleted.rows_affected(), 0);
+
+            // Each rejected write gets a savepoint, the error aborts what it runs in
+            let mut savepoint = tx.begin().await.unwrap();
+            let result = sqlx::query!(
+                r#"
+                INSERT INTO devices (client_id, nickname, imei, model, serial_number, upload_data, upload_gps, status)
+                VALUES ($1, 'planted', 'planted', 'RLS Test', 'planted', now(), now(), 'active')
+                "#,
+                b.client_id
+            )
+            .execute(&mut savepoint)
+            .await;
+            assert!(is_policy_violation(result), "device planted in client B");
+            savepoint.rollback().await.unwrap();
+
+            let mut savepoint = tx.begin().await.unwrap();
+            let result = sqlx::query!("UPDATE devices SET client_id = $1 WHERE id = $2", b.client_id, a.device_id)
+                .execute(&mut savepoint)
+                .await;
+            assert!(is_policy_violation(result), "device moved into client B");
+            savepoint.rollback().await.unwrap();
+
+            let mut savepoint = tx.begin().await.unwrap();
+            let result = sqlx::query!(
+                "INSERT INTO positions (device_id, recorded_at, latitude, longitude) VALUES ($1, now(), 0, 0)",
+                b.device_id
+            )
+            .execute(&mut savepoint)
+            .await;
+            assert!(is_policy_violation(result), "position written to a device of client B");
+            savepoint.rollback().await.unwrap();
+
+            let mut savepoint = tx.begin().await.unwrap();
+            let result = sqlx::query!(
+                "INSERT INTO trips (device_id, started_at, start_latitude, start_longitude) VALUES ($1, now(), 0, 0)",
+                b.device_id
+            )
+            .execute(&mut savepoint)
+            .await;
+            assert!(is_policy_violation(result), "trip written to a device of client B");
+            savepoint.rollback().await.unwrap();
+
+            tx.rollback().await.unwrap();
+        })
+        .await;
+    }
+
+    #[actix_web::test]
+    async fn registered_imeis_span_every_client() {
+        let Some(pool) = pool().await else { return };
+
+        // Committed, the lookup takes a connection of its own
+        let (a, b) = system(async {
+            let (tx, a, b) = seed_tenants(&pool).await;
+            tx.commit().await.unwrap();
+            (a, b)
+        })
+        .await;
+        let imei = b.client_id.simple().to_string()[..15].to_string();
+
+        let (visible, taken) = CLIENT_SCOPE
+            .scope(RefCell::new(Scope::clients(&[a.client_id])), async {
+                let visible = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "devices!" FROM devices WHERE imei = $1"#, imei)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+                let taken = registered_imeis(&pool, std::slice::from_ref(&imei)).await.unwrap();
+                (visible, taken)
+            })
+            .await;
+
+        system(async {
+            let clients = [a.client_id, b.client_id];
+            sqlx::query!("DELETE FROM clients WHERE id = ANY($1)", &clients[..])
+                .execute(&pool)
+                .await
+                .unwrap();
+            sqlx::query!("DELETE FROM device_status_history WHERE client_id = ANY($1)", &clients[..])
+                .execute(&pool)
+                .await
+                .unwrap();
+        })
+        .await;
+
+        assert_eq!(visible, 0, "client A reads the device of client B");
+        assert_eq!(taken, vec![imei], "the IMEI of client B passes as free to client A");
+    }
+
+    #[actix_web::test]
+    async fn empty_scope_sees_nothing() {
+        let Some(pool) = pool().await else { return };
+
+        // Taken outside any scope, as a request before its token is checked
+        let mut conn = pool.acquire().await.unwrap();
+        let clients = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "clients!" FROM clients"#)
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(clients, 0);
+        drop(conn);
+
+        system(async {
+            let (mut tx, a, b) = seed_tenants(&pool).await;
+            set_scope(&mut tx, Scope::Nothing).await.unwrap();
+
+            assert_eq!(visible(&mut tx, &[a.client_id, b.client_id]).await, [0, 0, 0, 0]);
+
+            let mut savepoint = tx.begin().await.unwrap();
+            let result = sqlx::query!(
+                "INSERT INTO positions (device_id, recorded_at, latitude, longitude) VALUES ($1, now(), 0, 0)",
+                a.device_id
+            )
+            .execute(&mut savepoint)
+            .await;
+            assert!(is_policy_violation(result), "position written without a scope");
+            savepoint.rollback().await.unwrap();
+
+            tx.rollback().await.unwrap();
+        })
+        .await;
+    }
+}
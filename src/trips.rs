@@ -0,0 +1,489 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::model::{PositionModel, TripModel};
+
+/// Slower than this (km/h), or with the ignition off, the device is stopped.
+const MOVING_SPEED: f64 = 5.0;
+/// Stopped this long on a device without ignition readings, or silent after a
+/// stationary fix, the trip ended where the stop began.
+const STOP_SECONDS: i64 = 5 * 60;
+/// With the ignition off this long, the trip ended when it was switched off.
+const IGNITION_OFF_SECONDS: i64 = 2 * 60;
+/// Shorter trips are GPS drift or repositioning and are dropped when they end.
+const MIN_TRIP_METERS: f64 = 200.0;
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Great-circle distance in meters between two (latitude, longitude) points.
+pub fn haversine_m(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (from_lat, to_lat) = (from.0.to_radians(), to.0.to_radians());
+    let half_lat = (to_lat - from_lat) / 2.0;
+    let half_lon = (to.1 - from.1).to_radians() / 2.0;
+    let a = half_lat.sin().powi(2) + from_lat.cos() * to_lat.cos() * half_lon.sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+struct TripState {
+    last_recorded_at: DateTime<Utc>,
+    last_latitude: f64,
+    last_longitude: f64,
+    last_moving: bool,
+    last_ignition: Option<bool>,
+    trip_id: Option<Uuid>,
+    stopped_since: Option<DateTime<Utc>>,
+    stop_latitude: Option<f64>,
+    stop_longitude: Option<f64>,
+    ignition_off_since: Option<DateTime<Utc>>,
+    pending_idle_seconds: i32,
+}
+
+/// Splits the positions of one device into trips, fix by fix.
+struct Segmenter {
+    device_id: Uuid,
+    state: Option<TripState>,
+    trip: Option<TripModel>,
+    ended: Vec<TripModel>,
+}
+
+impl Segmenter {
+    fn advance(&mut self, position: &PositionModel) {
+        let at = position.recorded_at;
+        let here = (position.latitude, position.longitude);
+
+        // Fixes older than the last one arrived late, trips only move forward
+        let last = match &self.state {
+            Some(state) if at <= state.last_recorded_at => return,
+            Some(state) => Some((state.last_recorded_at, (state.last_latitude, state.last_longitude), state.last_moving)),
+            None => None,
+        };
+        let (elapsed, distance) = match last {
+            Some((last_at, last_here, _)) => ((at - last_at).num_seconds(), haversine_m(last_here, here)),
+            None => (0, 0.0),
+        };
+        let speed = position.speed.unwrap_or(if elapsed > 0 {
+            distance / elapsed as f64 * 3.6
+        } else {
+            0.0
+        });
+        let moving = speed >= MOVING_SPEED && position.ignition != Some(false);
+
+        // Silent for as long as a stop after a stationary fix, the trip ended
+        // where it had stopped. A gap while driving is lost coverage and an
+        // engine left running is idling, the trip goes on in both.
+        if let Some(state) = &self.state {
+            let parked = !state.last_moving && state.last_ignition != Some(true);
+            if elapsed >= STOP_SECONDS && parked && self.trip.is_some() {
+                self.end_trip();
+            }
+        }
+
+        let state = self.state.get_or_insert(TripState {
+            last_recorded_at: at,
+            last_latitude: here.0,
+            last_longitude: here.1,
+            last_moving: false,
+            last_ignition: None,
+            trip_id: None,
+            stopped_since: None,
+            stop_latitude: None,
+            stop_longitude: None,
+            ignition_off_since: None,
+            pending_idle_seconds: 0,
+        });
+        let last_moving = last.is_some_and(|(_, _, last_moving)| last_moving);
+        let mut stopped = false;
+
+        match &mut self.trip {
+            Some(trip) => {
+                // The leg into the first stationary fix was still driven
+                if moving || last_moving {
+                    trip.distance_m += distance;
+                    trip.moving_seconds += elapsed as i32;
+                }
+                trip.max_speed = trip.max_speed.max(speed);
+
+                if moving {
+                    trip.idle_seconds += state.pending_idle_seconds;
+                    state.pending_idle_seconds = 0;
+                    state.stopped_since = None;
+                    state.stop_latitude = None;
+                    state.stop_longitude = None;
+                    state.ignition_off_since = None;
+                } else {
+                    // Stopped with the engine running, or for a short while on a
+                    // device without ignition readings, is idle time once the trip
+                    // goes on. A stop that ends the trip drops it.
+                    match state.stopped_since {
+                        Some(_) if position.ignition != Some(false) => {
+                            state.pending_idle_seconds += elapsed as i32;
+                        }
+                        Some(_) => (),
+                        None => {
+                            state.stopped_since = Some(at);
+                            state.stop_latitude = Some(here.0);
+                            state.stop_longitude = Some(here.1);
+                        }
+                    }
+                    if position.ignition == Some(false) {
+                        state.ignition_off_since.get_or_insert(at);
+                    } else {
+                        state.ignition_off_since = None;
+                    }
+
+                    // Idling with the ignition on never ends the trip
+                    stopped = match (position.ignition, state.ignition_off_since, state.stopped_since) {
+                        (Some(false), Some(off), _) => (at - off).num_seconds() >= IGNITION_OFF_SECONDS,
+                        (None, _, Some(since)) => (at - since).num_seconds() >= STOP_SECONDS,
+                        _ => false,
+                    };
+                }
+                if trip.moving_seconds > 0 {
+                    trip.avg_speed = trip.distance_m / trip.moving_seconds as f64 * 3.6;
+                }
+            }
+            None if moving => {
+                // Departed from the fix before when it is recent, the device was parked there
+                let (started_at, start, moving_seconds, distance_m) = match last {
+                    Some((last_at, last_here, _)) if elapsed < STOP_SECONDS => {
+                        (last_at, last_here, elapsed as i32, distance)
+                    }
+                    _ => (at, here, 0, 0.0),
+                };
+                let trip = TripModel {
+                    id: Uuid::new_v4(),
+                    device_id: self.device_id,
+                    started_at,
+                    start_latitude: start.0,
+                    start_longitude: start.1,
+                    ended_at: None,
+                    end_latitude: None,
+                    end_longitude: None,
+                    distance_m,
+                    max_speed: speed,
+                    avg_speed: if moving_seconds > 0 { distance_m / moving_seconds as f64 * 3.6 } else { speed },
+                    moving_seconds,
+                    idle_seconds: 0,
+                    created_at: None,
+                };
+                state.trip_id = Some(trip.id);
+                self.trip = Some(trip);
+            }
+            None => (),
+        }
+
+        state.last_recorded_at = at;
+        state.last_latitude = here.0;
+        state.last_longitude = here.1;
+        state.last_moving = moving;
+        state.last_ignition = position.ignition;
+
+        if stopped {
+            self.end_trip();
+        }
+    }
+
+    /// Ends the open trip where it stopped, or at the last fix when it never did.
+    /// Idling before the ignition went off was part of the trip, a stop without
+    /// ignition readings is parking and stays out of it.
+    fn end_trip(&mut self) {
+        let Some(state) = &mut self.state else { return };
+        if let Some(mut trip) = self.trip.take() {
+            let (ended_at, place) = match (state.stopped_since, state.stop_latitude, state.stop_longitude) {
+                (Some(since), Some(latitude), Some(longitude)) => (since, (latitude, longitude)),
+                _ => (state.last_recorded_at, (state.last_latitude, state.last_longitude)),
+            };
+            if let Some(off) = state.ignition_off_since {
+                trip.idle_seconds += state.pending_idle_seconds;
+                trip.ended_at = Some(off);
+            } else {
+                trip.ended_at = Some(ended_at);
+            }
+            trip.end_latitude = Some(place.0);
+            trip.end_longitude = Some(place.1);
+            self.ended.push(trip);
+        }
+        state.trip_id = None;
+        state.stopped_since = None;
+        state.stop_latitude = None;
+        state.stop_longitude = None;
+        state.ignition_off_since = None;
+        state.pending_idle_seconds = 0;
+    }
+}
+
+async fn save_trip(tx: &mut Transaction<'_, Postgres>, trip: &TripModel) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO trips
+            (id, device_id, started_at, start_latitude, start_longitude, ended_at, end_latitude, end_longitude,
+             distance_m, max_speed, avg_speed, moving_seconds, idle_seconds)
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+        ON CONFLICT (id) DO UPDATE SET
+            ended_at = EXCLUDED.ended_at,
+            end_latitude = EXCLUDED.end_latitude,
+            end_longitude = EXCLUDED.end_longitude,
+            distance_m = EXCLUDED.distance_m,
+            max_speed = EXCLUDED.max_speed,
+            avg_speed = EXCLUDED.avg_speed,
+            moving_seconds = EXCLUDED.moving_seconds,
+            idle_seconds = EXCLUDED.idle_seconds
+        "#,
+        trip.id,
+        trip.device_id,
+        trip.started_at,
+        trip.start_latitude,
+        trip.start_longitude,
+        trip.ended_at,
+        trip.end_latitude,
+        trip.end_longitude,
+        trip.distance_m,
+        trip.max_speed,
+        trip.avg_speed,
+        trip.moving_seconds,
+        trip.idle_seconds
+    )
+    .execute(&mut *tx)
+    .await
+    .map(|_| ())
+}
+
+/// Advances the trips of a device with a batch of its stored positions.
+///
+/// The caller holds the lock on the device row so batches of the same device
+/// are segmented one after the other.
+pub async fn track_positions(
+    tx: &mut Transaction<'_, Postgres>,
+    device_id: Uuid,
+    positions: &[PositionModel],
+) -> Result<(), sqlx::Error> {
+    let state = sqlx::query_as!(
+        TripState,
+        r#"
+        SELECT last_recorded_at, last_latitude, last_longitude, last_moving, last_ignition, trip_id,
+            stopped_since, stop_latitude, stop_longitude, ignition_off_since, pending_idle_seconds
+        FROM trip_states
+        WHERE device_id = $1
+        "#,
+        device_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let trip = match state.as_ref().and_then(|state| state.trip_id) {
+        Some(trip_id) => sqlx::query_as!(TripModel, "SELECT * FROM trips WHERE id = $1", trip_id)
+            .fetch_optional(&mut *tx)
+            .await?,
+        None => None,
+    };
+
+    let mut segmenter = Segmenter { device_id, state, trip, ended: Vec::new() };
+    let mut positions: Vec<&PositionModel> = positions.iter().collect();
+    positions.sort_by_key(|position| position.recorded_at);
+    for position in positions {
+        segmenter.advance(position);
+    }
+
+    for trip in &segmenter.ended {
+        if trip.distance_m < MIN_TRIP_METERS {
+            sqlx::query!("DELETE FROM trips WHERE id = $1", trip.id)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            save_trip(tx, trip).await?;
+        }
+    }
+    if let Some(trip) = &segmenter.trip {
+        save_trip(tx, trip).await?;
+    }
+
+    let state = match segmenter.state {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO trip_states
+            (device_id, last_recorded_at, last_latitude, last_longitude, last_moving, last_ignition, trip_id,
+             stopped_since, stop_latitude, stop_longitude, ignition_off_since, pending_idle_seconds)
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        ON CONFLICT (device_id) DO UPDATE SET
+            last_recorded_at = EXCLUDED.last_recorded_at,
+            last_latitude = EXCLUDED.last_latitude,
+            last_longitude = EXCLUDED.last_longitude,
+            last_moving = EXCLUDED.last_moving,
+            last_ignition = EXCLUDED.last_ignition,
+            trip_id = EXCLUDED.trip_id,
+            stopped_since = EXCLUDED.stopped_since,
+            stop_latitude = EXCLUDED.stop_latitude,
+            stop_longitude = EXCLUDED.stop_longitude,
+            ignition_off_since = EXCLUDED.ignition_off_since,
+            pending_idle_seconds = EXCLUDED.pending_idle_seconds
+        "#,
+        device_id,
+        state.last_recorded_at,
+        state.last_latitude,
+        state.last_longitude,
+        state.last_moving,
+        state.last_ignition,
+        state.trip_id,
+        state.stopped_since,
+        state.stop_latitude,
+        state.stop_longitude,
+        state.ignition_off_since,
+        state.pending_idle_seconds
+    )
+    .execute(&mut *tx)
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Meters per degree of latitude on the sphere the distances use.
+    const METERS_PER_DEGREE: f64 = 111_194.93;
+
+    fn time(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_760_000_000 + seconds, 0).unwrap()
+    }
+
+    /// A fix some seconds into the test, some meters north of where it began.
+    fn fix(seconds: i64, meters: f64, speed: f64, ignition: Option<bool>) -> PositionModel {
+        PositionModel {
+            id: seconds,
+            device_id: Uuid::nil(),
+            recorded_at: time(seconds),
+            latitude: -23.5 + meters / METERS_PER_DEGREE,
+            longitude: -46.6,
+            altitude: None,
+            speed: Some(speed),
+            course: None,
+            satellites: None,
+            ignition,
+            io: None,
+            created_at: None,
+        }
+    }
+
+    fn segmenter() -> Segmenter {
+        Segmenter { device_id: Uuid::nil(), state: None, trip: None, ended: Vec::new() }
+    }
+
+    /// Five minutes at 60 km/h with a fix every 30 seconds, ending 5 km north.
+    fn drive(segmenter: &mut Segmenter, ignition: Option<bool>) {
+        for step in 0..=10 {
+            segmenter.advance(&fix(step * 30, step as f64 * 500.0, 60.0, ignition));
+        }
+    }
+
+    #[test]
+    fn haversine_matches_known_distances() {
+        assert_eq!(haversine_m((-23.5, -46.6), (-23.5, -46.6)), 0.0);
+        assert!((haversine_m((0.0, 0.0), (1.0, 0.0)) - METERS_PER_DEGREE).abs() < 1.0);
+        // Across the antimeridian the short way round
+        assert!((haversine_m((0.0, 179.5), (0.0, -179.5)) - METERS_PER_DEGREE).abs() < 1.0);
+        // São Paulo to Rio de Janeiro
+        let distance = haversine_m((-23.5505, -46.6333), (-22.9068, -43.1729));
+        assert!((distance - 360_749.0).abs() < 1.0, "{distance}");
+    }
+
+    #[test]
+    fn stop_without_ignition_ends_trip_where_it_stopped() {
+        let mut segmenter = segmenter();
+        drive(&mut segmenter, None);
+        for seconds in (330..=570).step_by(60) {
+            segmenter.advance(&fix(seconds, 5000.0, 0.0, None));
+        }
+        assert!(segmenter.ended.is_empty(), "stopped for less than STOP_SECONDS");
+
+        segmenter.advance(&fix(330 + STOP_SECONDS, 5000.0, 0.0, None));
+        assert!(segmenter.trip.is_none());
+        let trip = &segmenter.ended[0];
+        assert_eq!(trip.started_at, time(0));
+        assert_eq!(trip.ended_at, Some(time(330)));
+        assert!((trip.distance_m - 5000.0).abs() < 1.0);
+        assert_eq!(trip.moving_seconds, 330);
+        assert_eq!(trip.idle_seconds, 0);
+    }
+
+    #[test]
+    fn idling_with_ignition_on_is_idle_time_of_the_trip() {
+        let mut segmenter = segmenter();
+        drive(&mut segmenter, Some(true));
+        // Ten minutes stopped with the engine running
+        for seconds in (360..=960).step_by(60) {
+            segmenter.advance(&fix(seconds, 5000.0, 0.0, Some(true)));
+        }
+        segmenter.advance(&fix(990, 5500.0, 60.0, Some(true)));
+
+        assert!(segmenter.ended.is_empty());
+        let trip = segmenter.trip.as_ref().unwrap();
+        assert_eq!(trip.idle_seconds, 600);
+        assert_eq!(trip.moving_seconds, 390);
+    }
+
+    #[test]
+    fn short_stop_without_ignition_is_idle_time_of_the_trip() {
+        let mut segmenter = segmenter();
+        drive(&mut segmenter, None);
+        // Four minutes at a traffic light on a tracker without ignition IO
+        for seconds in (330..=570).step_by(60) {
+            segmenter.advance(&fix(seconds, 5000.0, 0.0, None));
+        }
+        segmenter.advance(&fix(600, 5500.0, 60.0, None));
+
+        assert!(segmenter.ended.is_empty());
+        let trip = segmenter.trip.as_ref().unwrap();
+        assert_eq!(trip.idle_seconds, 240);
+        assert_eq!(trip.moving_seconds, 360);
+    }
+
+    #[test]
+    fn ignition_off_ends_trip_when_switched_off() {
+        let mut segmenter = segmenter();
+        drive(&mut segmenter, Some(true));
+        segmenter.advance(&fix(360, 5000.0, 0.0, Some(true)));
+        segmenter.advance(&fix(420, 5000.0, 0.0, Some(true)));
+        segmenter.advance(&fix(480, 5000.0, 0.0, Some(false)));
+        segmenter.advance(&fix(480 + IGNITION_OFF_SECONDS - 1, 5000.0, 0.0, Some(false)));
+        assert!(segmenter.ended.is_empty(), "off for less than IGNITION_OFF_SECONDS");
+
+        segmenter.advance(&fix(480 + IGNITION_OFF_SECONDS, 5000.0, 0.0, Some(false)));
+        assert!(segmenter.trip.is_none());
+        let trip = &segmenter.ended[0];
+        assert_eq!(trip.ended_at, Some(time(480)));
+        assert_eq!(trip.idle_seconds, 60);
+    }
+
+    #[test]
+    fn silence_while_moving_keeps_trip() {
+        let mut segmenter = segmenter();
+        drive(&mut segmenter, Some(true));
+        // Ten minutes without coverage, still driving on the other side
+        segmenter.advance(&fix(900, 15000.0, 60.0, Some(true)));
+
+        assert!(segmenter.ended.is_empty());
+        let trip = segmenter.trip.as_ref().unwrap();
+        assert!((trip.distance_m - 15000.0).abs() < 1.0);
+        assert_eq!(trip.moving_seconds, 900);
+    }
+
+    #[test]
+    fn silence_after_stationary_fix_ends_trip() {
+        let mut segmenter = segmenter();
+        drive(&mut segmenter, None);
+        segmenter.advance(&fix(330, 5000.0, 0.0, None));
+        segmenter.advance(&fix(330 + STOP_SECONDS, 5500.0, 60.0, None));
+
+        let trip = &segmenter.ended[0];
+        assert_eq!(trip.ended_at, Some(time(330)));
+        // Moving again after the silence starts a new trip at the fix
+        let next = segmenter.trip.as_ref().unwrap();
+        assert_eq!(next.started_at, time(330 + STOP_SECONDS));
+        assert_eq!(next.distance_m, 0.0);
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use chrono::{Datelike, FixedOffset, Months, NaiveDate, Utc};
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::model::DeviceUsageModel;
+
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// First day of a month given as YYYY-MM.
+pub fn parse_month(month: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").ok()
+}
+
+/// First day of the running month, billing days follow BRT like the rest of the data.
+pub fn current_month() -> NaiveDate {
+    let brt = FixedOffset::west_opt(3 * 60 * 60).unwrap();
+    let today = Utc::now().with_timezone(&brt).date_naive();
+    today.with_day(1).unwrap_or(today)
+}
+
+/// Active device-days of the client so far in a month that is not closed yet.
+pub async fn live_usage(
+    db: &Pool<Postgres>,
+    client_id: Uuid,
+    month: NaiveDate,
+) -> Result<Vec<DeviceUsageModel>, sqlx::Error> {
+    sqlx::query_as!(
+        DeviceUsageModel,
+        r#"
+        SELECT u.device_id AS "device_id!", d.imei AS "imei?", u.active_days AS "active_days!"
+        FROM device_usage($1) u
+        LEFT JOIN devices d ON d.id = u.device_id
+        WHERE u.client_id = $2
+        ORDER BY d.imei, u.device_id
+        "#,
+        month,
+        client_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// First month with usage recorded, earlier months cannot be billed.
+pub async fn first_month(db: &Pool<Postgres>) -> Result<NaiveDate, sqlx::Error> {
+    sqlx::query_scalar!("SELECT first_month FROM usage_history")
+        .fetch_one(db)
+        .await
+}
+
+/// Active device-days of the client in a closed month, none until the job has
+/// snapshotted the month.
+pub async fn closed_usage(
+    db: &Pool<Postgres>,
+    client_id: Uuid,
+    month: NaiveDate,
+) -> Result<Option<Vec<DeviceUsageModel>>, sqlx::Error> {
+    let snapshotted = sqlx::query_scalar!("SELECT month FROM usage_months WHERE month = $1", month)
+        .fetch_optional(db)
+        .await?;
+    if snapshotted.is_none() {
+        return Ok(None);
+    }
+
+    sqlx::query_as!(
+        DeviceUsageModel,
+        r#"
+        SELECT device_id, imei, active_days FROM usage_snapshots
+        WHERE client_id = $1 AND month = $2
+        ORDER BY imei, device_id
+        "#,
+        client_id,
+        month
+    )
+    .fetch_all(db)
+    .await
+    .map(Some)
+}
+
+/// Stores the usage of every client in a closed month, once. Only the job
+/// writes them, the connection must reach every client.
+pub async fn snapshot_month(db: &Pool<Postgres>, month: NaiveDate) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    // A concurrent run waits on the row and then finds the month taken
+    let claimed = sqlx::query_scalar!(
+        "INSERT INTO usage_months (month) VALUES ($1) ON CONFLICT DO NOTHING RETURNING month",
+        month
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    if claimed.is_none() {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO usage_snapshots (client_id, month, device_id, imei, active_days)
+        SELECT u.client_id, $1, u.device_id, d.imei, u.active_days
+        FROM device_usage($1) u
+        LEFT JOIN devices d ON d.id = u.device_id
+        "#,
+        month
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Snapshots each closed month as soon as it closes, and the ones missed while
+/// the server was down.
+pub async fn run_snapshots(db: Pool<Postgres>) {
+    let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let last_closed = match current_month().checked_sub_months(Months::new(1)) {
+            Some(month) => month,
+            None => continue,
+        };
+        let months = match sqlx::query_scalar!(
+            r#"
+            SELECT closed::DATE AS "month!"
+            FROM usage_history, generate_series(first_month, $1::DATE, INTERVAL '1 month') closed
+            WHERE NOT EXISTS (SELECT 1 FROM usage_months u WHERE u.month = closed::DATE)
+            ORDER BY 1
+            "#,
+            last_closed
+        )
+        .fetch_all(&db)
+        .await
+        {
+            Ok(months) => months,
+            Err(error) => {
+                log::error!("Usage months to snapshot not loaded: {:?}", error);
+                continue;
+            }
+        };
+
+        for month in months {
+            match snapshot_month(&db, month).await {
+                Ok(true) => log::info!("Usage of {} snapshotted", month.format("%Y-%m")),
+                Ok(false) => (),
+                Err(error) => log::error!("Usage snapshot of {} failed: {:?}", month.format("%Y-%m"), error),
+            }
+        }
+    }
+}
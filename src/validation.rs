@@ -0,0 +1,78 @@
+/// Luhn checksum over a string of ASCII digits, false for anything else.
+pub fn luhn_valid(digits: &str) -> bool {
+    let mut sum = 0;
+    for (position, c) in digits.chars().rev().enumerate() {
+        let mut digit = match c.to_digit(10) {
+            Some(digit) => digit,
+            None => return false,
+        };
+        if position % 2 == 1 {
+            digit *= 2;
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+        sum += digit;
+    }
+
+    !digits.is_empty() && sum % 10 == 0
+}
+
+/// IMEIs are 15 digits ending with a Luhn check digit.
+pub fn valid_imei(imei: &str) -> bool {
+    imei.len() == 15 && luhn_valid(imei)
+}
+
+/// ICCIDs are 19 or 20 digits, start with the telecom prefix 89 and end with a Luhn check digit.
+pub fn valid_iccid(iccid: &str) -> bool {
+    (19..=20).contains(&iccid.len()) && iccid.starts_with("89") && luhn_valid(iccid)
+}
+
+/// Longest tag name, the size of `tags.name`.
+pub const MAX_TAG_LENGTH: usize = 50;
+
+/// Trimmed tag name, `None` when it is empty or too long.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    (!tag.is_empty() && tag.chars().count() <= MAX_TAG_LENGTH).then(|| tag.to_string())
+}
+
+/// Longest firmware version, the size of `firmwares.version`.
+pub const MAX_FIRMWARE_VERSION_LENGTH: usize = 50;
+
+/// Firmware versions are 1 to 50 ASCII letters, digits, `.`, `_`, `-` or `+`.
+pub fn valid_firmware_version(version: &str) -> bool {
+    (1..=MAX_FIRMWARE_VERSION_LENGTH).contains(&version.len())
+        && version.bytes().all(|c| c.is_ascii_alphanumeric() || matches!(c, b'.' | b'_' | b'-' | b'+'))
+}
+
+/// Longest metric name, the size of `telemetry.metric`.
+pub const MAX_METRIC_LENGTH: usize = 100;
+
+/// Metric names are 1 to 100 ASCII letters, digits, `_`, `.` or `-`.
+pub fn valid_metric(metric: &str) -> bool {
+    (1..=MAX_METRIC_LENGTH).contains(&metric.len())
+        && metric.bytes().all(|c| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'.' | b'-'))
+}
+
+/// Reasons the device metadata is rejected, empty when it is an object
+/// following the client's schema.
+pub fn metadata_errors(schema: Option<&serde_json::Value>, metadata: &serde_json::Value) -> Vec<String> {
+    if !metadata.is_object() {
+        return vec!["metadata must be a JSON object".to_string()];
+    }
+
+    let schema = match schema {
+        Some(schema) => schema,
+        None => return Vec::new(),
+    };
+    let validator = match jsonschema::validator_for(schema) {
+        Ok(validator) => validator,
+        Err(error) => return vec![format!("The client's metadata schema is invalid: {}", error)],
+    };
+
+    validator
+        .iter_errors(metadata)
+        .map(|error| format!("metadata{}: {}", error.instance_path, error))
+        .collect()
+}